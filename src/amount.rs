@@ -0,0 +1,280 @@
+use std::str::FromStr;
+
+use rust_decimal::{Decimal, RoundingStrategy, prelude::ToPrimitive};
+use thiserror::Error;
+
+use crate::error::Error;
+
+// NOTE(juf): `Decimal` is 16 bytes and carries 28 digits, far more than bookkeeping needs. Amounts
+// the processor keeps for every transaction are packed into an i64 of 1/10_000 units instead,
+// which covers +-922 trillion at the four decimal places the input has. That halves the amount
+// and takes the cached transaction from 28 down to 24 bytes. Accounts keep their `Decimal`, there
+// are at most 65536 of them and they are where the arithmetic happens.
+
+/// Decimal places every [`Amount`] has.
+pub(crate) const SCALE: u32 = 4;
+const UNIT: i64 = 10_i64.pow(SCALE);
+
+/// Amount at a fixed scale of [`SCALE`] decimal places.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) struct Amount(i64);
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub(crate) enum AmountError {
+    #[error("amount {amount} has more than {scale} decimal places")]
+    TooPrecise { amount: Decimal, scale: u32 },
+    #[error("amount {0} is out of range")]
+    OutOfRange(Decimal),
+}
+
+impl Amount {
+    /// Amount of `units` 1/10_000.
+    pub(crate) const fn from_units(units: i64) -> Self {
+        Amount(units)
+    }
+
+    pub(crate) const fn units(self) -> i64 {
+        self.0
+    }
+}
+
+impl TryFrom<Decimal> for Amount {
+    type Error = AmountError;
+
+    fn try_from(value: Decimal) -> Result<Self, Self::Error> {
+        let units = value
+            .checked_mul(Decimal::from(UNIT))
+            .ok_or(AmountError::OutOfRange(value))?;
+        if !units.fract().is_zero() {
+            return Err(AmountError::TooPrecise {
+                amount: value,
+                scale: SCALE,
+            });
+        }
+        units
+            .to_i64()
+            .map(Amount)
+            .ok_or(AmountError::OutOfRange(value))
+    }
+}
+
+impl From<Amount> for Decimal {
+    /// Without trailing zeros, `10.5` comes back as `10.5`, not `10.5000`.
+    fn from(amount: Amount) -> Self {
+        Decimal::new(amount.0, SCALE).normalize()
+    }
+}
+
+/// How amounts with more decimal places than the scale are rounded.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Rounding {
+    /// Half to even, `0.125` to `0.12`, `0.135` to `0.14`
+    #[default]
+    Bankers,
+    /// Half away from zero, `0.125` to `0.13`, `-0.125` to `-0.13`
+    HalfUp,
+    /// Towards zero, `0.129` to `0.12`
+    Truncate,
+}
+
+impl FromStr for Rounding {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bankers" | "half-even" => Ok(Rounding::Bankers),
+            "half-up" => Ok(Rounding::HalfUp),
+            "truncate" => Ok(Rounding::Truncate),
+            _ => Err(Error::InvalidArgument(format!("unknown rounding {s}"))),
+        }
+    }
+}
+
+/// What happens to input amounts with more decimal places than the scale.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum OverPrecise {
+    /// The transaction is rejected
+    #[default]
+    Reject,
+    /// The amount is rounded to the scale
+    Round,
+}
+
+impl FromStr for OverPrecise {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(OverPrecise::Reject),
+            "round" => Ok(OverPrecise::Round),
+            _ => Err(Error::InvalidArgument(format!("unknown policy {s}"))),
+        }
+    }
+}
+
+// NOTE(juf): Decimal keeps the scale it was parsed or computed with, `1.5 - 1.5` is `0.0`, which
+// made the output a mix of `0`, `0.0` and `1.50`. Everything written out goes through
+// `Precision::round` now and has exactly `scale` decimal places.
+
+/// Decimal places amounts are taken in and written out with. At most [`SCALE`], the transaction
+/// store keeps no more.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Precision {
+    pub(crate) scale: u32,
+    pub(crate) rounding: Rounding,
+    pub(crate) over_precise: OverPrecise,
+}
+
+impl Default for Precision {
+    fn default() -> Self {
+        Self {
+            scale: SCALE,
+            rounding: Rounding::default(),
+            over_precise: OverPrecise::default(),
+        }
+    }
+}
+
+impl Precision {
+    /// Input `amount` as the processor takes it, rounded or refused if it is more precise than
+    /// the scale.
+    pub(crate) fn admit(&self, amount: Decimal) -> Result<Decimal, AmountError> {
+        let rounded = self.round(amount);
+        match self.over_precise {
+            OverPrecise::Round => Ok(rounded),
+            OverPrecise::Reject if rounded == amount => Ok(amount),
+            OverPrecise::Reject => Err(AmountError::TooPrecise {
+                amount,
+                scale: self.scale,
+            }),
+        }
+    }
+
+    /// `amount` rounded to the scale, with exactly `scale` decimal places and without a sign if
+    /// it is zero.
+    pub(crate) fn round(&self, amount: Decimal) -> Decimal {
+        let strategy = match self.rounding {
+            Rounding::Bankers => RoundingStrategy::MidpointNearestEven,
+            Rounding::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+            Rounding::Truncate => RoundingStrategy::ToZero,
+        };
+        let mut rounded = amount.round_dp_with_strategy(self.scale, strategy);
+        // Amounts too large for the scale keep fewer places
+        rounded.rescale(self.scale);
+        if rounded.is_zero() {
+            rounded.set_sign_positive(true);
+        }
+        rounded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::{Decimal, dec};
+
+    use crate::amount::{Amount, AmountError, OverPrecise, Precision, Rounding};
+
+    #[test]
+    fn converts_from_and_to_decimal() {
+        for (decimal, units) in [
+            (dec!(0), 0),
+            (dec!(10.5), 105_000),
+            (dec!(-0.0001), -1),
+            (dec!(1.23450000), 12_345),
+            (dec!(922337203685477.5807), i64::MAX),
+            (dec!(-922337203685477.5808), i64::MIN),
+        ] {
+            let amount = Amount::try_from(decimal).expect("fits");
+            assert_eq!(Amount::from_units(units), amount, "{decimal}");
+            assert_eq!(decimal, Decimal::from(amount));
+        }
+        assert_eq!(
+            "10.5",
+            Decimal::from(Amount::from_units(105_000)).to_string()
+        );
+        assert_eq!("0", Decimal::from(Amount::default()).to_string());
+    }
+
+    #[test]
+    fn refuses_what_it_cannot_represent() {
+        assert_eq!(
+            Err(AmountError::TooPrecise {
+                amount: dec!(0.00001),
+                scale: 4
+            }),
+            Amount::try_from(dec!(0.00001))
+        );
+        assert_eq!(
+            Err(AmountError::OutOfRange(dec!(922337203685477.5808))),
+            Amount::try_from(dec!(922337203685477.5808))
+        );
+        assert_eq!(
+            Err(AmountError::OutOfRange(Decimal::MAX)),
+            Amount::try_from(Decimal::MAX)
+        );
+        assert_eq!(
+            Err(AmountError::OutOfRange(Decimal::MIN)),
+            Amount::try_from(Decimal::MIN)
+        );
+    }
+
+    fn cents(rounding: Rounding) -> Precision {
+        Precision {
+            scale: 2,
+            rounding,
+            over_precise: OverPrecise::Round,
+        }
+    }
+
+    #[test]
+    fn rounds_to_the_scale() {
+        for (amount, bankers, half_up, truncate) in [
+            (dec!(0.125), "0.12", "0.13", "0.12"),
+            (dec!(0.135), "0.14", "0.14", "0.13"),
+            (dec!(-0.125), "-0.12", "-0.13", "-0.12"),
+            (dec!(0.129), "0.13", "0.13", "0.12"),
+            (dec!(1.5), "1.50", "1.50", "1.50"),
+            (dec!(7), "7.00", "7.00", "7.00"),
+            (dec!(-0.001), "0.00", "0.00", "0.00"),
+            (dec!(0.0), "0.00", "0.00", "0.00"),
+        ] {
+            for (rounding, expected) in [
+                (Rounding::Bankers, bankers),
+                (Rounding::HalfUp, half_up),
+                (Rounding::Truncate, truncate),
+            ] {
+                assert_eq!(
+                    expected,
+                    cents(rounding).round(amount).to_string(),
+                    "{amount} {rounding:?}"
+                );
+            }
+        }
+        assert_eq!("5.0000", Precision::default().round(dec!(5)).to_string());
+        let units = Precision {
+            scale: 0,
+            ..Precision::default()
+        };
+        assert_eq!("2", units.round(dec!(2.5)).to_string());
+    }
+
+    #[test]
+    fn admits_or_rejects_over_precise_amounts() {
+        let round = cents(Rounding::HalfUp);
+        assert_eq!(Ok(dec!(1.01)), round.admit(dec!(1.005)));
+        assert_eq!(Ok(dec!(1.5)), round.admit(dec!(1.5)));
+        let reject = Precision {
+            over_precise: OverPrecise::Reject,
+            ..round
+        };
+        assert_eq!(Ok(dec!(1.5)), reject.admit(dec!(1.5)));
+        assert_eq!(Ok(dec!(1.10)), reject.admit(dec!(1.1000)));
+        assert_eq!(
+            Err(AmountError::TooPrecise {
+                amount: dec!(1.005),
+                scale: 2
+            }),
+            reject.admit(dec!(1.005))
+        );
+    }
+}
@@ -0,0 +1,113 @@
+// NOTE(juf): Benchmarks, as ignored tests since the crate is a binary only and `benches/` can not
+// reach into it. Numbers only mean something in release builds:
+//
+//   cargo test --release bench -- --ignored --nocapture --test-threads 1
+
+use std::time::{Duration, Instant};
+
+use rust_decimal::Decimal;
+
+use crate::{
+    ingest::{default_csv_ingest, feed_processor},
+    objects::transactions::{Metadata, Transaction},
+    processor::Processor,
+    shard::{feed_sharded, tests::mixed_input},
+    store::compact::Compaction,
+};
+
+/// Runs `f` `runs` times and returns the fastest run along with its last result.
+pub(crate) fn fastest<R>(runs: usize, mut f: impl FnMut() -> R) -> (Duration, R) {
+    let mut best = Duration::MAX;
+    let mut result = None;
+    for _ in 0..runs {
+        let start = Instant::now();
+        let r = std::hint::black_box(f());
+        best = best.min(start.elapsed());
+        result = Some(r);
+    }
+    (best, result.expect("runs at least once"))
+}
+
+/// Deposits, each one disputed and resolved `window` transactions later, so all but the last
+/// `window` end up finalized.
+fn finalizing(txns: u32, window: u32) -> impl Iterator<Item = Transaction> {
+    (1..=txns).flat_map(move |tx_id| {
+        let deposit = Transaction::Deposit(
+            Metadata::new((tx_id % 1000) as u16, tx_id),
+            Decimal::new(i64::from(tx_id % 10_000), 2),
+        );
+        let old = tx_id.checked_sub(window).filter(|old| *old > 0);
+        let settle = old.into_iter().flat_map(|old| {
+            let metadata = Metadata::new((old % 1000) as u16, old);
+            [
+                Transaction::Dispute(metadata.clone()),
+                Transaction::Resolve(metadata),
+            ]
+        });
+        std::iter::once(deposit).chain(settle)
+    })
+}
+
+#[test]
+#[ignore = "benchmark"]
+fn bench_compaction_against_single_map() {
+    const TXNS: u32 = 2_000_000;
+    const WINDOW: u32 = 10_000;
+    for (label, compaction) in [
+        ("single map", None),
+        ("compact every 1", Some(1)),
+        ("compact every 4096", Some(4096)),
+    ] {
+        let (took, stats) = fastest(3, || {
+            let mut p = Processor::new();
+            if let Some(every) = compaction {
+                p = p.with_compaction(Compaction::every(every));
+            }
+            for txn in finalizing(TXNS, WINDOW) {
+                let _ = p.process_one(txn);
+            }
+            p.memory_stats()
+        });
+        println!("{label:>20}: {took:>10.2?}, {stats}");
+    }
+}
+
+#[test]
+#[ignore = "benchmark"]
+fn bench_sharded_against_in_order() {
+    const ROWS: u32 = 4_000_000;
+    let dir = tempfile::tempdir().expect("can create tempdir");
+    let path = dir.path().join("in.csv");
+    let rows_per_sec = |took: Duration| f64::from(ROWS) / took.as_secs_f64() / 1e6;
+    for across_clients in [false, true] {
+        std::fs::write(&path, mixed_input(ROWS, 10_000, 7, across_clients))
+            .expect("can write input");
+        println!("{ROWS} rows, across clients: {across_clients}");
+        let (took, rejected) = fastest(3, || {
+            let mut ingest = default_csv_ingest(&path).expect("can open input");
+            let mut rejected = 0;
+            feed_processor(&mut ingest, &mut Processor::new(), None, |_| rejected += 1)
+                .expect("can process");
+            rejected
+        });
+        println!(
+            "{:>12}: {took:>10.2?}, {:.2}M rows/s, {rejected} rejected",
+            "in order",
+            rows_per_sec(took)
+        );
+        for shards in [1, 2, 4, 8] {
+            let (took, rejected) = fastest(3, || {
+                let mut ingest = default_csv_ingest(&path).expect("can open input");
+                let mut rejected = 0;
+                feed_sharded(&mut ingest, shards, Processor::new, None, |_| rejected += 1)
+                    .expect("can process");
+                rejected
+            });
+            println!(
+                "{:>12}: {took:>10.2?}, {:.2}M rows/s, {rejected} rejected",
+                format!("{shards} shards"),
+                rows_per_sec(took)
+            );
+        }
+    }
+}
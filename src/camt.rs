@@ -0,0 +1,355 @@
+use std::{path::Path, str::FromStr};
+
+use rust_decimal::Decimal;
+
+use crate::{
+    date::Date,
+    error::Error,
+    events::fits,
+    objects::statements::{Balance, Balances, BankStatement, StatementLine},
+    xml::{self, Element},
+};
+
+// NOTE(juf): ISO 20022 cash management messages. camt.053 is the end of day statement, camt.054 a
+// debit/credit notification, camt.052 the intraday report. All three share the entry (`<Ntry>`)
+// layout, only the container element differs. Only booked entries are imported, pending ones
+// are not part of the reported balances yet.
+
+/// Reads all statements of a camt.052/053/054 file, one per `<Stmt>`, `<Ntfctn>` or `<Rpt>`.
+pub(crate) fn read(path: &Path) -> Result<Vec<BankStatement>, Error> {
+    parse(&std::fs::read_to_string(path)?)
+}
+
+pub(crate) fn parse(input: &str) -> Result<Vec<BankStatement>, Error> {
+    let document = xml::parse(input)?;
+    let message = document
+        .children
+        .iter()
+        .find(|child| {
+            matches!(
+                child.name.as_str(),
+                "BkToCstmrStmt" | "BkToCstmrDbtCdtNtfctn" | "BkToCstmrAcctRpt"
+            )
+        })
+        .ok_or_else(|| Error::InvalidRow("camt: no statement, notification or report".into()))?;
+    message
+        .children
+        .iter()
+        .filter(|child| matches!(child.name.as_str(), "Stmt" | "Ntfctn" | "Rpt"))
+        .enumerate()
+        .map(|(idx, statement)| to_statement(idx, statement))
+        .collect()
+}
+
+fn to_statement(idx: usize, statement: &Element) -> Result<BankStatement, Error> {
+    let mut balances = Balances {
+        statement: statement
+            .text_at(&["Id"])
+            .map_or_else(|| format!("camt-{}", idx + 1), String::from),
+        opening: None,
+        closing: None,
+    };
+    for balance in statement.children_named("Bal") {
+        let code = balance.text_at(&["Tp", "CdOrPrtry", "Cd"]);
+        let slot = match code {
+            // Banks use either the opening booked or the previous closing booked balance
+            Some("OPBD" | "PRCD") => &mut balances.opening,
+            Some("CLBD") => &mut balances.closing,
+            _ => continue,
+        };
+        if slot.is_none() {
+            *slot = Some(Balance {
+                date: date_of(balance, "Dt")?,
+                amount: signed_amount(balance)?,
+            });
+        }
+    }
+    let mut lines = Vec::new();
+    for entry in statement.children_named("Ntry") {
+        let status = entry
+            .text_at(&["Sts", "Cd"])
+            .or_else(|| entry.text_at(&["Sts"]));
+        if status.is_some_and(|status| status != "BOOK") {
+            continue;
+        }
+        lines.push((entry.line, to_statement_line(entry)?));
+    }
+    Ok(BankStatement { balances, lines })
+}
+
+fn to_statement_line(entry: &Element) -> Result<StatementLine, Error> {
+    let amount = signed_amount(entry)?;
+    let date = date_of(entry, "BookgDt").or_else(|_| date_of(entry, "ValDt"))?;
+    let value_date = entry
+        .child("ValDt")
+        .map(|_| date_of(entry, "ValDt"))
+        .transpose()?;
+    // NOTE(juf): Batch bookings carry one `<TxDtls>` per payment, we keep one line per entry as
+    // that is what the balances add up to and take the details of the first payment.
+    let details = entry.find(&["NtryDtls", "TxDtls"]);
+    let (party, party_account) = if amount.is_sign_negative() {
+        ("Cdtr", "CdtrAcct")
+    } else {
+        ("Dbtr", "DbtrAcct")
+    };
+    let counterparty = details.and_then(|details| {
+        details
+            .text_at(&["RltdPties", party, "Nm"])
+            // camt.053.001.08 onwards nests the name into a party element
+            .or_else(|| details.text_at(&["RltdPties", party, "Pty", "Nm"]))
+    });
+    let counterparty_account =
+        details.and_then(|details| details.text_at(&["RltdPties", party_account, "Id", "IBAN"]));
+    let remittance = entry
+        .find(&["NtryDtls"])
+        .map(|entry_details| {
+            entry_details
+                .children_named("TxDtls")
+                .flat_map(|details| details.find(&["RmtInf"]))
+                .flat_map(|info| info.children_named("Ustrd"))
+                .map(|line| line.text.trim())
+                .filter(|line| !line.is_empty())
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .filter(|remittance| !remittance.is_empty())
+        .or_else(|| {
+            details
+                .and_then(|details| details.text_at(&["RmtInf", "Strd", "CdtrRefInf", "Ref"]))
+                .map(String::from)
+        })
+        .or_else(|| entry.text_at(&["AddtlNtryInf"]).map(String::from));
+    let reference = details
+        .and_then(|details| details.text_at(&["Refs", "EndToEndId"]))
+        .filter(|reference| *reference != "NOTPROVIDED");
+    let external_id = entry
+        .text_at(&["AcctSvcrRef"])
+        .or_else(|| details.and_then(|details| details.text_at(&["Refs", "AcctSvcrRef"])));
+    if let Some(external_id) = external_id {
+        fits("<AcctSvcrRef>", external_id)
+            .map_err(|reason| Error::InvalidRow(format!("camt line {}: {reason}", entry.line)))?;
+    }
+    Ok(StatementLine {
+        date,
+        amount,
+        payee: counterparty.map(String::from),
+        memo: remittance,
+        external_id: external_id.map(String::from),
+        value_date,
+        counterparty_account: counterparty_account.map(String::from),
+        reference: reference.map(String::from),
+        cleared: None,
+        splits: Vec::new(),
+    })
+}
+
+/// `<Amt>` signed by `<CdtDbtInd>`, debits are negative.
+fn signed_amount(element: &Element) -> Result<Decimal, Error> {
+    let invalid =
+        |reason: String| Error::InvalidRow(format!("camt line {}: {reason}", element.line));
+    let raw = element
+        .text_at(&["Amt"])
+        .ok_or_else(|| invalid("missing <Amt>".into()))?;
+    let amount = Decimal::from_str(raw).map_err(|_| invalid(format!("invalid amount {raw:?}")))?;
+    match element.text_at(&["CdtDbtInd"]) {
+        Some("CRDT") => Ok(amount),
+        Some("DBIT") => Ok(-amount),
+        Some(other) => Err(invalid(format!("invalid <CdtDbtInd> {other:?}"))),
+        None => Err(invalid("missing <CdtDbtInd>".into())),
+    }
+}
+
+/// Date of the `<Dt>` or `<DtTm>` below `name`, the time is dropped.
+fn date_of(element: &Element, name: &str) -> Result<Date, Error> {
+    let raw = element
+        .text_at(&[name, "Dt"])
+        .or_else(|| element.text_at(&[name, "DtTm"]))
+        .ok_or_else(|| {
+            Error::InvalidRow(format!("camt line {}: missing <{name}>", element.line))
+        })?;
+    Date::from_str(raw.get(..10).unwrap_or(raw))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use rust_decimal::dec;
+
+    use crate::{
+        camt::{parse, read},
+        date::Date,
+        error::Error,
+        events::MAX_TEXT_LEN,
+        ingest::{feed_statement_processor, post_statement},
+        ledger::{Journal, OPENING_BALANCES},
+        objects::statements::{Balance, StatementAccounts, StatementLine},
+        processor::Processor,
+    };
+
+    #[test]
+    fn read_camt053_statement() {
+        let statements = read(Path::new("tests/statement.camt053.xml")).expect("valid camt.053");
+        assert_eq!(1, statements.len());
+        let statement = &statements[0];
+        assert_eq!("STMT-2025-03", statement.balances.statement);
+        assert_eq!(
+            Some(Balance {
+                date: Date::new(2025, 2, 28).expect("valid date"),
+                amount: dec!(1000.00)
+            }),
+            statement.balances.opening
+        );
+        assert_eq!(
+            dec!(2155.51),
+            statement.balances.closing.expect("has closing").amount
+        );
+        assert_eq!(3, statement.lines.len(), "pending entry is skipped");
+        assert_eq!(
+            (
+                24,
+                StatementLine {
+                    date: Date::new(2025, 3, 3).expect("valid date"),
+                    amount: dec!(1500.00),
+                    payee: Some("ACME GmbH".into()),
+                    memo: Some("Gehalt Maerz 2025".into()),
+                    external_id: Some("BANKREF-0001".into()),
+                    value_date: Some(Date::new(2025, 3, 4).expect("valid date")),
+                    counterparty_account: Some("DE89370400440532013000".into()),
+                    reference: Some("PAYROLL-2025-03".into()),
+                    cleared: None,
+                    splits: Vec::new(),
+                }
+            ),
+            statement.lines[0]
+        );
+        let rent = &statement.lines[1].1;
+        assert_eq!(dec!(-320.00), rent.amount);
+        assert_eq!(Some("Hausverwaltung Meier".into()), rent.payee);
+        assert_eq!(Some("Miete Maerz Whg 3".into()), rent.memo);
+        assert_eq!(None, rent.reference, "NOTPROVIDED is no reference");
+        assert_eq!(Some("Kontofuehrung".into()), statement.lines[2].1.memo);
+    }
+
+    #[test]
+    fn read_camt054_notification() {
+        let statements = read(Path::new("tests/notification.camt054.xml")).expect("valid camt.054");
+        assert_eq!(1, statements.len());
+        assert_eq!(None, statements[0].balances.opening);
+        let (_, line) = &statements[0].lines[0];
+        assert_eq!(dec!(-49.90), line.amount);
+        assert_eq!(Some("Stadtwerke".into()), line.payee);
+        assert_eq!(
+            Some("AT611904300234573201".into()),
+            line.counterparty_account
+        );
+        assert_eq!(Some("RF18539007547034".into()), line.memo);
+    }
+
+    #[test]
+    fn invalid_entries_are_errors() {
+        let doc = |entry: &str| {
+            format!(
+                "<Document><BkToCstmrStmt><Stmt><Id>1</Id>{entry}</Stmt></BkToCstmrStmt></Document>"
+            )
+        };
+        assert!(
+            parse(&doc(""))
+                .expect("empty statement")
+                .remove(0)
+                .lines
+                .is_empty()
+        );
+        let missing_indicator =
+            "<Ntry><Amt>1.00</Amt><BookgDt><Dt>2025-01-01</Dt></BookgDt></Ntry>";
+        assert!(parse(&doc(missing_indicator)).is_err());
+        let missing_date = "<Ntry><Amt>1.00</Amt><CdtDbtInd>CRDT</CdtDbtInd></Ntry>";
+        assert!(parse(&doc(missing_date)).is_err());
+        let long_reference = format!(
+            "<Ntry><Amt>1.00</Amt><CdtDbtInd>CRDT</CdtDbtInd><BookgDt><Dt>2025-01-01</Dt></BookgDt>\
+             <AcctSvcrRef>{}</AcctSvcrRef></Ntry>",
+            "9".repeat(MAX_TEXT_LEN + 1)
+        );
+        assert!(parse(&doc(&long_reference)).is_err());
+        assert!(parse("<Document></Document>").is_err());
+    }
+
+    #[test]
+    fn statement_reconciles_with_journal() {
+        let mut journal = Journal::new();
+        let accounts = StatementAccounts::default();
+        let statement = read(Path::new("tests/statement.camt053.xml"))
+            .expect("valid camt.053")
+            .remove(0);
+        post_statement(
+            statement.lines.clone().into_iter().map(Ok),
+            Some(&statement.balances),
+            &accounts,
+            &mut journal,
+            |line, err| panic!("line {line} refused: {err}"),
+        )
+        .expect("balances reconcile");
+        assert_eq!(dec!(2155.51), journal.balance("Assets:Bank"));
+        assert_eq!(dec!(-1000.00), journal.balance(OPENING_BALANCES));
+        assert_eq!(
+            Some("DE89370400440532013000"),
+            journal.entries()[1].meta("counterparty_account")
+        );
+
+        // The same statement once more does no longer start where the ledger stands
+        let mut refused = 0;
+        let err = post_statement(
+            statement.lines.into_iter().map(Ok),
+            Some(&statement.balances),
+            &accounts,
+            &mut journal,
+            |_, _| refused += 1,
+        )
+        .expect_err("opening balance does not match");
+        assert_eq!(3, refused);
+        assert!(matches!(
+            err,
+            Error::BalanceMismatch {
+                kind: "opening",
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn statement_reconciles_with_client_account() {
+        let mut statement = read(Path::new("tests/statement.camt053.xml"))
+            .expect("valid camt.053")
+            .remove(0);
+        let mut p = Processor::new();
+        feed_statement_processor(
+            statement.lines.clone().into_iter().map(Ok),
+            Some(&statement.balances),
+            1,
+            &mut p,
+            |rejection| panic!("rejected {rejection}"),
+        )
+        .expect("balances reconcile");
+        let acc = p.get_account_store_ref().get(&1).expect("account exists");
+        assert_eq!(dec!(2155.51), acc.total());
+
+        // A dropped booking no longer adds up to the closing balance
+        statement.lines.remove(0);
+        let mut p = Processor::new();
+        let err = feed_statement_processor(
+            statement.lines.into_iter().map(Ok),
+            Some(&statement.balances),
+            1,
+            &mut p,
+            |rejection| panic!("rejected {rejection}"),
+        )
+        .expect_err("closing balance does not match");
+        assert!(matches!(
+            err,
+            Error::BalanceMismatch {
+                kind: "closing",
+                ..
+            }
+        ));
+    }
+}
@@ -0,0 +1,243 @@
+use std::{collections::BTreeMap, fs::OpenOptions, path::Path};
+
+use serde::Deserialize;
+
+use crate::{
+    error::Error,
+    ledger::{AccountType, LedgerError},
+};
+
+#[derive(Debug, Deserialize)]
+struct Declaration {
+    account: String,
+    r#type: AccountType,
+}
+
+/// ChartOfAccounts holds the declared ledger accounts. Names are colon-separated paths, e.g.,
+/// `Expenses:Food:Groceries`, where every prefix (`Expenses:Food`, `Expenses`) is a parent node
+/// balances roll up into.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct ChartOfAccounts {
+    // NOTE(juf): BTreeMap so parents are always listed right before their children.
+    accounts: BTreeMap<String, AccountType>,
+    strict: bool,
+}
+
+impl ChartOfAccounts {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads the declarations from a CSV file with the header `account,type`, where type is one of
+    /// `assets`, `liabilities`, `income`, `expenses` or `equity`.
+    pub(crate) fn from_csv(path: &Path) -> Result<Self, Error> {
+        let f = OpenOptions::new().read(true).open(path)?;
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .comment(Some(b'#'))
+            .from_reader(f);
+        let mut chart = Self::new();
+        for declaration in reader.deserialize() {
+            let Declaration { account, r#type } = declaration?;
+            chart.declare(&account, r#type)?;
+        }
+        Ok(chart)
+    }
+
+    /// Postings to accounts which have not been declared are refused in strict mode.
+    pub(crate) fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub(crate) fn declare(&mut self, name: &str, r#type: AccountType) -> Result<(), LedgerError> {
+        if name.is_empty() || name.split(':').any(str::is_empty) {
+            return Err(LedgerError::InvalidAccountName(name.into()));
+        }
+        let root = AccountType::of(name)?;
+        if root != r#type {
+            return Err(LedgerError::AccountTypeMismatch {
+                account: name.into(),
+                declared: r#type,
+                expected: root,
+            });
+        }
+        self.accounts.insert(name.into(), r#type);
+        Ok(())
+    }
+
+    pub(crate) fn is_declared(&self, name: &str) -> bool {
+        self.accounts.contains_key(name)
+    }
+
+    /// Fails for undeclared accounts, but only in strict mode.
+    pub(crate) fn check(&self, name: &str) -> Result<(), LedgerError> {
+        if self.strict && !self.is_declared(name) {
+            return Err(LedgerError::UndeclaredAccount(name.into()));
+        }
+        Ok(())
+    }
+}
+
+/// Iterates `Assets`, `Assets:Bank`, `Assets:Bank:Checking` for `Assets:Bank:Checking`.
+pub(crate) fn ancestors_and_self(name: &str) -> impl Iterator<Item = &str> {
+    name.match_indices(':')
+        .map(|(idx, _)| &name[..idx])
+        .chain(std::iter::once(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use rust_decimal::dec;
+
+    use crate::{
+        chart::{ChartOfAccounts, ancestors_and_self},
+        error::Rejection,
+        ledger::{
+            AccountType, BANK, DISPUTE_RECEIVABLE, Journal, JournalEntry, LedgerError, Posting,
+            client_available, client_held,
+        },
+        objects::transactions::{Metadata, Transaction, TxType},
+        processor::{Outcome, Processor},
+    };
+
+    fn groceries(tx_id: u32, account: &str) -> JournalEntry {
+        JournalEntry::new(
+            tx_id,
+            TxType::Withdrawal,
+            vec![
+                Posting::new(account, dec!(12.5)),
+                Posting::new("Assets:Bank:Checking", dec!(-12.5)),
+            ],
+        )
+        .expect("entry is balanced")
+    }
+
+    #[test]
+    fn ancestors_are_listed_root_first() {
+        assert_eq!(
+            vec!["Assets", "Assets:Bank", "Assets:Bank:Checking"],
+            ancestors_and_self("Assets:Bank:Checking").collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec!["Assets"],
+            ancestors_and_self("Assets").collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn declarations_must_match_root_type() {
+        let mut chart = ChartOfAccounts::new();
+        assert!(
+            chart
+                .declare("Expenses:Food", AccountType::Expenses)
+                .is_ok()
+        );
+        assert_eq!(
+            Err(LedgerError::AccountTypeMismatch {
+                account: "Expenses:Salary".into(),
+                declared: AccountType::Income,
+                expected: AccountType::Expenses
+            }),
+            chart.declare("Expenses:Salary", AccountType::Income)
+        );
+        assert_eq!(
+            Err(LedgerError::InvalidAccountName("Expenses::Food".into())),
+            chart.declare("Expenses::Food", AccountType::Expenses)
+        );
+    }
+
+    #[test]
+    fn strict_chart_refuses_undeclared_accounts() {
+        let chart = ChartOfAccounts::from_csv(Path::new("tests/chart-household.csv"))
+            .expect("chart should be valid")
+            .strict(true);
+        let mut journal = Journal::with_chart(chart);
+        assert!(
+            journal
+                .post(groceries(1, "Expenses:Food:Groceries"))
+                .is_ok()
+        );
+        assert_eq!(
+            Err(LedgerError::UndeclaredAccount("Expenses:Food:Candy".into())),
+            journal.post(groceries(2, "Expenses:Food:Candy"))
+        );
+        assert_eq!(
+            1,
+            journal.entries().len(),
+            "refused entry must not be recorded"
+        );
+        assert_eq!(dec!(0), journal.balance("Expenses:Food:Candy"));
+    }
+
+    #[test]
+    fn lenient_chart_accepts_undeclared_accounts() {
+        let chart = ChartOfAccounts::from_csv(Path::new("tests/chart-household.csv"))
+            .expect("chart should be valid");
+        let mut journal = Journal::with_chart(chart);
+        assert!(journal.post(groceries(1, "Expenses:Food:Candy")).is_ok());
+    }
+
+    #[test]
+    fn balances_roll_up_to_parents() {
+        let chart = ChartOfAccounts::from_csv(Path::new("tests/chart-household.csv"))
+            .expect("chart should be valid")
+            .strict(true);
+        let mut journal = Journal::with_chart(chart);
+        journal
+            .post(groceries(1, "Expenses:Food:Groceries"))
+            .expect("account is declared");
+        journal
+            .post(groceries(2, "Expenses:Food:Restaurants"))
+            .expect("account is declared");
+        journal
+            .post(groceries(3, "Expenses:Household"))
+            .expect("account is declared");
+        let rolled_up = journal.rolled_up_balances();
+        assert_eq!(Some(&dec!(12.5)), rolled_up.get("Expenses:Food:Groceries"));
+        assert_eq!(Some(&dec!(25.0)), rolled_up.get("Expenses:Food"));
+        assert_eq!(Some(&dec!(37.5)), rolled_up.get("Expenses"));
+        assert_eq!(Some(&dec!(-37.5)), rolled_up.get("Assets"));
+        assert_eq!(None, rolled_up.get("Income"));
+    }
+
+    #[test]
+    fn strict_chart_rejects_transactions_of_undeclared_clients() {
+        let mut chart = ChartOfAccounts::new().strict(true);
+        for account in [
+            BANK.to_string(),
+            DISPUTE_RECEIVABLE.to_string(),
+            client_available(1),
+            client_held(1),
+        ] {
+            chart
+                .declare(&account, AccountType::of(&account).expect("valid root"))
+                .expect("valid declaration");
+        }
+        let mut p = Processor::new().with_journal(Journal::with_chart(chart));
+        assert_eq!(
+            Outcome::Applied,
+            p.process_one(Transaction::Deposit(Metadata::new(1, 1), dec!(1.0)))
+                .expect("can process")
+        );
+        assert_eq!(
+            Outcome::Rejected(Rejection::Ledger {
+                client: 2,
+                tx_id: 2,
+                source: LedgerError::UndeclaredAccount(client_available(2))
+            }),
+            p.process_one(Transaction::Deposit(Metadata::new(2, 2), dec!(1.0)))
+                .expect("can process")
+        );
+        assert_eq!(
+            dec!(0),
+            p.get_account_store_ref()
+                .get(&2)
+                .map(|acc| acc.available)
+                .unwrap_or_default(),
+            "rejected transaction must not touch the account"
+        );
+    }
+}
@@ -0,0 +1,580 @@
+use std::{path::PathBuf, str::FromStr};
+
+use crate::{
+    amount::{OverPrecise, Precision, Rounding, SCALE},
+    date::Date,
+    error::Error,
+    objects::statements::StatementAccounts,
+    policy::{LockedAccounts, WithdrawalDisputes},
+    sha256::{self, Digest},
+    shard::MAX_SHARDS,
+};
+
+/// Layout of the input file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum InputFormat {
+    /// The `type,client,tx,amount` transactions CSV
+    Transactions,
+    /// OFX 1.x/2.x or QFX statement
+    Ofx,
+    /// ISO 20022 camt.052/053/054 statement
+    Camt,
+    /// SWIFT MT940 customer statement
+    Mt940,
+    /// Quicken Interchange Format
+    Qif,
+    /// Beancount or ledger-cli/hledger journal
+    Journal,
+}
+
+impl FromStr for InputFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" | "transactions" => Ok(InputFormat::Transactions),
+            "ofx" | "qfx" => Ok(InputFormat::Ofx),
+            "camt" | "camt052" | "camt053" | "camt054" => Ok(InputFormat::Camt),
+            "mt940" | "sta" => Ok(InputFormat::Mt940),
+            "qif" => Ok(InputFormat::Qif),
+            "beancount" | "ledger" | "hledger" | "journal" => Ok(InputFormat::Journal),
+            _ => Err(Error::InvalidArgument(format!("unknown format {s}"))),
+        }
+    }
+}
+
+/// What the run does with its input.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Command {
+    /// Process the input file
+    #[default]
+    Run,
+    /// Rebuild the accounts from the event log given as input, `replay <log>`
+    Replay,
+    /// Check the hash chain of the event log given as input, `verify <log>`
+    Verify,
+    /// Rewrite the version 1 event log given as input with hash chain, `upgrade <log>`
+    Upgrade,
+}
+
+// NOTE(juf): Hand-rolled on purpose, the handful of flags does not justify pulling in clap and
+// its dependency tree.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct Args {
+    pub(crate) command: Command,
+    pub(crate) input: PathBuf,
+    /// Write rows that can not be turned into a transaction into this file instead of aborting.
+    pub(crate) quarantine: Option<PathBuf>,
+    /// Write the double-entry journal, one row per posting, into this file.
+    pub(crate) journal: Option<PathBuf>,
+    /// Chart of accounts (`account,type` CSV) the journal is checked against.
+    pub(crate) chart: Option<PathBuf>,
+    /// Refuse postings to accounts not declared in the chart of accounts.
+    pub(crate) strict: bool,
+    /// Write the rolled up balance of every ledger account node into this file.
+    pub(crate) balances: Option<PathBuf>,
+    /// Write the register of the bank account as QIF into this file.
+    pub(crate) qif: Option<PathBuf>,
+    /// Write the full history as Beancount file.
+    pub(crate) beancount: Option<PathBuf>,
+    /// Write the full history as ledger-cli/hledger journal.
+    pub(crate) ledger: Option<PathBuf>,
+    /// Commodity amounts are written with in Beancount and ledger files, `USD` if not given.
+    pub(crate) commodity: Option<String>,
+    /// Date Beancount and ledger files use for entries without one, today if not given.
+    pub(crate) as_of: Option<Date>,
+    /// Decimal places amounts are taken in and written out with, 4 if not given.
+    pub(crate) scale: Option<u32>,
+    /// How amounts are rounded to the scale, banker's rounding if not given.
+    pub(crate) rounding: Option<Rounding>,
+    /// Whether input amounts more precise than the scale are rejected or rounded.
+    pub(crate) over_precise: Option<OverPrecise>,
+    /// Load the processor state from this file before the run and save it back after it.
+    /// `replay` starts from scratch and only saves the state it rebuilt.
+    pub(crate) state: Option<PathBuf>,
+    /// Append every applied transaction to this event log.
+    pub(crate) event_log: Option<PathBuf>,
+    /// Head hash `verify` expects the event log to end in, as printed by the run that wrote it.
+    pub(crate) head: Option<Digest>,
+    /// Keep the cached transactions in this file instead of memory, it is overwritten.
+    pub(crate) txn_store: Option<PathBuf>,
+    /// MiB of cached transactions the transaction store file keeps in memory.
+    pub(crate) memory_budget: Option<usize>,
+    /// Move finalized transactions into the compact set once that many piled up.
+    pub(crate) compact_every: Option<usize>,
+    /// Report the memory taken up by transactions to stderr at the end of the run.
+    pub(crate) stats: bool,
+    /// Check the invariants of the account after every transaction, abort on the first one broken.
+    pub(crate) paranoid: bool,
+    /// Check the invariants of every account at the end of the run and report on them to stderr.
+    pub(crate) check: bool,
+    /// What disputes of withdrawals do to the balances, what the books were kept with or `hold`
+    /// if not given.
+    pub(crate) withdrawal_disputes: Option<WithdrawalDisputes>,
+    /// What locked accounts still take besides unlocks, `block-all` if not given.
+    pub(crate) locked_accounts: Option<LockedAccounts>,
+    /// Process the transactions on that many threads, each owning the clients `client % n`.
+    pub(crate) shards: Option<usize>,
+    /// Keep the books in this SQLite file, continuing from what it holds.
+    pub(crate) sqlite: Option<PathBuf>,
+    /// Read the input as bank statement described by this import profile.
+    pub(crate) profile: Option<PathBuf>,
+    /// Layout of the input, derived from the file extension if not given.
+    pub(crate) format: Option<InputFormat>,
+    /// Client bank statement bookings are applied to, without one they are booked into the journal.
+    pub(crate) client: Option<u16>,
+    /// Ledger account of the bank account statements are booked against.
+    pub(crate) account: Option<String>,
+}
+
+impl Args {
+    pub(crate) fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, Error> {
+        let mut parsed = Args::default();
+        let mut input = None;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--quarantine" => parsed.quarantine = Some(value_of(&arg, args.next())?),
+                "--journal" => parsed.journal = Some(value_of(&arg, args.next())?),
+                "--chart" => parsed.chart = Some(value_of(&arg, args.next())?),
+                "--strict" => parsed.strict = true,
+                "--balances" => parsed.balances = Some(value_of(&arg, args.next())?),
+                "--qif" => parsed.qif = Some(value_of(&arg, args.next())?),
+                "--beancount" => parsed.beancount = Some(value_of(&arg, args.next())?),
+                "--ledger" => parsed.ledger = Some(value_of(&arg, args.next())?),
+                "--commodity" => parsed.commodity = Some(value_of(&arg, args.next())?),
+                "--as-of" => parsed.as_of = Some(value_of(&arg, args.next())?),
+                "--scale" => parsed.scale = Some(value_of(&arg, args.next())?),
+                "--rounding" => parsed.rounding = Some(value_of(&arg, args.next())?),
+                "--over-precise" => parsed.over_precise = Some(value_of(&arg, args.next())?),
+                "--state" => parsed.state = Some(value_of(&arg, args.next())?),
+                "--event-log" => parsed.event_log = Some(value_of(&arg, args.next())?),
+                "--head" => {
+                    let value: String = value_of(&arg, args.next())?;
+                    parsed.head = Some(sha256::from_hex(&value).ok_or_else(|| {
+                        Error::InvalidArgument(format!(
+                            "invalid value {value} for --head, expected a SHA-256 in hex"
+                        ))
+                    })?);
+                }
+                "--txn-store" => parsed.txn_store = Some(value_of(&arg, args.next())?),
+                "--memory-budget" => parsed.memory_budget = Some(value_of(&arg, args.next())?),
+                "--compact-every" => parsed.compact_every = Some(value_of(&arg, args.next())?),
+                "--stats" => parsed.stats = true,
+                "--paranoid" => parsed.paranoid = true,
+                "--check" => parsed.check = true,
+                "--withdrawal-disputes" => {
+                    parsed.withdrawal_disputes = Some(value_of(&arg, args.next())?)
+                }
+                "--locked-accounts" => parsed.locked_accounts = Some(value_of(&arg, args.next())?),
+                "--shards" => parsed.shards = Some(value_of(&arg, args.next())?),
+                "--sqlite" if cfg!(feature = "sqlite") => {
+                    parsed.sqlite = Some(value_of(&arg, args.next())?)
+                }
+                "--sqlite" => {
+                    return Err(Error::InvalidArgument(
+                        "--sqlite requires a build with the sqlite feature".into(),
+                    ));
+                }
+                "--profile" => parsed.profile = Some(value_of(&arg, args.next())?),
+                "--format" => parsed.format = Some(value_of(&arg, args.next())?),
+                "--client" => parsed.client = Some(value_of(&arg, args.next())?),
+                "--account" => parsed.account = Some(value_of(&arg, args.next())?),
+                flag if flag.starts_with("--") => {
+                    return Err(Error::InvalidArgument(format!("unknown flag {flag}")));
+                }
+                // A file named like a command has to be given as path, e.g., `./replay`
+                "replay" if input.is_none() && parsed.command == Command::Run => {
+                    parsed.command = Command::Replay;
+                }
+                "verify" if input.is_none() && parsed.command == Command::Run => {
+                    parsed.command = Command::Verify;
+                }
+                "upgrade" if input.is_none() && parsed.command == Command::Run => {
+                    parsed.command = Command::Upgrade;
+                }
+                _ if input.is_none() => input = Some(PathBuf::from(arg)),
+                _ => {
+                    return Err(Error::InvalidArgument(format!("unexpected argument {arg}")));
+                }
+            }
+        }
+        parsed.input = input.ok_or(Error::MissingArgument)?;
+        if parsed.command == Command::Replay && parsed.event_log.is_some() {
+            return Err(Error::InvalidArgument(
+                "replay reads the event log, it does not append to one".into(),
+            ));
+        }
+        if parsed.head.is_some() && parsed.command != Command::Verify {
+            return Err(Error::InvalidArgument(
+                "--head is the head hash `verify` checks the event log against".into(),
+            ));
+        }
+        if parsed.sqlite.is_some() && (parsed.state.is_some() || parsed.txn_store.is_some()) {
+            return Err(Error::InvalidArgument(
+                "--sqlite keeps the books, it does not go with --state or --txn-store".into(),
+            ));
+        }
+        if parsed.memory_budget.is_some() && parsed.txn_store.is_none() {
+            return Err(Error::InvalidArgument(
+                "--memory-budget requires --txn-store, without it everything is in memory".into(),
+            ));
+        }
+        if parsed.scale.is_some_and(|scale| scale > SCALE) {
+            return Err(Error::InvalidArgument(format!(
+                "--scale takes at most {SCALE} decimal places"
+            )));
+        }
+        if let Some(shards) = parsed.shards {
+            if !(1..=MAX_SHARDS).contains(&shards) {
+                return Err(Error::InvalidArgument(format!(
+                    "--shards takes 1 to {MAX_SHARDS} shards"
+                )));
+            }
+            if parsed.command != Command::Run
+                || parsed.format() != InputFormat::Transactions
+                || parsed.profile.is_some()
+                || parsed.state.is_some()
+                || parsed.event_log.is_some()
+                || parsed.txn_store.is_some()
+                || parsed.sqlite.is_some()
+                || parsed.chart.is_some()
+                || parsed.paranoid
+                || parsed.writes_journal()
+            {
+                return Err(Error::InvalidArgument(
+                    "--shards processes transactions CSVs in memory only, without state, event \
+                     log, stores, journal or paranoid checks"
+                        .into(),
+                ));
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// Bytes of cached transactions the transaction store keeps in memory, 256 MiB by default.
+    pub(crate) fn memory_budget(&self) -> usize {
+        self.memory_budget
+            .unwrap_or(256)
+            .saturating_mul(1024 * 1024)
+    }
+
+    pub(crate) fn format(&self) -> InputFormat {
+        self.format.unwrap_or_else(|| {
+            match self
+                .input
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .map(str::to_ascii_lowercase)
+                .as_deref()
+            {
+                Some("ofx" | "qfx") => InputFormat::Ofx,
+                Some("xml") => InputFormat::Camt,
+                Some("mt940" | "sta") => InputFormat::Mt940,
+                Some("qif") => InputFormat::Qif,
+                Some("beancount" | "bean" | "ledger" | "hledger" | "journal") => {
+                    InputFormat::Journal
+                }
+                _ => InputFormat::Transactions,
+            }
+        })
+    }
+
+    pub(crate) fn precision(&self) -> Precision {
+        let default = Precision::default();
+        Precision {
+            scale: self.scale.unwrap_or(default.scale),
+            rounding: self.rounding.unwrap_or(default.rounding),
+            over_precise: self.over_precise.unwrap_or(default.over_precise),
+        }
+    }
+
+    /// Whether any output needs the double-entry journal.
+    pub(crate) fn writes_journal(&self) -> bool {
+        self.journal.is_some()
+            || self.balances.is_some()
+            || self.qif.is_some()
+            || self.beancount.is_some()
+            || self.ledger.is_some()
+    }
+
+    pub(crate) fn statement_accounts(&self) -> StatementAccounts {
+        let mut accounts = StatementAccounts::default();
+        if let Some(account) = &self.account {
+            accounts.account = account.clone();
+        }
+        accounts
+    }
+}
+
+fn value_of<T: FromStr>(flag: &str, value: Option<String>) -> Result<T, Error> {
+    let value = value.ok_or_else(|| Error::InvalidArgument(format!("{flag} requires a value")))?;
+    value
+        .parse()
+        .map_err(|_| Error::InvalidArgument(format!("invalid value {value} for {flag}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::{
+        amount::{OverPrecise, Precision, Rounding},
+        cli::{Args, Command, InputFormat},
+        error::Error,
+        policy::{LockedAccounts, WithdrawalDisputes},
+        sha256,
+    };
+
+    fn parse(args: &[&str]) -> Result<Args, Error> {
+        Args::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parse_input_and_flags_in_any_order() {
+        let expected = Args {
+            input: PathBuf::from("in.csv"),
+            quarantine: Some(PathBuf::from("bad.csv")),
+            chart: Some(PathBuf::from("chart.csv")),
+            strict: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            expected,
+            parse(&[
+                "in.csv",
+                "--quarantine",
+                "bad.csv",
+                "--strict",
+                "--chart",
+                "chart.csv"
+            ])
+            .expect("should parse")
+        );
+        assert_eq!(
+            expected,
+            parse(&[
+                "--chart",
+                "chart.csv",
+                "--strict",
+                "--quarantine",
+                "bad.csv",
+                "in.csv"
+            ])
+            .expect("should parse")
+        );
+    }
+
+    #[test]
+    fn parse_rejects_missing_and_unknown_arguments() {
+        assert!(matches!(parse(&[]), Err(Error::MissingArgument)));
+        assert!(matches!(
+            parse(&["in.csv", "--quarantine"]),
+            Err(Error::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            parse(&["in.csv", "--nope"]),
+            Err(Error::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            parse(&["in.csv", "other.csv"]),
+            Err(Error::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            parse(&["in.csv", "--client", "-1"]),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn parse_commands() {
+        let args = parse(&["replay", "events.log", "--state", "state"]).expect("should parse");
+        assert_eq!(Command::Replay, args.command);
+        assert_eq!(PathBuf::from("events.log"), args.input);
+        let args = parse(&["verify", "events.log"]).expect("should parse");
+        assert_eq!(Command::Verify, args.command);
+        let head = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        let args = parse(&["verify", "events.log", "--head", head]).expect("should parse");
+        assert_eq!(Some(head.to_string()), args.head.as_ref().map(sha256::hex));
+        assert!(matches!(
+            parse(&["verify", "events.log", "--head", "ba78"]),
+            Err(Error::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            parse(&["replay", "events.log", "--head", head]),
+            Err(Error::InvalidArgument(_))
+        ));
+        let args = parse(&["upgrade", "events.log"]).expect("should parse");
+        assert_eq!(Command::Upgrade, args.command);
+        let args = parse(&["./replay"]).expect("should parse");
+        assert_eq!(Command::Run, args.command);
+        assert!(matches!(parse(&["replay"]), Err(Error::MissingArgument)));
+        assert!(matches!(
+            parse(&["replay", "events.log", "--event-log", "events.log"]),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn parse_memory_budget() {
+        let args = parse(&["in.csv", "--txn-store", "txns", "--memory-budget", "64"])
+            .expect("should parse");
+        assert_eq!(Some(PathBuf::from("txns")), args.txn_store);
+        assert_eq!(64 * 1024 * 1024, args.memory_budget());
+        let args = parse(&["in.csv", "--txn-store", "txns"]).expect("should parse");
+        assert_eq!(256 * 1024 * 1024, args.memory_budget());
+        let args = parse(&["in.csv", "--compact-every", "1000", "--stats"]).expect("should parse");
+        assert_eq!((Some(1000), true), (args.compact_every, args.stats));
+        assert!(matches!(
+            parse(&["in.csv", "--memory-budget", "64"]),
+            Err(Error::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            parse(&["in.csv", "--txn-store", "txns", "--memory-budget", "1G"]),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn parse_sqlite() {
+        if cfg!(feature = "sqlite") {
+            let args = parse(&["in.csv", "--sqlite", "books.sqlite"]).expect("should parse");
+            assert_eq!(Some(PathBuf::from("books.sqlite")), args.sqlite);
+            assert!(matches!(
+                parse(&["in.csv", "--sqlite", "books.sqlite", "--state", "state"]),
+                Err(Error::InvalidArgument(_))
+            ));
+        } else {
+            assert!(matches!(
+                parse(&["in.csv", "--sqlite", "books.sqlite"]),
+                Err(Error::InvalidArgument(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn parse_shards() {
+        let args = parse(&[
+            "in.csv",
+            "--shards",
+            "4",
+            "--compact-every",
+            "10",
+            "--stats",
+            "--check",
+        ])
+        .expect("should parse");
+        assert_eq!((Some(4), true), (args.shards, args.check));
+        for invalid in [
+            &["in.csv", "--shards", "0"][..],
+            &["in.csv", "--shards", "65"],
+            &["in.csv", "--shards", "4", "--state", "state"],
+            &["in.csv", "--shards", "4", "--journal", "journal.csv"],
+            &["in.csv", "--shards", "4", "--paranoid"],
+            &["in.ofx", "--shards", "4", "--client", "1"],
+            &["replay", "events.log", "--shards", "4"],
+        ] {
+            assert!(
+                matches!(parse(invalid), Err(Error::InvalidArgument(_))),
+                "{invalid:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_precision() {
+        let args = parse(&["in.csv"]).expect("should parse");
+        assert_eq!(Precision::default(), args.precision());
+        let args = parse(&[
+            "in.csv",
+            "--scale",
+            "2",
+            "--rounding",
+            "half-up",
+            "--over-precise",
+            "round",
+        ])
+        .expect("should parse");
+        assert_eq!(
+            Precision {
+                scale: 2,
+                rounding: Rounding::HalfUp,
+                over_precise: OverPrecise::Round,
+            },
+            args.precision()
+        );
+        for invalid in [
+            &["in.csv", "--scale", "5"][..],
+            &["in.csv", "--scale", "-1"],
+            &["in.csv", "--rounding", "up"],
+            &["in.csv", "--over-precise", "ignore"],
+        ] {
+            assert!(
+                matches!(parse(invalid), Err(Error::InvalidArgument(_))),
+                "{invalid:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_withdrawal_disputes() {
+        assert_eq!(
+            None,
+            parse(&["in.csv"])
+                .expect("should parse")
+                .withdrawal_disputes
+        );
+        for (policy, expected) in [
+            ("hold", WithdrawalDisputes::Hold),
+            (
+                "credit-on-chargeback",
+                WithdrawalDisputes::CreditOnChargeback,
+            ),
+            ("refuse", WithdrawalDisputes::Refuse),
+        ] {
+            let args = parse(&["in.csv", "--withdrawal-disputes", policy]).expect("should parse");
+            assert_eq!(Some(expected), args.withdrawal_disputes);
+        }
+        assert!(matches!(
+            parse(&["in.csv", "--withdrawal-disputes", "ignore"]),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn parse_locked_accounts() {
+        assert_eq!(
+            None,
+            parse(&["in.csv"]).expect("should parse").locked_accounts
+        );
+        for (policy, expected) in [
+            ("block-all", LockedAccounts::BlockAll),
+            ("block-withdrawals", LockedAccounts::BlockWithdrawals),
+            ("allow-disputes", LockedAccounts::AllowDisputes),
+        ] {
+            let args = parse(&["in.csv", "--locked-accounts", policy]).expect("should parse");
+            assert_eq!(Some(expected), args.locked_accounts);
+        }
+        assert!(matches!(
+            parse(&["in.csv", "--locked-accounts", "allow-all"]),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn format_defaults_to_file_extension() {
+        let args = parse(&["in.QFX"]).expect("should parse");
+        assert_eq!(InputFormat::Ofx, args.format());
+        let args = parse(&["in.csv"]).expect("should parse");
+        assert_eq!(InputFormat::Transactions, args.format());
+        let args = parse(&["in.txt", "--format", "ofx"]).expect("should parse");
+        assert_eq!(InputFormat::Ofx, args.format());
+        let args = parse(&["statement.camt053.xml"]).expect("should parse");
+        assert_eq!(InputFormat::Camt, args.format());
+        let args = parse(&["export.STA"]).expect("should parse");
+        assert_eq!(InputFormat::Mt940, args.format());
+        let args = parse(&["history.qif"]).expect("should parse");
+        assert_eq!(InputFormat::Qif, args.format());
+        let args = parse(&["books.beancount"]).expect("should parse");
+        assert_eq!(InputFormat::Journal, args.format());
+    }
+}
@@ -0,0 +1,224 @@
+use std::{
+    fmt::Display,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+/// Plain calendar date, bank statements do not carry more than that.
+// NOTE(juf): Hand-rolled instead of pulling in chrono/time, we only need parsing, validation and
+// ordering of dates, stepping to the next day and today's date in UTC, no time zones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub(crate) struct Date {
+    year: u16,
+    month: u8,
+    day: u8,
+}
+
+impl Date {
+    pub(crate) fn new(year: u16, month: u8, day: u8) -> Result<Self, Error> {
+        if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+            return Err(Error::InvalidDate(format!("{year:04}-{month:02}-{day:02}")));
+        }
+        Ok(Self { year, month, day })
+    }
+
+    pub(crate) fn year(&self) -> u16 {
+        self.year
+    }
+
+    pub(crate) fn month(&self) -> u8 {
+        self.month
+    }
+
+    pub(crate) fn day(&self) -> u8 {
+        self.day
+    }
+
+    pub(crate) fn next_day(self) -> Self {
+        if self.day < days_in_month(self.year, self.month) {
+            Self {
+                day: self.day + 1,
+                ..self
+            }
+        } else if self.month < 12 {
+            Self {
+                month: self.month + 1,
+                day: 1,
+                ..self
+            }
+        } else {
+            Self {
+                year: self.year + 1,
+                month: 1,
+                day: 1,
+            }
+        }
+    }
+
+    /// Today in UTC.
+    pub(crate) fn today() -> Self {
+        let days = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs() / 86_400);
+        Self::from_days_since_epoch(days)
+    }
+
+    /// Civil date of the day `days` after 1970-01-01, see
+    /// <https://howardhinnant.github.io/date_algorithms.html#civil_from_days>.
+    fn from_days_since_epoch(days: u64) -> Self {
+        let z = days + 719_468;
+        let era = z / 146_097;
+        let day_of_era = z - era * 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+        let month = if shifted_month < 10 {
+            shifted_month + 3
+        } else {
+            shifted_month - 9
+        };
+        let year = year_of_era + era * 400 + u64::from(month <= 2);
+        Self {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+        }
+    }
+
+    /// Parses `value` according to `format`, which supports `%Y` (4 digit year), `%y` (2 digit
+    /// year, 20xx), `%m` (month), `%d` (day), everything else has to match literally.
+    /// Month and day accept one or two digits.
+    pub(crate) fn parse(value: &str, format: &str) -> Result<Self, Error> {
+        let invalid = || Error::InvalidDate(format!("{value:?} does not match {format:?}"));
+        let (mut year, mut month, mut day) = (None, None, None);
+        let mut rest = value.trim();
+        let mut spec = format.chars();
+        while let Some(c) = spec.next() {
+            if c != '%' {
+                rest = rest.strip_prefix(c).ok_or_else(invalid)?;
+                continue;
+            }
+            let (max_digits, target) = match spec.next() {
+                Some('Y') => (4, &mut year),
+                Some('y') => (2, &mut year),
+                Some('m') => (2, &mut month),
+                Some('d') => (2, &mut day),
+                _ => return Err(Error::InvalidDate(format!("unsupported format {format:?}"))),
+            };
+            let digits = rest
+                .bytes()
+                .take(max_digits)
+                .take_while(u8::is_ascii_digit)
+                .count();
+            if digits == 0 {
+                return Err(invalid());
+            }
+            let number: u16 = rest[..digits].parse().map_err(|_| invalid())?;
+            *target = Some(number);
+            rest = &rest[digits..];
+        }
+        if !rest.is_empty() {
+            return Err(invalid());
+        }
+        let year = year.ok_or_else(invalid)?;
+        let year = if year < 100 { 2000 + year } else { year };
+        Self::new(
+            year,
+            month.ok_or_else(invalid)? as u8,
+            day.ok_or_else(invalid)? as u8,
+        )
+    }
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400)) => {
+            29
+        }
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+impl Display for Date {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+impl FromStr for Date {
+    type Err = Error;
+
+    /// ISO 8601, `YYYY-MM-DD`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s, "%Y-%m-%d")
+    }
+}
+
+impl TryFrom<String> for Date {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Date> for String {
+    fn from(value: Date) -> Self {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::date::Date;
+
+    #[test]
+    fn parse_formats() {
+        let expected = Date::new(2025, 3, 7).expect("valid date");
+        assert_eq!(
+            expected,
+            Date::parse("07.03.2025", "%d.%m.%Y").expect("valid")
+        );
+        assert_eq!(
+            expected,
+            Date::parse("07.03.25", "%d.%m.%y").expect("valid")
+        );
+        assert_eq!(
+            expected,
+            Date::parse("3/7/2025", "%m/%d/%Y").expect("valid")
+        );
+        assert_eq!(expected, Date::parse("20250307", "%Y%m%d").expect("valid"));
+        assert_eq!(expected, "2025-03-07".parse().expect("valid"));
+        assert_eq!("2025-03-07", expected.to_string());
+    }
+
+    #[test]
+    fn parse_rejects_invalid_dates() {
+        assert!(Date::parse("29.02.2025", "%d.%m.%Y").is_err());
+        assert!(Date::parse("29.02.2024", "%d.%m.%Y").is_ok());
+        assert!(Date::parse("31.04.2024", "%d.%m.%Y").is_err());
+        assert!(Date::parse("2024-13-01", "%Y-%m-%d").is_err());
+        assert!(Date::parse("2024-01-01 trailing", "%Y-%m-%d").is_err());
+        assert!(Date::parse("01.01.2024", "%Y-%m-%d").is_err());
+    }
+
+    #[test]
+    fn day_arithmetic() {
+        let date = |y, m, d| Date::new(y, m, d).expect("valid date");
+        assert_eq!(date(2024, 2, 29), date(2024, 2, 28).next_day());
+        assert_eq!(date(2025, 3, 1), date(2025, 2, 28).next_day());
+        assert_eq!(date(2026, 1, 1), date(2025, 12, 31).next_day());
+        assert_eq!(date(1970, 1, 1), Date::from_days_since_epoch(0));
+        assert_eq!(date(2000, 2, 29), Date::from_days_since_epoch(11_016));
+        assert_eq!(date(2024, 10, 4), Date::from_days_since_epoch(20_000));
+    }
+}
@@ -35,7 +35,8 @@ deposit, 2, 3, 4.0"#;
                 r#type: TxType::Deposit,
                 client: 2,
                 tx: 3,
-                amount: Some(dec!(4.0))
+                amount: Some(dec!(4.0)),
+                reason: None
             }
         );
     }
@@ -60,7 +61,8 @@ deposit, 2, 3, 4.0"#;
                 r#type: TxType::Deposit,
                 client: 2,
                 tx: 3,
-                amount: Some(dec!(4.0))
+                amount: Some(dec!(4.0)),
+                reason: None
             }
         );
         let txn: Transaction = next
@@ -117,4 +119,34 @@ withdrawal, 2, 4, 4.0"#;
             Transaction::Withdrawal(Metadata::new(2, 4), dec!(4.0))
         );
     }
+
+    #[test]
+    fn unlock_rows_need_a_reason() {
+        let doc = r#"type,client,tx,amount,reason
+unlock, 2, 5, ,"chargeback investigated, card was stolen"
+reinstate, 2, 6, , reopened
+unlock, 2, 7, ,"#;
+        let doc = format!(
+            "{doc}\nunlock, 2, 8, ,{}",
+            "x".repeat(crate::events::MAX_TEXT_LEN + 1)
+        );
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(doc.as_bytes());
+        let txns: Vec<_> = rdr
+            .deserialize::<Row>()
+            .map(|row| Transaction::try_from(row.expect("row should contain no errors")))
+            .collect();
+        assert!(matches!(
+            &txns[..],
+            [
+                Ok(Transaction::Unlock(first, reason)),
+                Ok(Transaction::Unlock(_, _)),
+                Err(crate::error::Error::InvalidRow(_)),
+                Err(crate::error::Error::InvalidRow(too_long)),
+            ] if too_long.contains("reason")
+                && *first == Metadata::new(2, 5)
+                && reason == "chargeback investigated, card was stolen"
+        ));
+    }
 }
@@ -4,8 +4,6 @@ use std::{
     path::Path,
 };
 
-// used by test, we could move it there to avoid the linter error
-#[allow(dead_code)]
 pub(crate) fn default_csv_egress(path: &Path) -> std::io::Result<csv::Writer<BufWriter<File>>> {
     // NOTE(juf): The buffer size can/should be adjusted based on the use-case.
     // NOTE(juf): csv already comes with buffered writes. This is just an example how buffered
@@ -28,3 +26,13 @@ pub(crate) fn stdout_csv_egress() -> std::io::Result<csv::Writer<BufWriter<Stdou
     let writer = csv::WriterBuilder::new().from_writer(buf);
     Ok(writer)
 }
+
+/// Plain buffered file for the text formats that are not CSV, e.g., QIF.
+pub(crate) fn default_egress(path: &Path) -> std::io::Result<BufWriter<File>> {
+    let f = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)?;
+    Ok(BufWriter::new(f))
+}
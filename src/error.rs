@@ -1,5 +1,16 @@
+use rust_decimal::Decimal;
 use thiserror::Error;
 
+use crate::{
+    amount::AmountError,
+    invariants::Report,
+    ledger::LedgerError,
+    objects::{
+        accounts::Overflow,
+        transactions::{TransactionState, TxType},
+    },
+};
+
 #[derive(Debug, Error)]
 pub(crate) enum Error {
     #[error("invalid row {0}")]
@@ -10,4 +21,134 @@ pub(crate) enum Error {
     StdIO(#[from] std::io::Error),
     #[error("cli requires input argument to read transactions from")]
     MissingArgument,
+    #[error("invalid argument: {0}")]
+    InvalidArgument(String),
+    #[error("ledger error {0}")]
+    Ledger(#[from] LedgerError),
+    #[error("invalid date {0}")]
+    InvalidDate(String),
+    #[error("invalid import profile {0}")]
+    InvalidProfile(String),
+    #[error("invalid state file {0}")]
+    InvalidState(String),
+    #[error("invalid event log {0}")]
+    InvalidLog(String),
+    #[cfg(feature = "sqlite")]
+    #[error("sqlite error {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error(
+        "statement {statement}: {kind} balance {expected} does not match the ledger balance {actual}"
+    )]
+    BalanceMismatch {
+        statement: String,
+        kind: &'static str,
+        expected: Decimal,
+        actual: Decimal,
+    },
+    #[error("broken invariants, {0}")]
+    BrokenInvariants(Report),
+}
+
+impl Error {
+    /// Stable, machine-readable identifier of the error, e.g., for the quarantine file.
+    pub(crate) fn code(&self) -> &'static str {
+        match self {
+            Error::InvalidRow(_) => "invalid_row",
+            Error::FailedDeserialization(err) => match err.kind() {
+                csv::ErrorKind::Utf8 { .. } => "invalid_utf8",
+                csv::ErrorKind::UnequalLengths { .. } => "unequal_lengths",
+                csv::ErrorKind::Deserialize { .. } => "malformed_field",
+                _ => "csv",
+            },
+            Error::StdIO(_) => "io",
+            Error::Ledger(_) => "ledger",
+            Error::InvalidDate(_) => "invalid_date",
+            Error::InvalidProfile(_) => "invalid_profile",
+            Error::InvalidState(_) => "invalid_state",
+            Error::InvalidLog(_) => "invalid_log",
+            #[cfg(feature = "sqlite")]
+            Error::Sqlite(_) => "sqlite",
+            Error::BalanceMismatch { .. } => "balance_mismatch",
+            Error::BrokenInvariants(_) => "broken_invariants",
+            Error::MissingArgument | Error::InvalidArgument(_) => "invalid_argument",
+        }
+    }
+}
+
+/// Reason why the [`crate::processor::Processor`] refused to apply a transaction. Every variant
+/// names the offending tx id and client, the variant itself is the rule that was broken.
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub(crate) enum Rejection {
+    #[error("tx {tx_id} (client {client}): negative amount {amount}")]
+    NegativeAmount {
+        client: u16,
+        tx_id: u32,
+        amount: Decimal,
+    },
+    /// Amounts of deposits and withdrawals are kept for disputes, packed into an [`Amount`].
+    ///
+    /// [`Amount`]: crate::amount::Amount
+    #[error("tx {tx_id} (client {client}): {source}")]
+    UnrepresentableAmount {
+        client: u16,
+        tx_id: u32,
+        source: AmountError,
+    },
+    /// Applying it would take a balance of the account beyond what [`Decimal`] holds.
+    #[error("tx {tx_id} (client {client}): {source}")]
+    BalanceOverflow {
+        client: u16,
+        tx_id: u32,
+        source: Overflow,
+    },
+    #[error("tx {tx_id} (client {client}): tx id was already processed")]
+    DuplicateTransaction { client: u16, tx_id: u32 },
+    #[error(
+        "tx {tx_id} (client {client}): insufficient funds, available {available}, requested {requested}"
+    )]
+    InsufficientFunds {
+        client: u16,
+        tx_id: u32,
+        available: Decimal,
+        requested: Decimal,
+    },
+    #[error("tx {tx_id} (client {client}): account is locked")]
+    AccountLocked { client: u16, tx_id: u32 },
+    #[error("tx {tx_id} (client {client}): referenced transaction is unknown")]
+    UnknownTransaction { client: u16, tx_id: u32 },
+    #[error("tx {tx_id} (client {client}): referenced transaction belongs to client {owner}")]
+    ClientMismatch { client: u16, tx_id: u32, owner: u16 },
+    #[error("tx {tx_id} (client {client}): cannot {action:?} a transaction in state {state:?}")]
+    InvalidTransition {
+        client: u16,
+        tx_id: u32,
+        action: TxType,
+        state: TransactionState,
+    },
+    #[error("tx {tx_id} (client {client}): withdrawals can not be disputed")]
+    WithdrawalDispute { client: u16, tx_id: u32 },
+    #[error("tx {tx_id} (client {client}): account is not locked")]
+    NotLocked { client: u16, tx_id: u32 },
+    /// Without an event log the operator's reason would not be kept anywhere.
+    #[error("tx {tx_id} (client {client}): unlocks need an event log to keep their reason in")]
+    UnrecordedUnlock { client: u16, tx_id: u32 },
+    #[error("tx {tx_id} (client {client}): external id {external_id} was already applied")]
+    DuplicateExternalId {
+        client: u16,
+        tx_id: u32,
+        external_id: String,
+    },
+    /// The transaction store failed, nothing was applied.
+    #[error("tx {tx_id} (client {client}): transaction store failed: {reason}")]
+    Storage {
+        client: u16,
+        tx_id: u32,
+        reason: String,
+    },
+    #[error("tx {tx_id} (client {client}): {source}")]
+    Ledger {
+        client: u16,
+        tx_id: u32,
+        source: LedgerError,
+    },
 }
@@ -0,0 +1,817 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use rust_decimal::Decimal;
+
+use crate::{
+    error::Error,
+    objects::transactions::{InitialState, Metadata, Transaction, TransactionState, TxType},
+    policy::WithdrawalDisputes,
+    sha256::{Digest, sha256},
+    state::{sibling, sync_parent},
+};
+
+// NOTE(juf): Append-only log of everything the processor accepted, the command side of the CQRS
+// idea from the README. The file starts with `MAGIC`, followed by one record per event:
+//
+//   length: u32 LE | previous: 32 bytes | payload | crc32(previous, payload): u32 LE
+//
+// where the length counts `previous` and the payload. Records form a hash chain, `previous` is
+// the SHA-256 of the record before, its `previous` and payload, all zeros for the first one.
+// Editing any record breaks the link of the one after it, so a log that verifies is the history
+// as it was appended, up to the head hash, which has to be kept elsewhere to also notice records
+// dropped from the end or rewritten with the chain recomputed, `verify --head` checks it. The
+// payload is
+//
+//   type: u8 | client: u16 LE | tx: u32 LE | amount: 16 bytes | state: u8 | external id: rest
+//
+// with the amount as `Decimal::serialize` and zero for disputes, resolves and chargebacks. Unlocks
+// refer to no transaction, their state is 5 and the rest is the reason the operator gave. The
+// first record may instead be the withdrawal dispute policy the log is kept with,
+//
+//   type: 6 | policy: rest
+//
+// as it is spelled on the command line. Replaying the log under another policy would end up with
+// other balances for disputes still open, see [`policy`](crate::policy). Logs from before the
+// policy was recorded were kept with `hold`, which was the only one there was. The last record may be cut short by a crash while appending, it was never acknowledged and is
+// dropped. A complete record with a wrong checksum is corruption and refused.
+//
+// Logs of the first version, `MAGIC_V1`, have no hash chain, their records are
+//
+//   length: u32 LE | payload | crc32(payload): u32 LE
+//
+// They are still read, checked by their checksums only, but not appended to. `upgrade` rewrites
+// one as chained log.
+
+const MAGIC: &[u8; 8] = b"CMEVLOG2";
+/// First version of the log, without the hash chain.
+const MAGIC_V1: &[u8; 8] = b"CMEVLOG1";
+const GENESIS: Digest = [0; 32];
+/// Type of the record holding the withdrawal dispute policy, after those of the events.
+const POLICY: u8 = 6;
+/// Fixed part of the payload, everything up to the external id.
+const FIXED_LEN: usize = 1 + 2 + 4 + 16 + 1;
+/// Upper bound of a single record, anything larger is a damaged length prefix.
+const MAX_LEN: u32 = 64 * 1024;
+/// Upper bound in bytes of the text a record ends in, an external id or unlock reason, keeps
+/// records well within `MAX_LEN`.
+pub(crate) const MAX_TEXT_LEN: usize = 1024;
+
+/// Checks that `text`, the `what` of a row, fits into an event record, see [`MAX_TEXT_LEN`].
+pub(crate) fn fits(what: &str, text: &str) -> Result<(), String> {
+    match text.len() {
+        len if len > MAX_TEXT_LEN => Err(format!(
+            "{what} is {len} bytes long, at most {MAX_TEXT_LEN} are kept"
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// A transaction the processor accepted together with the state it left the referenced
+/// transaction in, if it refers to one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Event {
+    pub(crate) action: TxType,
+    pub(crate) metadata: Metadata,
+    /// Amount of deposits and withdrawals, the others refer to one of those.
+    pub(crate) amount: Option<Decimal>,
+    pub(crate) state: Option<TransactionState>,
+    /// External id of a bank statement booking.
+    pub(crate) external_id: Option<String>,
+    /// Why the account was unlocked.
+    pub(crate) reason: Option<String>,
+}
+
+impl Event {
+    pub(crate) fn transaction(&self) -> Transaction {
+        let metadata = self.metadata.clone();
+        let amount = self.amount.unwrap_or_default();
+        match self.action {
+            TxType::Deposit => Transaction::Deposit(metadata, amount),
+            TxType::Withdrawal => Transaction::Withdrawal(metadata, amount),
+            TxType::Dispute => Transaction::Dispute(metadata),
+            TxType::Resolve => Transaction::Resolve(metadata),
+            TxType::Chargeback => Transaction::Chargeback(metadata),
+            TxType::Unlock => {
+                Transaction::Unlock(metadata, self.reason.clone().unwrap_or_default())
+            }
+        }
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(match self.action {
+            TxType::Deposit => 0,
+            TxType::Withdrawal => 1,
+            TxType::Dispute => 2,
+            TxType::Resolve => 3,
+            TxType::Chargeback => 4,
+            TxType::Unlock => 5,
+        });
+        out.extend(self.metadata.client.to_le_bytes());
+        out.extend(self.metadata.tx_id.to_le_bytes());
+        out.extend(self.amount.unwrap_or_default().serialize());
+        out.push(match self.state {
+            Some(TransactionState::Initial(InitialState::Deposit)) => 0,
+            Some(TransactionState::Initial(InitialState::Withdrawal)) => 1,
+            Some(TransactionState::Disputed(InitialState::Deposit)) => 2,
+            Some(TransactionState::Disputed(InitialState::Withdrawal)) => 3,
+            Some(TransactionState::Finalized) => 4,
+            None => 5,
+        });
+        if let Some(rest) = self.external_id.as_ref().or(self.reason.as_ref()) {
+            out.extend(rest.as_bytes());
+        }
+    }
+
+    fn decode(payload: &[u8]) -> Option<Self> {
+        if payload.len() < FIXED_LEN {
+            return None;
+        }
+        let action = match payload[0] {
+            0 => TxType::Deposit,
+            1 => TxType::Withdrawal,
+            2 => TxType::Dispute,
+            3 => TxType::Resolve,
+            4 => TxType::Chargeback,
+            5 => TxType::Unlock,
+            _ => return None,
+        };
+        let client = u16::from_le_bytes(payload[1..3].try_into().ok()?);
+        let tx_id = u32::from_le_bytes(payload[3..7].try_into().ok()?);
+        let amount = Decimal::deserialize(payload[7..23].try_into().ok()?);
+        let state = match payload[23] {
+            0 => Some(TransactionState::Initial(InitialState::Deposit)),
+            1 => Some(TransactionState::Initial(InitialState::Withdrawal)),
+            2 => Some(TransactionState::Disputed(InitialState::Deposit)),
+            3 => Some(TransactionState::Disputed(InitialState::Withdrawal)),
+            4 => Some(TransactionState::Finalized),
+            5 => None,
+            _ => return None,
+        };
+        let unlock = action == TxType::Unlock;
+        if unlock != state.is_none() {
+            return None;
+        }
+        let rest = match &payload[FIXED_LEN..] {
+            [] => None,
+            raw => Some(String::from_utf8(raw.to_vec()).ok()?),
+        };
+        let (external_id, reason) = if unlock { (None, rest) } else { (rest, None) };
+        Some(Self {
+            action,
+            metadata: Metadata { client, tx_id },
+            amount: matches!(action, TxType::Deposit | TxType::Withdrawal).then_some(amount),
+            state,
+            external_id,
+            reason,
+        })
+    }
+}
+
+/// Appending end of the event log.
+pub(crate) struct EventLog {
+    writer: BufWriter<File>,
+    /// Hash of the last record, the next one links to it.
+    head: Digest,
+    /// Policy the log is kept with, none as long as it has no records.
+    withdrawal_disputes: Option<WithdrawalDisputes>,
+    record: Vec<u8>,
+    // NOTE(juf): Appending happens while processing, where outcomes are about transactions and
+    // not about the disk. The first failure is kept, appending stops and `finish` reports it.
+    error: Option<std::io::Error>,
+}
+
+impl EventLog {
+    /// Opens the log at `path` for appending, creates it if missing. A record cut short by a
+    /// crash is cut off, so new records follow the last complete one. Version 1 logs have to be
+    /// upgraded first.
+    pub(crate) fn open(path: &Path) -> Result<Self, Error> {
+        let mut f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let (end, head, withdrawal_disputes) = if f.metadata()?.len() == 0 {
+            f.write_all(MAGIC)?;
+            (MAGIC.len() as u64, GENESIS, None)
+        } else {
+            let mut events = EventReader::new(BufReader::new(&mut f))?;
+            if !events.is_chained() {
+                return Err(Error::InvalidLog(
+                    "version 1 log without hash chain, run `upgrade` on it before appending".into(),
+                ));
+            }
+            for event in events.by_ref() {
+                event?;
+            }
+            let (end, head, torn) = (events.offset, events.head, events.torn);
+            let withdrawal_disputes = events.withdrawal_disputes;
+            drop(events);
+            if torn {
+                f.set_len(end)?;
+            }
+            (end, head, withdrawal_disputes)
+        };
+        f.seek(SeekFrom::Start(end))?;
+        Ok(Self {
+            writer: BufWriter::new(f),
+            head,
+            withdrawal_disputes,
+            record: Vec::with_capacity(GENESIS.len() + FIXED_LEN),
+            error: None,
+        })
+    }
+
+    pub(crate) fn append(&mut self, event: &Event) {
+        if self.error.is_some() {
+            return;
+        }
+        self.record.clear();
+        self.record.extend(self.head);
+        event.encode(&mut self.record);
+        // NOTE(juf): Rows are checked to fit when parsed, this keeps the writer from producing
+        // a record the reader refuses if one slips through anyway.
+        if self.record.len() > MAX_LEN as usize {
+            self.error = Some(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "event of tx {} is {} bytes long, records hold at most {MAX_LEN}",
+                    event.metadata.tx_id,
+                    self.record.len()
+                ),
+            ));
+            return;
+        }
+        self.write_record();
+    }
+
+    /// Policy the log is kept with, none as long as it has no records, see the note on top.
+    pub(crate) fn withdrawal_disputes(&self) -> Option<WithdrawalDisputes> {
+        self.withdrawal_disputes
+    }
+
+    /// Records `policy` as the one the log is kept with, only before any other record.
+    pub(crate) fn record_withdrawal_disputes(&mut self, policy: WithdrawalDisputes) {
+        if self.error.is_some() || self.withdrawal_disputes.is_some() {
+            return;
+        }
+        self.record.clear();
+        self.record.extend(self.head);
+        self.record.push(POLICY);
+        self.record.extend(policy.to_string().as_bytes());
+        self.write_record();
+        self.withdrawal_disputes = Some(policy);
+    }
+
+    /// Writes `record`, the link to the head and payload, with its length and checksum.
+    fn write_record(&mut self) {
+        let len = self.record.len() as u32;
+        let result = self
+            .writer
+            .write_all(&len.to_le_bytes())
+            .and_then(|_| self.writer.write_all(&self.record))
+            .and_then(|_| self.writer.write_all(&crc32(&self.record).to_le_bytes()));
+        match result {
+            Ok(()) => self.head = sha256(&[&self.record]),
+            Err(err) => self.error = Some(err),
+        }
+    }
+
+    /// Hash of the last record, it vouches for the whole history before it.
+    pub(crate) fn head(&self) -> Digest {
+        self.head
+    }
+
+    /// Flushes and syncs what was appended, reports the first failure to append if any.
+    pub(crate) fn finish(&mut self) -> Result<(), Error> {
+        if let Some(err) = self.error.take() {
+            return Err(err.into());
+        }
+        self.writer.flush()?;
+        self.writer.get_ref().sync_all()?;
+        Ok(())
+    }
+}
+
+/// Reads the events of a log in the order they were appended.
+pub(crate) struct EventReader<R> {
+    reader: R,
+    /// Byte offset past the last complete record.
+    offset: u64,
+    /// Number of records read.
+    count: u64,
+    /// Hash of the last record read.
+    head: Digest,
+    /// Whether the log ends in a record cut short.
+    torn: bool,
+    /// Whether records link to the one before, false for version 1 logs.
+    chained: bool,
+    /// Policy the log was kept with, known once the first record is read.
+    withdrawal_disputes: Option<WithdrawalDisputes>,
+    /// First event, read ahead to learn the policy.
+    peeked: Option<Result<Option<Event>, Error>>,
+    done: bool,
+}
+
+impl EventReader<BufReader<File>> {
+    pub(crate) fn open(path: &Path) -> Result<Self, Error> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> EventReader<R> {
+    pub(crate) fn new(mut reader: R) -> Result<Self, Error> {
+        let mut magic = [0u8; MAGIC.len()];
+        let chained = match reader.read_exact(&mut magic) {
+            Ok(()) if &magic == MAGIC => true,
+            Ok(()) if &magic == MAGIC_V1 => false,
+            Ok(()) | Err(_) => return Err(Error::InvalidLog("not an event log".into())),
+        };
+        let mut events = Self {
+            reader,
+            offset: MAGIC.len() as u64,
+            count: 0,
+            head: GENESIS,
+            torn: false,
+            chained,
+            withdrawal_disputes: None,
+            peeked: None,
+            done: false,
+        };
+        let first = events.read_event();
+        if matches!(first, Ok(Some(_))) && events.withdrawal_disputes.is_none() {
+            events.withdrawal_disputes = Some(WithdrawalDisputes::Hold);
+        }
+        events.peeked = Some(first);
+        Ok(events)
+    }
+
+    /// Policy the log was kept with, `hold` for logs from before it was recorded and none for
+    /// logs without events.
+    pub(crate) fn withdrawal_disputes(&self) -> Option<WithdrawalDisputes> {
+        self.withdrawal_disputes
+    }
+
+    /// Whether the records are hash chained, i.e., [`EventReader::head`] vouches for them. Version
+    /// 1 logs are only checked record by record.
+    pub(crate) fn is_chained(&self) -> bool {
+        self.chained
+    }
+
+    /// Whether the log ended in a record cut short, only known once all events are read.
+    pub(crate) fn is_torn(&self) -> bool {
+        self.torn
+    }
+
+    /// Number of records read so far.
+    pub(crate) fn records_read(&self) -> u64 {
+        self.count
+    }
+
+    /// Hash of the last record read so far.
+    pub(crate) fn head(&self) -> Digest {
+        self.head
+    }
+
+    fn read_event(&mut self) -> Result<Option<Event>, Error> {
+        let mut len = [0u8; 4];
+        match read_full(&mut self.reader, &mut len)? {
+            0 => return Ok(None),
+            4 => {}
+            _ => {
+                self.torn = true;
+                return Ok(None);
+            }
+        }
+        let len = u32::from_le_bytes(len);
+        let min_len = if self.chained {
+            GENESIS.len() as u32
+        } else {
+            0
+        };
+        if !(min_len..=MAX_LEN).contains(&len) {
+            return Err(self.corrupt(format!("implausible record length {len}")));
+        }
+        let mut record = vec![0u8; len as usize + 4];
+        if read_full(&mut self.reader, &mut record)? < record.len() {
+            self.torn = true;
+            return Ok(None);
+        }
+        let (linked, checksum) = record.split_at(len as usize);
+        if crc32(linked).to_le_bytes() != checksum {
+            return Err(self.corrupt("checksum does not match".into()));
+        }
+        if !self.chained {
+            let event =
+                Event::decode(linked).ok_or_else(|| self.corrupt("invalid payload".into()))?;
+            self.offset += 4 + u64::from(len) + 4;
+            self.count += 1;
+            return Ok(Some(event));
+        }
+        let (previous, payload) = linked.split_at(GENESIS.len());
+        if previous != self.head {
+            return Err(self.corrupt(match self.count {
+                0 => "does not start the chain, records before it are missing".into(),
+                count => {
+                    format!("does not link to record {count}, the history was edited in between")
+                }
+            }));
+        }
+        if payload.first() == Some(&POLICY) {
+            if self.count > 0 {
+                return Err(self.corrupt("the withdrawal dispute policy is not the first".into()));
+            }
+            let policy = std::str::from_utf8(&payload[1..])
+                .ok()
+                .and_then(|policy| policy.parse().ok())
+                .ok_or_else(|| self.corrupt("invalid withdrawal dispute policy".into()))?;
+            self.withdrawal_disputes = Some(policy);
+            self.head = sha256(&[linked]);
+            self.offset += 4 + u64::from(len) + 4;
+            self.count += 1;
+            return self.read_event();
+        }
+        let event = Event::decode(payload).ok_or_else(|| self.corrupt("invalid payload".into()))?;
+        self.head = sha256(&[linked]);
+        self.offset += 4 + u64::from(len) + 4;
+        self.count += 1;
+        Ok(Some(event))
+    }
+
+    fn corrupt(&self, reason: String) -> Error {
+        Error::InvalidLog(format!(
+            "record {} at byte {}: {reason}",
+            self.count + 1,
+            self.offset
+        ))
+    }
+}
+
+impl<R: Read> Iterator for EventReader<R> {
+    type Item = Result<Event, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let event = match self.peeked.take() {
+            Some(peeked) => peeked,
+            None => self.read_event(),
+        }
+        .transpose();
+        self.done = !matches!(event, Some(Ok(_)));
+        event
+    }
+}
+
+/// Rewrites the version 1 log at `path` as hash chained log, replacing it in one step. A record
+/// cut short at the end is dropped. Returns the number of records and the head of the chain.
+pub(crate) fn upgrade(path: &Path) -> Result<(u64, Digest), Error> {
+    let mut events = EventReader::open(path)?;
+    if events.is_chained() {
+        return Err(Error::InvalidLog("the log is hash chained already".into()));
+    }
+    let tmp = sibling(path, ".tmp");
+    File::create(&tmp)?;
+    let mut log = EventLog::open(&tmp)?;
+    if let Some(policy) = events.withdrawal_disputes() {
+        log.record_withdrawal_disputes(policy);
+    }
+    for event in events.by_ref() {
+        log.append(&event?);
+    }
+    log.finish()?;
+    drop(log.writer);
+    std::fs::rename(&tmp, path)?;
+    sync_parent(path)?;
+    Ok((events.records_read(), log.head))
+}
+
+/// Like `read_exact`, but returns how much was read when the input ends early.
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(read)
+}
+
+/// CRC-32 (IEEE), bitwise, records are small enough not to bother with a table.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::OpenOptions, io::Write};
+
+    use rust_decimal::dec;
+
+    use crate::{
+        error::Error,
+        events::{
+            Event, EventLog, EventReader, MAGIC, MAGIC_V1, MAX_LEN, MAX_TEXT_LEN, POLICY, crc32,
+            fits, upgrade,
+        },
+        objects::transactions::{InitialState, Metadata, TransactionState, TxType},
+        policy::WithdrawalDisputes,
+    };
+
+    fn events() -> Vec<Event> {
+        vec![
+            Event {
+                action: TxType::Deposit,
+                metadata: Metadata::new(1, 1),
+                amount: Some(dec!(10.5)),
+                state: Some(TransactionState::Initial(InitialState::Deposit)),
+                external_id: Some("FITID-1".into()),
+                reason: None,
+            },
+            Event {
+                action: TxType::Dispute,
+                metadata: Metadata::new(1, 1),
+                amount: None,
+                state: Some(TransactionState::Disputed(InitialState::Deposit)),
+                external_id: None,
+                reason: None,
+            },
+        ]
+    }
+
+    fn read_all(path: &std::path::Path) -> Result<Vec<Event>, Error> {
+        EventReader::open(path)?.collect()
+    }
+
+    #[test]
+    fn unlocks_carry_their_reason() {
+        let unlock = Event {
+            action: TxType::Unlock,
+            metadata: Metadata::new(1, 7),
+            amount: None,
+            state: None,
+            external_id: None,
+            reason: Some("chargeback was a bank error".into()),
+        };
+        let mut payload = Vec::new();
+        unlock.encode(&mut payload);
+        assert_eq!(Some(unlock), Event::decode(&payload));
+
+        // Only unlocks leave no transaction state behind
+        let mut deposit = Vec::new();
+        events()[0].encode(&mut deposit);
+        deposit[23] = 5;
+        assert_eq!(None, Event::decode(&deposit));
+        payload[23] = 0;
+        assert_eq!(None, Event::decode(&payload));
+    }
+
+    #[test]
+    fn version_1_logs_are_read_and_upgraded() {
+        let dir = tempfile::tempdir().expect("can create tempdir");
+        let path = dir.path().join("events.log");
+        let mut v1 = MAGIC_V1.to_vec();
+        for event in events() {
+            let mut payload = Vec::new();
+            event.encode(&mut payload);
+            v1.extend((payload.len() as u32).to_le_bytes());
+            v1.extend(&payload);
+            v1.extend(crc32(&payload).to_le_bytes());
+        }
+        std::fs::write(&path, &v1).expect("can write");
+
+        let mut reader = EventReader::open(&path).expect("version 1 is read");
+        assert!(!reader.is_chained());
+        assert_eq!(
+            events(),
+            reader.by_ref().collect::<Result<Vec<_>, _>>().expect("ok")
+        );
+        assert_eq!(2, reader.records_read());
+        assert!(
+            EventLog::open(&path).is_err(),
+            "no unchained records appended"
+        );
+
+        let (count, head) = upgrade(&path).expect("can upgrade");
+        assert_eq!(2, count);
+        let mut reader = EventReader::open(&path).expect("valid log");
+        assert!(reader.is_chained());
+        assert_eq!(events(), read_all(&path).expect("valid log"));
+        reader.by_ref().for_each(drop);
+        assert_eq!(head, reader.head());
+        assert!(upgrade(&path).is_err(), "already chained");
+        let mut log = EventLog::open(&path).expect("appends after the upgrade");
+        log.append(&events()[1]);
+        log.finish().expect("can finish");
+    }
+
+    #[test]
+    fn withdrawal_dispute_policy_is_the_first_record() {
+        let dir = tempfile::tempdir().expect("can create tempdir");
+        let path = dir.path().join("events.log");
+        let mut log = EventLog::open(&path).expect("can open");
+        assert_eq!(None, log.withdrawal_disputes());
+        log.record_withdrawal_disputes(WithdrawalDisputes::CreditOnChargeback);
+        log.record_withdrawal_disputes(WithdrawalDisputes::Hold);
+        log.append(&events()[0]);
+        log.finish().expect("can finish");
+        drop(log);
+        let reader = EventReader::open(&path).expect("valid log");
+        assert_eq!(
+            Some(WithdrawalDisputes::CreditOnChargeback),
+            reader.withdrawal_disputes(),
+            "known before the events are read"
+        );
+        assert_eq!(
+            vec![events()[0].clone()],
+            reader.collect::<Result<Vec<_>, _>>().expect("valid log"),
+            "the policy is no event"
+        );
+        assert_eq!(
+            Some(WithdrawalDisputes::CreditOnChargeback),
+            EventLog::open(&path)
+                .expect("can open")
+                .withdrawal_disputes()
+        );
+
+        // Logs from before the policy was recorded
+        let path = dir.path().join("legacy.log");
+        let mut log = EventLog::open(&path).expect("can open");
+        log.append(&events()[0]);
+        log.finish().expect("can finish");
+        drop(log);
+        let reader = EventReader::open(&path).expect("valid log");
+        assert_eq!(Some(WithdrawalDisputes::Hold), reader.withdrawal_disputes());
+
+        let mut log = EventLog::open(&path).expect("can open");
+        log.record.clear();
+        log.record.extend(log.head);
+        log.record.push(POLICY);
+        log.record.extend(b"hold");
+        log.write_record();
+        log.finish().expect("can finish");
+        drop(log);
+        let err = read_all(&path).expect_err("the policy comes first");
+        assert!(err.to_string().contains("record 2"), "{err}");
+    }
+
+    #[test]
+    fn oversized_records_are_refused() {
+        let dir = tempfile::tempdir().expect("can create tempdir");
+        let path = dir.path().join("events.log");
+        assert!(fits("FITID", &"x".repeat(MAX_TEXT_LEN)).is_ok());
+        assert!(fits("FITID", &"x".repeat(MAX_TEXT_LEN + 1)).is_err());
+
+        let mut log = EventLog::open(&path).expect("can open");
+        log.append(&events()[0]);
+        log.append(&Event {
+            external_id: Some("x".repeat(MAX_LEN as usize)),
+            ..events()[1].clone()
+        });
+        log.append(&events()[1]);
+        assert!(log.finish().is_err(), "the reader would refuse the record");
+        drop(log);
+        assert_eq!(
+            vec![events()[0].clone()],
+            read_all(&path).expect("the log stays readable")
+        );
+        EventLog::open(&path).expect("can append again");
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(0xCBF4_3926, crc32(b"123456789"));
+    }
+
+    #[test]
+    fn log_is_appended_across_opens() {
+        let dir = tempfile::tempdir().expect("can create tempdir");
+        let path = dir.path().join("events.log");
+        for event in events() {
+            let mut log = EventLog::open(&path).expect("can open");
+            log.append(&event);
+            log.finish().expect("can finish");
+        }
+        assert_eq!(events(), read_all(&path).expect("valid log"));
+    }
+
+    #[test]
+    fn torn_tail_is_dropped_and_cut_off() {
+        let dir = tempfile::tempdir().expect("can create tempdir");
+        let path = dir.path().join("events.log");
+        let mut log = EventLog::open(&path).expect("can open");
+        log.append(&events()[0]);
+        log.finish().expect("can finish");
+        let complete = std::fs::metadata(&path).expect("exists").len();
+        // Crash halfway through the second record
+        let mut f = OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .expect("can open");
+        f.write_all(&[60, 0, 0, 0, 2, 1]).expect("can write");
+        drop(f);
+
+        let mut reader = EventReader::open(&path).expect("valid log");
+        assert_eq!(
+            vec![events()[0].clone()],
+            reader.by_ref().collect::<Result<Vec<_>, _>>().expect("ok")
+        );
+        assert!(reader.is_torn());
+
+        let mut log = EventLog::open(&path).expect("can open");
+        assert_eq!(
+            complete,
+            std::fs::metadata(&path).expect("exists").len(),
+            "torn record is cut off"
+        );
+        log.append(&events()[1]);
+        log.finish().expect("can finish");
+        assert_eq!(events(), read_all(&path).expect("valid log"));
+    }
+
+    #[test]
+    fn corrupt_records_are_refused() {
+        let dir = tempfile::tempdir().expect("can create tempdir");
+        let path = dir.path().join("events.log");
+        let mut log = EventLog::open(&path).expect("can open");
+        for event in events() {
+            log.append(&event);
+        }
+        log.finish().expect("can finish");
+        let mut bytes = std::fs::read(&path).expect("can read");
+        // Flip a bit of the first amount
+        bytes[8 + 4 + 32 + 7] ^= 1;
+        std::fs::write(&path, &bytes).expect("can write");
+        assert!(matches!(read_all(&path), Err(Error::InvalidLog(_))));
+        assert!(
+            EventLog::open(&path).is_err(),
+            "no appending to a corrupt log"
+        );
+
+        std::fs::write(&path, "type,client,tx,amount\n").expect("can write");
+        assert!(matches!(read_all(&path), Err(Error::InvalidLog(_))));
+    }
+
+    #[test]
+    fn edited_history_breaks_the_chain() {
+        let dir = tempfile::tempdir().expect("can create tempdir");
+        let path = dir.path().join("events.log");
+        let mut log = EventLog::open(&path).expect("can open");
+        for event in events() {
+            log.append(&event);
+        }
+        log.finish().expect("can finish");
+        let head = log.head();
+        let mut reader = EventReader::open(&path).expect("valid log");
+        reader.by_ref().for_each(drop);
+        assert_eq!((2, head), (reader.records_read(), reader.head()));
+
+        let bytes = std::fs::read(&path).expect("can read");
+        let first_len = u32::from_le_bytes(bytes[8..12].try_into().expect("4 bytes")) as usize;
+        let first = 8..8 + 4 + first_len + 4;
+
+        // Changed amount with a checksum fixed up to match, only the next link notices
+        let mut edited = bytes.clone();
+        edited[8 + 4 + 32 + 7] ^= 1;
+        let crc = crc32(&edited[12..12 + first_len]);
+        edited[12 + first_len..first.end].copy_from_slice(&crc.to_le_bytes());
+        std::fs::write(&path, &edited).expect("can write");
+        let err = EventReader::open(&path)
+            .expect("valid header")
+            .collect::<Result<Vec<_>, _>>()
+            .expect_err("chain is broken");
+        assert!(
+            err.to_string()
+                .contains("record 2 at byte 79: does not link to record 1"),
+            "{err}"
+        );
+
+        // Dropped first record
+        let mut dropped = MAGIC.to_vec();
+        dropped.extend(&bytes[first.end..]);
+        std::fs::write(&path, &dropped).expect("can write");
+        let err = EventReader::open(&path)
+            .expect("valid header")
+            .collect::<Result<Vec<_>, _>>()
+            .expect_err("chain is broken");
+        assert!(
+            err.to_string().contains("does not start the chain"),
+            "{err}"
+        );
+    }
+}
@@ -1,8 +1,26 @@
 use std::{
     fs::{File, OpenOptions},
+    io::Read,
     path::Path,
 };
 
+use rust_decimal::Decimal;
+
+use crate::{
+    error::{Error, Rejection},
+    ledger::{Journal, JournalEntry, LedgerError, OPENING_BALANCES, Posting},
+    objects::{
+        statements::{Balance, Balances, StatementAccounts, StatementLine},
+        transactions::{Row, Transaction},
+    },
+    processor::{Outcome, Processor},
+    quarantine::Quarantine,
+    store::{AccountStore, TransactionStore},
+};
+
+/// Rows between two commits of stores that keep the books beyond the run.
+const COMMIT_EVERY: u64 = 1024;
+
 pub(crate) fn default_csv_ingest(filename: &Path) -> std::io::Result<csv::Reader<File>> {
     let f = OpenOptions::new().read(true).open(filename)?;
     // NOTE(juf): The buffer size can/should be adjusted based on the use-case.
@@ -13,3 +31,180 @@ pub(crate) fn default_csv_ingest(filename: &Path) -> std::io::Result<csv::Reader
         .from_reader(f);
     Ok(reader)
 }
+
+/// Reads every row from `reader` and hands it to the processor. Rows that can not be turned into a
+/// [`Transaction`] abort the run, unless a [`Quarantine`] is given, which then receives them. The
+/// processor commits every [`COMMIT_EVERY`] rows, the rest is left to the caller.
+pub(crate) fn feed_processor<R: Read, A: AccountStore, T: TransactionStore>(
+    reader: &mut csv::Reader<R>,
+    p: &mut Processor<A, T>,
+    quarantine: Option<&mut Quarantine>,
+    mut on_rejection: impl FnMut(Rejection),
+) -> Result<(), Error> {
+    for_each_transaction(reader, quarantine, |row, txn| {
+        if row.is_multiple_of(COMMIT_EVERY) {
+            p.commit()?;
+        }
+        if let Outcome::Rejected(rejection) = p.process_one(txn)? {
+            on_rejection(rejection);
+        }
+        Ok(())
+    })
+}
+
+/// Reads every row from `reader` and hands it to `f` as [`Transaction`] along with its row
+/// number, counting from 1. Rows that can not be turned into one abort the run, unless a
+/// [`Quarantine`] is given, which then receives them.
+pub(crate) fn for_each_transaction<R: Read>(
+    reader: &mut csv::Reader<R>,
+    mut quarantine: Option<&mut Quarantine>,
+    mut f: impl FnMut(u64, Transaction) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let headers = reader.byte_headers()?.clone();
+    let mut record = csv::ByteRecord::new();
+    let mut rows = 0u64;
+    loop {
+        rows += 1;
+        let start = reader.position().clone();
+        let txn = match reader.read_byte_record(&mut record) {
+            Ok(false) => break,
+            Ok(true) => record
+                .deserialize::<Row>(Some(&headers))
+                .map_err(Error::from)
+                .and_then(Transaction::try_from),
+            Err(err) => Err(err.into()),
+        };
+        match (txn, quarantine.as_deref_mut()) {
+            (Ok(txn), _) => f(rows, txn)?,
+            (Err(err), Some(quarantine)) => {
+                // NOTE(juf): On error the record is not populated, fall back to where the reader
+                // stood before, which may include preceding blank lines.
+                let (line, first_byte) = match &err {
+                    Error::FailedDeserialization(csv_err) => csv_err
+                        .position()
+                        .map_or((start.line(), start.byte()), |pos| (pos.line(), pos.byte())),
+                    _ => record
+                        .position()
+                        .map_or((start.line(), start.byte()), |pos| (pos.line(), pos.byte())),
+                };
+                quarantine.quarantine(line, first_byte..reader.position().byte(), &err)?;
+            }
+            (Err(err), None) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+/// Hands every booking of a statement to the processor as transaction of `client`, committed as
+/// one batch. With `balances`, the account total is reconciled with the ones the statement
+/// reports first, a statement that does not reconcile is not committed at all.
+pub(crate) fn feed_statement_processor<A: AccountStore, T: TransactionStore>(
+    lines: impl Iterator<Item = Result<(u64, StatementLine), Error>>,
+    balances: Option<&Balances>,
+    client: u16,
+    p: &mut Processor<A, T>,
+    mut on_rejection: impl FnMut(Rejection),
+) -> Result<(), Error> {
+    let total = |p: &Processor<A, T>| {
+        p.get_account_store_ref()
+            .get(client)
+            .map_or(Decimal::ZERO, |acc| acc.total())
+    };
+    // NOTE(juf): The first statement of a fresh account starts at its opening balance, deposit it
+    // so the following bookings can be reconciled. Negative opening balances can not be deposited
+    // and are left for the reconciliation to report.
+    if let Some(Balances {
+        statement,
+        opening: Some(opening),
+        ..
+    }) = balances
+        && total(p).is_zero()
+        && opening.amount.is_sign_positive()
+        && !opening.amount.is_zero()
+        && let Outcome::Rejected(rejection) =
+            p.process_statement_line(client, opening_line(statement, opening))?
+    {
+        on_rejection(rejection);
+    }
+    let before = total(p);
+    for line in lines {
+        let (_, statement) = line?;
+        if let Outcome::Rejected(rejection) = p.process_statement_line(client, statement)? {
+            on_rejection(rejection);
+        }
+    }
+    if let Some(balances) = balances {
+        balances.reconcile(before, total(p))?;
+    }
+    p.commit()
+}
+
+/// Books every line of a statement into the journal. The line number of the booking doubles as tx
+/// id. Entries the journal refuses, e.g., re-imported ones, are reported and skipped. With
+/// `balances`, the bank account is reconciled with the ones the statement reports.
+pub(crate) fn post_statement(
+    lines: impl Iterator<Item = Result<(u64, StatementLine), Error>>,
+    balances: Option<&Balances>,
+    accounts: &StatementAccounts,
+    journal: &mut Journal,
+    mut on_refusal: impl FnMut(u64, LedgerError),
+) -> Result<(), Error> {
+    // NOTE(juf): Same as for clients, the first statement of an account brings its opening
+    // balance, which is booked against equity. Line 0 marks it when reporting a refusal.
+    if let Some(Balances {
+        statement,
+        opening: Some(opening),
+        ..
+    }) = balances
+        && journal.balance(&accounts.account).is_zero()
+        && !opening.amount.is_zero()
+    {
+        let line = opening_line(statement, opening);
+        let entry = JournalEntry::new(
+            0,
+            line.action(),
+            vec![
+                Posting::new(&accounts.account, line.amount),
+                Posting::new(OPENING_BALANCES, -line.amount),
+            ],
+        )
+        .expect("two postings of the same amount with opposite signs are balanced")
+        .describe(Some(line.date), None, line.memo)
+        .with_external_id(line.external_id);
+        if let Err(err) = journal.post(entry) {
+            on_refusal(0, err);
+        }
+    }
+    let before = journal.balance(&accounts.account);
+    for line in lines {
+        let (line, statement) = line?;
+        let tx_id = u32::try_from(line)
+            .map_err(|_| Error::InvalidRow(format!("line {line} exceeds the tx id space")))?;
+        let posted = statement
+            .into_entry(tx_id, accounts)
+            .and_then(|entry| journal.post(entry));
+        if let Err(err) = posted {
+            on_refusal(line, err);
+        }
+    }
+    match balances {
+        Some(balances) => balances.reconcile(before, journal.balance(&accounts.account)),
+        None => Ok(()),
+    }
+}
+
+/// Booking of the opening balance, its external id makes sure it is only booked once.
+fn opening_line(statement: &str, opening: &Balance) -> StatementLine {
+    StatementLine {
+        date: opening.date,
+        amount: opening.amount,
+        payee: None,
+        memo: Some("Opening balance".into()),
+        external_id: Some(format!("{statement}:opening")),
+        value_date: None,
+        counterparty_account: None,
+        reference: None,
+        cleared: None,
+        splits: Vec::new(),
+    }
+}
@@ -0,0 +1,512 @@
+use std::{collections::HashMap, fmt};
+
+use rust_decimal::Decimal;
+use thiserror::Error;
+
+use crate::{
+    error::Error,
+    objects::{
+        accounts::Account,
+        transactions::{InitialState, TransactionState, TxType},
+    },
+    policy::{LockedAccounts, WithdrawalDisputes},
+    store::{AccountStore, TransactionStore},
+};
+
+// NOTE(juf): What has to hold for every account, no matter the input:
+//   1. held is never negative
+//   2. total is available + held, it is derived, so all that can go wrong is that it does not fit
+//   3. held is the sum of the transactions of the client under dispute, without withdrawals if
+//      they do not hold their amount, see `WithdrawalDisputes`
+//   4. locked accounts saw exactly one chargeback since they were last unlocked, the others none.
+//      More than one only if the `LockedAccounts` policy lets disputes of locked accounts through
+// The processor keeps what the stores do not know, the chargebacks per client. In paranoid mode
+// it also keeps the amounts under dispute per client and checks the account after every applied
+// transaction, a broken invariant stops the run right at the transaction that broke it. `check` goes
+// through the whole transaction store instead and can be run at any time.
+
+/// An invariant an account breaks.
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub(crate) enum Violation {
+    #[error("client {client}: held {held} is negative")]
+    NegativeHeld { client: u16, held: Decimal },
+    #[error("client {client}: total of available {available} and held {held} overflows")]
+    TotalOverflow {
+        client: u16,
+        available: Decimal,
+        held: Decimal,
+    },
+    #[error("client {client}: held {held}, but {disputed} is under dispute")]
+    HeldNotDisputed {
+        client: u16,
+        held: Decimal,
+        disputed: Decimal,
+    },
+    #[error("client {client}: locked after {chargebacks} chargebacks instead of one")]
+    Locked { client: u16, chargebacks: u32 },
+    #[error("client {client}: not locked after {chargebacks} chargebacks")]
+    NotLocked { client: u16, chargebacks: u32 },
+}
+
+/// Outcome of an invariant check.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct Report {
+    /// Transaction that broke the invariants, paranoid mode only.
+    pub(crate) after: Option<(TxType, u32)>,
+    pub(crate) accounts: usize,
+    pub(crate) transactions: usize,
+    pub(crate) violations: Vec<Violation>,
+}
+
+impl Report {
+    pub(crate) fn is_ok(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some((action, tx_id)) = self.after {
+            write!(f, "after {} of tx {tx_id}, ", action.name())?;
+        }
+        write!(
+            f,
+            "checked {} accounts and {} transactions, {} violations",
+            self.accounts,
+            self.transactions,
+            self.violations.len()
+        )?;
+        for violation in &self.violations {
+            write!(f, "\n  {violation}")?;
+        }
+        Ok(())
+    }
+}
+
+/// What the invariants are checked against besides the stores, kept up to date by the processor.
+#[derive(Debug, Default)]
+pub(crate) struct Invariants {
+    /// Check the account after every applied transaction.
+    paranoid: bool,
+    /// Chargebacks per client since the account was last unlocked, accounts carried over locked
+    /// count as charged back once.
+    chargebacks: HashMap<u16, u32>,
+    /// Amounts under dispute per client, paranoid mode only. Taken from the transaction store on
+    /// the first transaction.
+    disputed: Option<HashMap<u16, Decimal>>,
+    withdrawal_disputes: WithdrawalDisputes,
+    locked_accounts: LockedAccounts,
+}
+
+impl Invariants {
+    /// Additionally check the account after every applied transaction.
+    pub(crate) fn paranoid(self) -> Self {
+        Self {
+            paranoid: true,
+            ..self
+        }
+    }
+
+    /// Disputed withdrawals hold their amount if `policy` says so.
+    pub(crate) fn withdrawal_disputes(&mut self, policy: WithdrawalDisputes) {
+        self.withdrawal_disputes = policy;
+    }
+
+    /// Locked accounts take further chargebacks if `policy` says so.
+    pub(crate) fn locked_accounts(&mut self, policy: LockedAccounts) {
+        self.locked_accounts = policy;
+    }
+
+    /// Takes note of an account loaded from a previous run. It can only have been locked by a
+    /// chargeback.
+    pub(crate) fn carry_over(&mut self, account: &Account) {
+        if account.locked {
+            self.chargebacks.insert(account.id, 1);
+        }
+    }
+
+    /// Takes over the chargebacks seen by another processor and its policies, see
+    /// [`Processor::merge`](crate::processor::Processor::merge).
+    pub(crate) fn absorb(&mut self, other: Invariants) {
+        self.withdrawal_disputes = other.withdrawal_disputes;
+        self.locked_accounts = other.locked_accounts;
+        for (client, chargebacks) in other.chargebacks {
+            *self.chargebacks.entry(client).or_default() += chargebacks;
+        }
+    }
+
+    /// Takes note of a transaction applied to the account of `client`, what was done, the kind
+    /// of transaction moved and the amount. In paranoid mode returns what the account breaks now.
+    pub(crate) fn applied(
+        &mut self,
+        client: u16,
+        (action, origin, amount): (TxType, InitialState, Decimal),
+        accounts: &impl AccountStore,
+        txns: &impl TransactionStore,
+    ) -> Result<Vec<Violation>, Error> {
+        if action == TxType::Chargeback {
+            *self.chargebacks.entry(client).or_default() += 1;
+        }
+        if !self.paranoid {
+            return Ok(Vec::new());
+        }
+        let change = match action {
+            _ if !self.holds(origin) => Decimal::ZERO,
+            TxType::Dispute => amount,
+            TxType::Resolve | TxType::Chargeback => -amount,
+            TxType::Deposit | TxType::Withdrawal | TxType::Unlock => Decimal::ZERO,
+        };
+        self.check_after(client, change, accounts, txns)
+    }
+
+    /// Takes note of the account of `client` being unlocked. In paranoid mode returns what the
+    /// account breaks now.
+    pub(crate) fn unlocked(
+        &mut self,
+        client: u16,
+        accounts: &impl AccountStore,
+        txns: &impl TransactionStore,
+    ) -> Result<Vec<Violation>, Error> {
+        self.chargebacks.remove(&client);
+        if !self.paranoid {
+            return Ok(Vec::new());
+        }
+        self.check_after(client, Decimal::ZERO, accounts, txns)
+    }
+
+    /// Checks the account of `client` after a transaction changed the amount under dispute by
+    /// `change`.
+    fn check_after(
+        &mut self,
+        client: u16,
+        change: Decimal,
+        accounts: &impl AccountStore,
+        txns: &impl TransactionStore,
+    ) -> Result<Vec<Violation>, Error> {
+        let account = accounts
+            .get(client)
+            .expect("transactions are applied to an account");
+        let disputed = match self.disputed.as_mut() {
+            Some(disputed) => {
+                let sum = disputed.entry(client).or_default();
+                *sum += change;
+                *sum
+            }
+            // Already includes the transaction just applied
+            None => {
+                let (disputed, _) = self.disputed_by_client(txns)?;
+                self.disputed
+                    .insert(disputed)
+                    .get(&client)
+                    .copied()
+                    .unwrap_or_default()
+            }
+        };
+        let mut violations = Vec::new();
+        self.check_account(account, disputed, &mut violations);
+        Ok(violations)
+    }
+
+    /// Checks every account against the transaction store.
+    pub(crate) fn check(
+        &self,
+        accounts: &impl AccountStore,
+        txns: &impl TransactionStore,
+    ) -> Result<Report, Error> {
+        let (mut disputed, transactions) = self.disputed_by_client(txns)?;
+        let mut report = Report {
+            transactions,
+            ..Report::default()
+        };
+        let mut sorted: Vec<&Account> = accounts.accounts().collect();
+        sorted.sort_by_key(|account| account.id);
+        for account in sorted {
+            report.accounts += 1;
+            let sum = disputed.remove(&account.id).unwrap_or_default();
+            self.check_account(account, sum, &mut report.violations);
+        }
+        // Disputes of clients without an account
+        let mut orphaned: Vec<_> = disputed.into_iter().collect();
+        orphaned.sort();
+        for (client, disputed) in orphaned {
+            self.check_account(&Account::new(client), disputed, &mut report.violations);
+        }
+        Ok(report)
+    }
+
+    /// Whether transactions of the kind hold their amount while under dispute.
+    fn holds(&self, origin: InitialState) -> bool {
+        origin == InitialState::Deposit || self.withdrawal_disputes.holds()
+    }
+
+    /// Sum of the amounts held for disputes per client and the number of transactions in the
+    /// store.
+    fn disputed_by_client(
+        &self,
+        txns: &impl TransactionStore,
+    ) -> Result<(HashMap<u16, Decimal>, usize), Error> {
+        let mut disputed: HashMap<u16, Decimal> = HashMap::new();
+        let mut count = 0;
+        for txn in txns.transactions() {
+            let (metadata, amount, state) = txn?;
+            count += 1;
+            if let TransactionState::Disputed(origin) = state
+                && self.holds(origin)
+            {
+                *disputed.entry(metadata.client).or_default() += Decimal::from(amount);
+            }
+        }
+        Ok((disputed, count))
+    }
+
+    fn check_account(&self, account: &Account, disputed: Decimal, violations: &mut Vec<Violation>) {
+        let client = account.id;
+        if account.held < Decimal::ZERO {
+            violations.push(Violation::NegativeHeld {
+                client,
+                held: account.held,
+            });
+        }
+        if account.checked_total().is_err() {
+            violations.push(Violation::TotalOverflow {
+                client,
+                available: account.available,
+                held: account.held,
+            });
+        }
+        if account.held != disputed {
+            violations.push(Violation::HeldNotDisputed {
+                client,
+                held: account.held,
+                disputed,
+            });
+        }
+        let chargebacks = self.chargebacks.get(&client).copied().unwrap_or_default();
+        let further_chargebacks = match self.locked_accounts {
+            LockedAccounts::BlockAll => false,
+            LockedAccounts::BlockWithdrawals | LockedAccounts::AllowDisputes => true,
+        };
+        match (account.locked, chargebacks) {
+            (true, 1) | (false, 0) => {}
+            (true, 2..) if further_chargebacks => {}
+            (true, chargebacks) => violations.push(Violation::Locked {
+                client,
+                chargebacks,
+            }),
+            (false, chargebacks) => violations.push(Violation::NotLocked {
+                client,
+                chargebacks,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use rust_decimal::dec;
+
+    use crate::{
+        error::Error,
+        ingest::{default_csv_ingest, feed_processor},
+        invariants::{Invariants, Report, Violation},
+        objects::{
+            accounts::Account,
+            transactions::{InitialState, Metadata, Transaction, TransactionState, TxType},
+        },
+        policy::LockedAccounts,
+        processor::{Outcome, Processor},
+        shard::tests::mixed_input,
+        state::{self, Record},
+        store::{AccountStore, CachedTransaction},
+    };
+
+    #[test]
+    fn hold_for_any_input() {
+        let dir = tempfile::tempdir().expect("can create tempdir");
+        let path = dir.path().join("in.csv");
+        std::fs::write(&path, mixed_input(20_000, 200, 11, true)).expect("can write input");
+        let mut p = Processor::new().with_paranoid_checks();
+        let mut ingest = default_csv_ingest(&path).expect("can open input");
+        feed_processor(&mut ingest, &mut p, None, |_| {}).expect("can process");
+        let report = p.check_invariants().expect("can check");
+        assert!(report.is_ok(), "{report}");
+        assert_eq!(200, report.accounts);
+        assert!(
+            p.get_account_store_ref()
+                .accounts()
+                .any(|acc| acc.locked && acc.held > dec!(0))
+        );
+    }
+
+    #[test]
+    fn broken_books_are_reported() {
+        let dir = tempfile::tempdir().expect("can create tempdir");
+        let path = dir.path().join("state");
+        let records = [
+            Record::Account(Account {
+                id: 1,
+                locked: false,
+                available: dec!(5),
+                held: dec!(-1),
+            }),
+            Record::Account(Account {
+                id: 2,
+                locked: true,
+                available: dec!(0),
+                held: dec!(3),
+            }),
+            Record::Txn(
+                Metadata::new(2, 1),
+                dec!(2),
+                TransactionState::Disputed(InitialState::Deposit),
+            ),
+            Record::Txn(
+                Metadata::new(3, 2),
+                dec!(4),
+                TransactionState::Disputed(InitialState::Withdrawal),
+            ),
+        ];
+        state::write(&path, records.into_iter().map(Ok)).expect("can write state");
+        let mut p = Processor::load(&path).expect("can load");
+        assert_eq!(
+            Outcome::Applied,
+            p.process_one(Transaction::Deposit(Metadata::new(4, 3), dec!(1)))
+                .expect("can process")
+        );
+        assert_eq!(
+            Report {
+                after: None,
+                accounts: 3,
+                transactions: 3,
+                violations: vec![
+                    Violation::NegativeHeld {
+                        client: 1,
+                        held: dec!(-1)
+                    },
+                    Violation::HeldNotDisputed {
+                        client: 1,
+                        held: dec!(-1),
+                        disputed: dec!(0)
+                    },
+                    Violation::HeldNotDisputed {
+                        client: 2,
+                        held: dec!(3),
+                        disputed: dec!(2)
+                    },
+                    Violation::HeldNotDisputed {
+                        client: 3,
+                        held: dec!(0),
+                        disputed: dec!(4)
+                    },
+                ],
+            },
+            p.check_invariants().expect("can check")
+        );
+    }
+
+    #[test]
+    fn locks_have_to_match_chargebacks() {
+        let mut p = Processor::new().with_paranoid_checks();
+        for txn in [
+            Transaction::Deposit(Metadata::new(1, 1), dec!(5)),
+            Transaction::Dispute(Metadata::new(1, 1)),
+            Transaction::Chargeback(Metadata::new(1, 1)),
+        ] {
+            assert_eq!(Outcome::Applied, p.process_one(txn).expect("can process"));
+        }
+        assert!(p.check_invariants().expect("can check").is_ok());
+
+        let locked = Account {
+            locked: true,
+            ..Account::new(1)
+        };
+        let accounts = HashMap::from([(1, locked.clone())]);
+        let txns: HashMap<u32, CachedTransaction> = HashMap::new();
+        let mut invariants = Invariants::default();
+        assert_eq!(
+            vec![Violation::Locked {
+                client: 1,
+                chargebacks: 0
+            }],
+            invariants
+                .check(&accounts, &txns)
+                .expect("can check")
+                .violations
+        );
+        invariants.carry_over(&locked);
+        assert!(
+            invariants
+                .check(&accounts, &txns)
+                .expect("can check")
+                .is_ok()
+        );
+        invariants
+            .applied(
+                1,
+                (TxType::Chargeback, InitialState::Deposit, dec!(0)),
+                &accounts,
+                &txns,
+            )
+            .expect("can apply");
+        assert_eq!(
+            vec![Violation::Locked {
+                client: 1,
+                chargebacks: 2
+            }],
+            invariants
+                .check(&accounts, &txns)
+                .expect("can check")
+                .violations
+        );
+        invariants.locked_accounts(LockedAccounts::AllowDisputes);
+        assert!(
+            invariants
+                .check(&accounts, &txns)
+                .expect("can check")
+                .is_ok()
+        );
+        let mut invariants = Invariants::default().paranoid();
+        assert_eq!(
+            Ok(vec![Violation::NotLocked {
+                client: 2,
+                chargebacks: 1
+            }]),
+            invariants
+                .applied(
+                    2,
+                    (TxType::Chargeback, InitialState::Deposit, dec!(0)),
+                    &HashMap::from([(2, Account::new(2))]),
+                    &txns
+                )
+                .map_err(|err| err.to_string())
+        );
+    }
+
+    #[test]
+    fn paranoid_mode_stops_at_the_first_violation() {
+        let dir = tempfile::tempdir().expect("can create tempdir");
+        let path = dir.path().join("state");
+        let broken = Account {
+            id: 1,
+            locked: false,
+            available: dec!(0),
+            held: dec!(1),
+        };
+        state::write(&path, [Ok(Record::Account(broken))].into_iter()).expect("can write state");
+        let mut p = Processor::load(&path)
+            .expect("can load")
+            .with_paranoid_checks();
+        let Err(Error::BrokenInvariants(report)) =
+            p.process_one(Transaction::Deposit(Metadata::new(1, 1), dec!(1)))
+        else {
+            panic!("the held amount is not under dispute");
+        };
+        assert_eq!(
+            "after deposit of tx 1, checked 1 accounts and 1 transactions, 1 violations\n  \
+             client 1: held 1, but 0 is under dispute",
+            report.to_string()
+        );
+    }
+}
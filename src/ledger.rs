@@ -0,0 +1,518 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    amount::Precision,
+    chart::{ChartOfAccounts, ancestors_and_self},
+    date::Date,
+    objects::transactions::{InitialState, TxType},
+    policy::WithdrawalDisputes,
+};
+
+// NOTE(juf): Sign convention, debits are positive and credits are negative. A journal entry is
+// balanced when the amounts of all its postings sum up to zero.
+
+pub(crate) const BANK: &str = "Assets:Bank";
+pub(crate) const DISPUTE_RECEIVABLE: &str = "Assets:Receivable:Disputes";
+pub(crate) const OPENING_BALANCES: &str = "Equity:Opening-Balances";
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub(crate) enum LedgerError {
+    #[error("journal entry for tx {tx_id} requires at least two postings, got {count}")]
+    TooFewPostings { tx_id: u32, count: usize },
+    #[error("journal entry for tx {tx_id} is unbalanced, postings sum up to {sum}")]
+    Unbalanced { tx_id: u32, sum: Decimal },
+    #[error("unknown account type of ledger account {0}")]
+    UnknownAccountType(String),
+    #[error("invalid ledger account name {0:?}")]
+    InvalidAccountName(String),
+    #[error("ledger account {account} declared as {declared:?} but is {expected:?}")]
+    AccountTypeMismatch {
+        account: String,
+        declared: AccountType,
+        expected: AccountType,
+    },
+    #[error("ledger account {0} is not declared in the chart of accounts")]
+    UndeclaredAccount(String),
+    #[error("booking with external id {0} was already recorded")]
+    DuplicateExternalId(String),
+}
+
+/// Top-level classification of a ledger account, derived from the first segment of its name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum AccountType {
+    Assets,
+    Liabilities,
+    Income,
+    Expenses,
+    Equity,
+}
+
+impl AccountType {
+    pub(crate) fn of(name: &str) -> Result<Self, LedgerError> {
+        match name.split(':').next() {
+            Some("Assets") => Ok(AccountType::Assets),
+            Some("Liabilities") => Ok(AccountType::Liabilities),
+            Some("Income") => Ok(AccountType::Income),
+            Some("Expenses") => Ok(AccountType::Expenses),
+            Some("Equity") => Ok(AccountType::Equity),
+            _ => Err(LedgerError::UnknownAccountType(name.into())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Posting {
+    pub(crate) account: String,
+    pub(crate) amount: Decimal,
+    /// Details of this posting alone, e.g., the memo of a split.
+    meta: Vec<(String, String)>,
+}
+
+impl Posting {
+    pub(crate) fn new(account: impl Into<String>, amount: Decimal) -> Self {
+        Self {
+            account: account.into(),
+            amount,
+            meta: Vec::new(),
+        }
+    }
+
+    /// Attaches the detail under `key`, if there is one.
+    pub(crate) fn with_meta(mut self, key: &str, value: Option<String>) -> Self {
+        if let Some(value) = value {
+            self.meta.push((key.to_string(), value));
+        }
+        self
+    }
+
+    pub(crate) fn meta(&self, key: &str) -> Option<&str> {
+        self.meta
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// JournalEntry is a set of at least two postings which sum up to zero. It can only be constructed
+/// in a balanced state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct JournalEntry {
+    tx_id: u32,
+    action: TxType,
+    postings: Vec<Posting>,
+    date: Option<Date>,
+    payee: Option<String>,
+    narration: Option<String>,
+    external_id: Option<String>,
+    /// Further details of the booking, e.g., the counterparty account of a bank statement line.
+    meta: Vec<(String, String)>,
+}
+
+impl JournalEntry {
+    pub(crate) fn new(
+        tx_id: u32,
+        action: TxType,
+        postings: Vec<Posting>,
+    ) -> Result<Self, LedgerError> {
+        if postings.len() < 2 {
+            return Err(LedgerError::TooFewPostings {
+                tx_id,
+                count: postings.len(),
+            });
+        }
+        for posting in postings.iter() {
+            AccountType::of(&posting.account)?;
+        }
+        let sum: Decimal = postings.iter().map(|p| p.amount).sum();
+        if !sum.is_zero() {
+            return Err(LedgerError::Unbalanced { tx_id, sum });
+        }
+        Ok(Self {
+            tx_id,
+            action,
+            postings,
+            date: None,
+            payee: None,
+            narration: None,
+            external_id: None,
+            meta: Vec::new(),
+        })
+    }
+
+    /// Attaches the descriptive details bank statements come with.
+    pub(crate) fn describe(
+        mut self,
+        date: Option<Date>,
+        payee: Option<String>,
+        narration: Option<String>,
+    ) -> Self {
+        self.date = date;
+        self.payee = payee;
+        self.narration = narration;
+        self
+    }
+
+    /// Id the bank assigned to the booking, an entry with the same id is only recorded once.
+    pub(crate) fn with_external_id(mut self, external_id: Option<String>) -> Self {
+        self.external_id = external_id;
+        self
+    }
+
+    /// Attaches the detail under `key`, if there is one.
+    pub(crate) fn with_meta(mut self, key: &str, value: Option<String>) -> Self {
+        if let Some(value) = value {
+            self.meta.push((key.to_string(), value));
+        }
+        self
+    }
+
+    pub(crate) fn meta(&self, key: &str) -> Option<&str> {
+        self.meta
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Maps an applied transaction onto its postings. `origin` is the kind of the transaction that
+    /// is moved, i.e., for disputes, resolves and chargebacks the kind of the referenced one.
+    /// `None` if nothing moves, unlocks and disputes of withdrawals under
+    /// [`WithdrawalDisputes::CreditOnChargeback`].
+    pub(crate) fn for_transaction(
+        client: u16,
+        tx_id: u32,
+        action: TxType,
+        origin: InitialState,
+        amount: Decimal,
+        withdrawal_disputes: WithdrawalDisputes,
+    ) -> Option<Self> {
+        let available = client_available(client);
+        let held = client_held(client);
+        let postings = match (action, origin) {
+            (TxType::Dispute | TxType::Resolve, InitialState::Withdrawal)
+                if !withdrawal_disputes.holds() =>
+            {
+                return None;
+            }
+            (TxType::Unlock, _) => return None,
+            // The withdrawal is undone, as if the amount was deposited again
+            (TxType::Chargeback, InitialState::Withdrawal) if !withdrawal_disputes.holds() => {
+                vec![Posting::new(BANK, amount), Posting::new(available, -amount)]
+            }
+            (TxType::Deposit, _) => {
+                vec![Posting::new(BANK, amount), Posting::new(available, -amount)]
+            }
+            (TxType::Withdrawal, _) => {
+                vec![Posting::new(available, amount), Posting::new(BANK, -amount)]
+            }
+            (TxType::Dispute, InitialState::Deposit) => {
+                vec![Posting::new(available, amount), Posting::new(held, -amount)]
+            }
+            (TxType::Resolve, InitialState::Deposit) => {
+                vec![Posting::new(held, amount), Posting::new(available, -amount)]
+            }
+            (TxType::Chargeback, InitialState::Deposit) => {
+                vec![Posting::new(held, amount), Posting::new(BANK, -amount)]
+            }
+            // NOTE(juf): A disputed withdrawal holds funds without touching available, we owe the
+            // client the amount while we (hopefully) get it back from the counterparty.
+            (TxType::Dispute, InitialState::Withdrawal) => vec![
+                Posting::new(DISPUTE_RECEIVABLE, amount),
+                Posting::new(held, -amount),
+            ],
+            (TxType::Resolve, InitialState::Withdrawal) => vec![
+                Posting::new(held, amount),
+                Posting::new(DISPUTE_RECEIVABLE, -amount),
+            ],
+            (TxType::Chargeback, InitialState::Withdrawal) => vec![
+                Posting::new(held, amount),
+                Posting::new(available, -amount),
+                Posting::new(BANK, amount),
+                Posting::new(DISPUTE_RECEIVABLE, -amount),
+            ],
+        };
+        let entry = Self::new(tx_id, action, postings)
+            .expect("mapping of transactions onto postings is always balanced");
+        Some(entry)
+    }
+
+    pub(crate) fn postings(&self) -> &[Posting] {
+        &self.postings
+    }
+
+    pub(crate) fn tx_id(&self) -> u32 {
+        self.tx_id
+    }
+
+    pub(crate) fn action(&self) -> TxType {
+        self.action
+    }
+
+    pub(crate) fn external_id(&self) -> Option<&str> {
+        self.external_id.as_deref()
+    }
+
+    /// All details attached with [`JournalEntry::with_meta`], in the order they were attached.
+    pub(crate) fn metadata(&self) -> &[(String, String)] {
+        &self.meta
+    }
+
+    pub(crate) fn date(&self) -> Option<Date> {
+        self.date
+    }
+
+    pub(crate) fn payee(&self) -> Option<&str> {
+        self.payee.as_deref()
+    }
+
+    pub(crate) fn narration(&self) -> Option<&str> {
+        self.narration.as_deref()
+    }
+}
+
+pub(crate) fn client_available(client: u16) -> String {
+    format!("Liabilities:Clients:{client}:Available")
+}
+
+pub(crate) fn client_held(client: u16) -> String {
+    format!("Liabilities:Clients:{client}:Held")
+}
+
+/// Every ledger account a transaction of `client` may post to.
+pub(crate) fn client_accounts(client: u16) -> [String; 4] {
+    [
+        BANK.into(),
+        DISPUTE_RECEIVABLE.into(),
+        client_available(client),
+        client_held(client),
+    ]
+}
+
+/// A single posting flattened for egress, one row per posting.
+#[derive(Debug, Serialize)]
+pub(crate) struct PostingRow<'a> {
+    tx: u32,
+    date: Option<Date>,
+    r#type: TxType,
+    account: &'a str,
+    amount: Decimal,
+    payee: Option<&'a str>,
+    narration: Option<&'a str>,
+}
+
+/// Rolled up balance of a ledger account node, including all of its children.
+#[derive(Debug, Serialize)]
+pub(crate) struct BalanceRow<'a> {
+    account: &'a str,
+    r#type: AccountType,
+    balance: Decimal,
+}
+
+/// Journal is the append-only list of journal entries together with the running balance of every
+/// ledger account touched.
+#[derive(Debug, Default)]
+pub(crate) struct Journal {
+    entries: Vec<JournalEntry>,
+    balances: HashMap<String, Decimal>,
+    chart: Option<ChartOfAccounts>,
+    external_ids: HashSet<String>,
+}
+
+impl Journal {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn with_chart(chart: ChartOfAccounts) -> Self {
+        Self {
+            chart: Some(chart),
+            ..Default::default()
+        }
+    }
+
+    /// Checks the accounts against the chart of accounts, if any.
+    pub(crate) fn check_accounts<'a>(
+        &self,
+        mut accounts: impl Iterator<Item = &'a str>,
+    ) -> Result<(), LedgerError> {
+        match &self.chart {
+            Some(chart) => accounts.try_for_each(|account| chart.check(account)),
+            None => Ok(()),
+        }
+    }
+
+    /// Records the entry, unless one of its postings targets an account the chart of accounts
+    /// refuses or its external id was already recorded. In that case nothing is recorded.
+    pub(crate) fn post(&mut self, entry: JournalEntry) -> Result<(), LedgerError> {
+        self.check_accounts(entry.postings.iter().map(|p| p.account.as_str()))?;
+        if let Some(external_id) = &entry.external_id
+            && !self.external_ids.insert(external_id.clone())
+        {
+            return Err(LedgerError::DuplicateExternalId(external_id.clone()));
+        }
+        for posting in entry.postings.iter() {
+            *self
+                .balances
+                .entry(posting.account.clone())
+                .or_insert(Decimal::ZERO) += posting.amount;
+        }
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    pub(crate) fn entries(&self) -> &[JournalEntry] {
+        &self.entries
+    }
+
+    pub(crate) fn balance(&self, account: &str) -> Decimal {
+        self.balances.get(account).copied().unwrap_or_default()
+    }
+
+    /// Balance of the account including all of its children.
+    pub(crate) fn rolled_up_balance(&self, account: &str) -> Decimal {
+        self.balances
+            .iter()
+            .filter(|(name, _)| {
+                name.strip_prefix(account)
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with(':'))
+            })
+            .map(|(_, balance)| *balance)
+            .sum()
+    }
+
+    /// Balances of every account node, where parents include the balances of their children.
+    pub(crate) fn rolled_up_balances(&self) -> BTreeMap<String, Decimal> {
+        let mut rolled_up = BTreeMap::new();
+        for (account, balance) in self.balances.iter() {
+            for node in ancestors_and_self(account) {
+                *rolled_up.entry(node.to_string()).or_insert(Decimal::ZERO) += *balance;
+            }
+        }
+        rolled_up
+    }
+
+    /// Rows of the rolled up balances, rounded to `precision`.
+    pub(crate) fn balance_rows(
+        rolled_up: &BTreeMap<String, Decimal>,
+        precision: Precision,
+    ) -> impl Iterator<Item = BalanceRow<'_>> {
+        rolled_up.iter().filter_map(move |(account, balance)| {
+            Some(BalanceRow {
+                account,
+                // Every posted account has passed `AccountType::of`
+                r#type: AccountType::of(account).ok()?,
+                balance: precision.round(*balance),
+            })
+        })
+    }
+
+    /// One row per posting, amounts rounded to `precision`.
+    pub(crate) fn rows(&self, precision: Precision) -> impl Iterator<Item = PostingRow<'_>> {
+        self.entries.iter().flat_map(move |entry| {
+            entry.postings().iter().map(move |posting| PostingRow {
+                tx: entry.tx_id,
+                date: entry.date,
+                r#type: entry.action,
+                account: &posting.account,
+                amount: precision.round(posting.amount),
+                payee: entry.payee.as_deref(),
+                narration: entry.narration.as_deref(),
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use rust_decimal::{Decimal, dec};
+
+    use crate::{
+        ingest::{default_csv_ingest, feed_processor},
+        ledger::{
+            BANK, DISPUTE_RECEIVABLE, Journal, JournalEntry, LedgerError, Posting,
+            client_available, client_held,
+        },
+        objects::transactions::TxType,
+        processor::Processor,
+    };
+
+    #[test]
+    fn unbalanced_entries_are_refused() {
+        assert_eq!(
+            Err(LedgerError::Unbalanced {
+                tx_id: 1,
+                sum: dec!(0.5)
+            }),
+            JournalEntry::new(
+                1,
+                TxType::Deposit,
+                vec![
+                    Posting::new(BANK, dec!(1.0)),
+                    Posting::new("Equity:Opening", dec!(-0.5))
+                ]
+            )
+        );
+        assert_eq!(
+            Err(LedgerError::TooFewPostings { tx_id: 1, count: 1 }),
+            JournalEntry::new(1, TxType::Deposit, vec![Posting::new(BANK, dec!(0))])
+        );
+        assert_eq!(
+            Err(LedgerError::UnknownAccountType("Bank".into())),
+            JournalEntry::new(
+                1,
+                TxType::Deposit,
+                vec![
+                    Posting::new("Bank", dec!(1.0)),
+                    Posting::new("Equity:Opening", dec!(-1.0))
+                ]
+            )
+        );
+        assert!(
+            JournalEntry::new(
+                1,
+                TxType::Deposit,
+                vec![
+                    Posting::new(BANK, dec!(1.0)),
+                    Posting::new("Equity:Opening", dec!(-1.0))
+                ]
+            )
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn journal_mirrors_account_balances() {
+        let mut ingest =
+            default_csv_ingest(Path::new("tests/sample-3-accounts-all-types-simple-1.csv"))
+                .expect("Can open file and create ingest");
+        let mut p = Processor::new().with_journal(Journal::new());
+        feed_processor(&mut ingest, &mut p, None, |_| {}).expect("Should be valid input");
+        let journal = p.journal().expect("journal should be enabled");
+        assert!(!journal.entries().is_empty());
+        for entry in journal.entries() {
+            assert_eq!(
+                Decimal::ZERO,
+                entry.postings().iter().map(|p| p.amount).sum::<Decimal>(),
+                "every entry must be balanced"
+            );
+        }
+        let mut client_total = Decimal::ZERO;
+        for (id, acc) in p.get_account_store_ref() {
+            // Client balances are liabilities, i.e., credits, hence negative.
+            assert_eq!(acc.available, -journal.balance(&client_available(*id)));
+            assert_eq!(acc.held, -journal.balance(&client_held(*id)));
+            client_total += acc.total();
+        }
+        assert_eq!(
+            client_total,
+            journal.balance(BANK) + journal.balance(DISPUTE_RECEIVABLE),
+            "assets must match what is owed to clients"
+        );
+    }
+}
@@ -1,39 +1,546 @@
-use std::{env::args, path::Path};
+use std::{collections::HashMap, env::args, io::Write};
 
 use crate::{
-    egress::stdout_csv_egress,
-    ingest::default_csv_ingest,
-    objects::transactions::{Row, Transaction},
+    chart::ChartOfAccounts,
+    cli::{Args, Command, InputFormat},
+    date::Date,
+    egress::{default_csv_egress, default_egress, stdout_csv_egress},
+    events::{EventLog, EventReader},
+    ingest::{default_csv_ingest, feed_processor, feed_statement_processor, post_statement},
+    ledger::Journal,
+    objects::statements::{Balances, BankStatement, StatementAccounts, StatementLine},
     processor::Processor,
+    profile::ImportProfile,
+    quarantine::Quarantine,
+    shard::feed_sharded,
+    store::{AccountStore, TransactionStore, compact::Compaction, disk::DiskStore},
 };
 
+pub(crate) mod amount;
+#[cfg(test)]
+pub(crate) mod bench;
+pub(crate) mod camt;
+pub(crate) mod chart;
+pub(crate) mod cli;
+pub(crate) mod date;
 pub(crate) mod deserialize;
 pub(crate) mod egress;
 pub(crate) mod error;
+pub(crate) mod events;
 pub(crate) mod ingest;
+pub(crate) mod invariants;
+pub(crate) mod ledger;
+pub(crate) mod mt940;
 pub(crate) mod objects;
+pub(crate) mod ofx;
+pub(crate) mod plaintext;
+pub(crate) mod policy;
 pub(crate) mod processor;
+pub(crate) mod profile;
+pub(crate) mod qif;
+pub(crate) mod quarantine;
 pub(crate) mod serialize;
+pub(crate) mod sha256;
+pub(crate) mod shard;
+pub(crate) mod state;
+pub(crate) mod store;
+pub(crate) mod xml;
+
+/// Bookings of a bank statement together with the line they were found at.
+type StatementLines<'a> = Box<dyn Iterator<Item = Result<(u64, StatementLine), error::Error>> + 'a>;
+
+/// Bank statement, its bookings and the balances to reconcile them with, if the format has them.
+struct Statement<'a> {
+    lines: StatementLines<'a>,
+    balances: Option<Balances>,
+}
+
+impl<'a> Statement<'a> {
+    fn without_balances(lines: impl Iterator<Item = (u64, StatementLine)> + 'a) -> Self {
+        Self {
+            lines: Box::new(lines.map(Ok)),
+            balances: None,
+        }
+    }
+}
+
+impl From<BankStatement> for Statement<'_> {
+    fn from(statement: BankStatement) -> Self {
+        Self {
+            balances: Some(statement.balances),
+            ..Statement::without_balances(statement.lines.into_iter())
+        }
+    }
+}
 
 fn main() -> Result<(), error::Error> {
-    let input_file_name = if let Some(name) = args().nth(1) {
-        name
-    } else {
-        // TODO(juf): Add nicer error
-        return Err(error::Error::MissingArgument);
+    let args = Args::parse(args().skip(1))?;
+    let journal = match &args.chart {
+        Some(path) => Some(Journal::with_chart(
+            ChartOfAccounts::from_csv(path)?.strict(args.strict),
+        )),
+        None if args.writes_journal() => Some(Journal::new()),
+        None => None,
     };
-    let mut ingest = default_csv_ingest(Path::new(&input_file_name))?;
-    let mut p = Processor::new();
-    let iter = ingest.deserialize();
-    for row in iter {
-        let row: Row = row?;
-        let txn: Transaction = row.try_into()?;
-        p.process_one(txn);
+    if args.command == Command::Verify {
+        return verify(&args);
+    }
+    if args.command == Command::Upgrade {
+        let (count, head) = events::upgrade(&args.input)?;
+        println!("upgraded {count} records, head {}", sha256::hex(&head));
+        return Ok(());
+    }
+    if let Some(shards) = args.shards {
+        return run_sharded(&args, shards);
+    }
+    // NOTE(juf): The transaction store file is opt-in, as long as everything fits the HashMap is
+    // faster and leaves nothing behind.
+    #[cfg(feature = "sqlite")]
+    if let Some(path) = &args.sqlite {
+        return run(
+            &args,
+            Processor::open_sqlite(path, args.withdrawal_disputes)?,
+            journal,
+        );
+    }
+    match &args.txn_store {
+        Some(path) => {
+            let store = DiskStore::create(path, args.memory_budget())?;
+            run(
+                &args,
+                Processor::with_stores(HashMap::new(), store),
+                journal,
+            )
+        }
+        None => run(&args, Processor::new(), journal),
+    }
+}
+
+/// Checks the event log given as input record by record and, for hash chained logs, that it ends
+/// in the head hash given with `--head`. Without one, a log rewritten from some record on with
+/// the chain recomputed passes.
+fn verify(args: &Args) -> Result<(), error::Error> {
+    let mut events = EventReader::open(&args.input)?;
+    for event in events.by_ref() {
+        event?;
+    }
+    if events.is_torn() {
+        eprintln!("verify: the last record was cut short and is not covered");
+    }
+    if !events.is_chained() {
+        if args.head.is_some() {
+            return Err(error::Error::InvalidLog(
+                "version 1 log without hash chain has no head to check, `upgrade` it".into(),
+            ));
+        }
+        println!(
+            "verified the checksums of {} records, version 1 log without hash chain, `upgrade` it",
+            events.records_read()
+        );
+        return Ok(());
+    }
+    let head = events.head();
+    if let Some(expected) = &args.head
+        && head != *expected
+    {
+        return Err(error::Error::InvalidLog(format!(
+            "the log ends in head {} instead of {} after {} records, records were rewritten or \
+             dropped",
+            sha256::hex(&head),
+            sha256::hex(expected),
+            events.records_read()
+        )));
+    }
+    println!(
+        "verified {} records, head {}{}",
+        events.records_read(),
+        sha256::hex(&head),
+        if args.head.is_some() {
+            " as expected"
+        } else {
+            ""
+        }
+    );
+    Ok(())
+}
+
+/// Feeds the input to `p` and reports on the outcome.
+fn run<A: AccountStore, T: TransactionStore>(
+    args: &Args,
+    mut p: Processor<A, T>,
+    journal: Option<Journal>,
+) -> Result<(), error::Error> {
+    p = p
+        .with_precision(args.precision())
+        .with_locked_accounts(args.locked_accounts.unwrap_or_default());
+    // NOTE(juf): Only if given, books loaded later on are continued with their own otherwise.
+    if let Some(policy) = args.withdrawal_disputes {
+        p = p.with_withdrawal_disputes(policy);
+    }
+    if args.paranoid {
+        p = p.with_paranoid_checks();
+    }
+    if args.command == Command::Replay {
+        if let Some(journal) = journal {
+            p = p.with_journal(journal);
+        }
+        let mut events = EventReader::open(&args.input)?;
+        let count = p.replay(&mut events)?;
+        p.commit()?;
+        if events.is_torn() {
+            eprintln!("replay: dropped the last record, it was cut short");
+        }
+        if events.is_chained() {
+            eprintln!(
+                "replayed {count} events, head {}",
+                sha256::hex(&events.head())
+            );
+        } else {
+            eprintln!("replayed {count} events of a version 1 log, the hash chain is not verified");
+        }
+        return report_accounts(args, &p);
+    }
+    let profile = match &args.profile {
+        Some(path) => Some(ImportProfile::from_file(path)?),
+        None => None,
+    };
+    if profile.is_none() && args.format() == InputFormat::Journal {
+        let mut journal = journal.unwrap_or_default();
+        plaintext::import(&std::fs::read_to_string(&args.input)?, &mut journal)?;
+        return report_ledger(args, &journal);
+    }
+    // Bank statements and who they belong to, a client or the journal only.
+    let statement: Option<(Vec<Statement>, Option<u16>, StatementAccounts)> =
+        match (&profile, args.format()) {
+            (Some(profile), _) => Some((
+                vec![Statement {
+                    lines: Box::new(profile.read(&args.input)?),
+                    balances: None,
+                }],
+                profile.client.or(args.client),
+                profile.accounts.clone(),
+            )),
+            (None, InputFormat::Ofx) => {
+                let statement = ofx::read(&args.input)?;
+                let accounts = statement.accounts(args.account.as_deref());
+                let lines = match args.client {
+                    Some(client) => statement.for_client(client)?,
+                    None => statement.lines,
+                };
+                Some((
+                    vec![Statement::without_balances(lines.into_iter())],
+                    args.client,
+                    accounts,
+                ))
+            }
+            (None, InputFormat::Camt) => Some((
+                camt::read(&args.input)?
+                    .into_iter()
+                    .map(Statement::from)
+                    .collect(),
+                args.client,
+                args.statement_accounts(),
+            )),
+            (None, InputFormat::Mt940) => Some((
+                mt940::read(&args.input)?
+                    .into_iter()
+                    .map(Statement::from)
+                    .collect(),
+                args.client,
+                args.statement_accounts(),
+            )),
+            (None, InputFormat::Qif) => {
+                let register = qif::read(&args.input)?;
+                if register.dropped > 0 {
+                    eprintln!(
+                        "qif: dropped {} fields without a place in the journal, e.g., addresses",
+                        register.dropped
+                    );
+                }
+                Some((
+                    vec![Statement::without_balances(register.lines.into_iter())],
+                    args.client,
+                    args.statement_accounts(),
+                ))
+            }
+            (None, InputFormat::Transactions | InputFormat::Journal) => None,
+        };
+    if let Some(every) = args.compact_every {
+        p = p.with_compaction(Compaction::every(every));
+    }
+    if let Some(path) = &args.state {
+        p = p.restore(path)?;
+    }
+    if let Some(path) = &args.event_log {
+        p = p.with_event_log(EventLog::open(path)?)?;
+    }
+    let mut journal = journal;
+    if !matches!(statement, Some((_, None, _)))
+        && let Some(journal) = journal.take()
+    {
+        p = p.with_journal(journal);
+    }
+    // NOTE(juf): stdout is reserved for the account statements
+    let on_rejection = |rejection| eprintln!("rejected {rejection}");
+    match statement {
+        Some((statements, None, accounts)) => {
+            let mut journal = journal.unwrap_or_default();
+            for statement in statements {
+                post_statement(
+                    statement.lines,
+                    statement.balances.as_ref(),
+                    &accounts,
+                    &mut journal,
+                    |line, err| eprintln!("refused line {line}: {err}"),
+                )?;
+            }
+            return report_ledger(args, &journal);
+        }
+        Some((statements, Some(client), _)) => {
+            for statement in statements {
+                feed_statement_processor(
+                    statement.lines,
+                    statement.balances.as_ref(),
+                    client,
+                    &mut p,
+                    on_rejection,
+                )?;
+            }
+        }
+        None => {
+            let mut ingest = default_csv_ingest(&args.input)?;
+            let mut quarantine = open_quarantine(args)?;
+            feed_processor(&mut ingest, &mut p, quarantine.as_mut(), on_rejection)?;
+            finish_quarantine(quarantine)?;
+        }
+    }
+    p.commit()?;
+    if args.stats {
+        eprintln!("memory: {}", p.memory_stats());
+    }
+    if let Some(head) = p.finish_event_log()? {
+        eprintln!("event log head {}", sha256::hex(&head));
+    }
+    report_accounts(args, &p)
+}
+
+/// Feeds the transactions CSV to `shards` processors running in parallel, see [`shard`], and
+/// reports on the outcome.
+fn run_sharded(args: &Args, shards: usize) -> Result<(), error::Error> {
+    let mut ingest = default_csv_ingest(&args.input)?;
+    let mut quarantine = open_quarantine(args)?;
+    let p = feed_sharded(
+        &mut ingest,
+        shards,
+        || {
+            let mut p = Processor::new()
+                .with_precision(args.precision())
+                .with_withdrawal_disputes(args.withdrawal_disputes.unwrap_or_default())
+                .with_locked_accounts(args.locked_accounts.unwrap_or_default());
+            if let Some(every) = args.compact_every {
+                p = p.with_compaction(Compaction::every(every));
+            }
+            p
+        },
+        quarantine.as_mut(),
+        |rejection| eprintln!("rejected {rejection}"),
+    )?;
+    finish_quarantine(quarantine)?;
+    if args.stats {
+        eprintln!("memory: {}", p.memory_stats());
     }
+    report_accounts(args, &p)
+}
+
+fn open_quarantine(args: &Args) -> Result<Option<Quarantine>, error::Error> {
+    match &args.quarantine {
+        Some(path) => Ok(Some(Quarantine::create(&args.input, path)?)),
+        None => Ok(None),
+    }
+}
+
+fn finish_quarantine(quarantine: Option<Quarantine>) -> Result<(), error::Error> {
+    if let Some(mut quarantine) = quarantine {
+        quarantine.flush()?;
+        if quarantine.count() > 0 {
+            eprintln!("quarantined {} rows", quarantine.count());
+        }
+    }
+    Ok(())
+}
+
+/// Output of a run on client accounts, the accounts go to stdout.
+fn report_accounts<A: AccountStore, T: TransactionStore>(
+    args: &Args,
+    p: &Processor<A, T>,
+) -> Result<(), error::Error> {
+    if args.check {
+        let report = p.check_invariants()?;
+        if !report.is_ok() {
+            return Err(error::Error::BrokenInvariants(report));
+        }
+        eprintln!("invariants: {report}");
+    }
+    // NOTE(juf): Saved only once the whole input went through, a run that fails halfway leaves
+    // the previous state untouched and can simply be repeated.
+    if let Some(path) = &args.state {
+        p.save(path)?;
+    }
+    if let Some(journal) = p.journal() {
+        write_journal(args, journal)?;
+    }
+    let precision = args.precision();
     let mut egress = stdout_csv_egress()?;
-    for (_, account) in p.get_account_store_ref().iter() {
-        egress.serialize(account)?;
+    for account in p.get_account_store_ref().accounts() {
+        egress.serialize(account.rounded(&precision))?;
     }
+    Ok(())
+}
 
+/// Output of a pure bookkeeping run, there are no client accounts to report on, so the rolled up
+/// ledger balances go to stdout instead.
+fn report_ledger(args: &Args, journal: &Journal) -> Result<(), error::Error> {
+    write_journal(args, journal)?;
+    let mut egress = stdout_csv_egress()?;
+    let rolled_up = journal.rolled_up_balances();
+    for row in Journal::balance_rows(&rolled_up, args.precision()) {
+        egress.serialize(row)?;
+    }
     Ok(())
 }
+
+/// Writes the journal, rolled up balances and export files, if requested.
+fn write_journal(args: &Args, journal: &Journal) -> Result<(), error::Error> {
+    let precision = args.precision();
+    if let Some(path) = &args.journal {
+        let mut out = default_csv_egress(path)?;
+        for row in journal.rows(precision) {
+            out.serialize(row)?;
+        }
+        out.flush()?;
+    }
+    if let Some(path) = &args.balances {
+        let mut out = default_csv_egress(path)?;
+        let rolled_up = journal.rolled_up_balances();
+        for row in Journal::balance_rows(&rolled_up, precision) {
+            out.serialize(row)?;
+        }
+        out.flush()?;
+    }
+    if let Some(path) = &args.qif {
+        let mut out = default_egress(path)?;
+        let skipped = qif::write(
+            journal,
+            &args.statement_accounts().account,
+            precision,
+            &mut out,
+        )?;
+        out.flush()?;
+        if skipped > 0 {
+            eprintln!("qif: skipped {skipped} entries without a date");
+        }
+    }
+    let commodity = args.commodity.as_deref().unwrap_or("USD");
+    let undated = args.as_of.unwrap_or_else(Date::today);
+    if let Some(path) = &args.beancount {
+        let mut out = default_egress(path)?;
+        plaintext::write_beancount(journal, commodity, undated, precision, &mut out)?;
+        out.flush()?;
+    }
+    if let Some(path) = &args.ledger {
+        let mut out = default_egress(path)?;
+        plaintext::write_ledger(journal, commodity, undated, precision, &mut out)?;
+        out.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::dec;
+
+    use crate::{
+        cli::Args,
+        events::{Event, EventLog},
+        objects::{
+            accounts::Account,
+            transactions::{InitialState, Metadata, TransactionState, TxType},
+        },
+        processor::Processor,
+        run, sha256,
+        state::{self, Record},
+        verify,
+    };
+
+    fn args(args: &[&str]) -> Args {
+        Args::parse(args.iter().map(|arg| arg.to_string())).expect("valid arguments")
+    }
+
+    #[test]
+    fn card_statements_are_refused_for_clients() {
+        let err = run(
+            &args(&["tests/statement-v2.ofx", "--client", "1"]),
+            Processor::new(),
+            None,
+        )
+        .expect_err("a charge can not run a client account into debt");
+        assert_eq!("invalid_argument", err.code());
+        assert!(err.to_string().contains("credit card"), "{err}");
+    }
+
+    #[test]
+    fn verify_checks_the_head() {
+        let dir = tempfile::tempdir().expect("can create tempdir");
+        let path = dir.path().join("events.log");
+        let write = |amount| {
+            std::fs::remove_file(&path).ok();
+            let mut log = EventLog::open(&path).expect("can open");
+            for (tx_id, amount) in [(1, dec!(10)), (2, amount)] {
+                log.append(&Event {
+                    action: TxType::Deposit,
+                    metadata: Metadata::new(1, tx_id),
+                    amount: Some(amount),
+                    state: Some(TransactionState::Initial(InitialState::Deposit)),
+                    external_id: None,
+                    reason: None,
+                });
+            }
+            log.finish().expect("can finish");
+            sha256::hex(&log.head())
+        };
+        let path_arg = path.to_str().expect("utf-8 path");
+        let head = write(dec!(5));
+        verify(&args(&["verify", path_arg, "--head", &head])).expect("ends in the head");
+
+        // The second deposit edited, with the chain recomputed from there on
+        write(dec!(500));
+        verify(&args(&["verify", path_arg])).expect("the chain itself is intact");
+        let err = verify(&args(&["verify", path_arg, "--head", &head]))
+            .expect_err("the head was given away before the edit");
+        assert!(err.to_string().contains("rewritten"), "{err}");
+    }
+
+    #[test]
+    fn paranoid_runs_fail_with_the_report() {
+        let dir = tempfile::tempdir().expect("can create tempdir");
+        let (input, state) = (dir.path().join("in.csv"), dir.path().join("state"));
+        std::fs::write(&input, "type,client,tx,amount\ndeposit,1,1,1.0\n").expect("can write");
+        let broken = Account {
+            held: dec!(1),
+            ..Account::new(1)
+        };
+        state::write(&state, [Ok(Record::Account(broken))].into_iter()).expect("can write");
+        let saved = std::fs::read(&state).expect("can read");
+        let input = input.to_str().expect("utf-8 path");
+        let state_arg = state.to_str().expect("utf-8 path");
+        let err = run(
+            &args(&[input, "--state", state_arg, "--paranoid"]),
+            Processor::new(),
+            None,
+        )
+        .expect_err("held 1 is not under dispute");
+        assert_eq!("broken_invariants", err.code());
+        assert!(err.to_string().contains("after deposit of tx 1"), "{err}");
+        assert_eq!(saved, std::fs::read(&state).expect("can read"));
+    }
+}
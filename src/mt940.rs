@@ -0,0 +1,419 @@
+use std::{path::Path, str::FromStr};
+
+use rust_decimal::Decimal;
+
+use crate::{
+    date::Date,
+    error::Error,
+    events::fits,
+    objects::statements::{Balance, Balances, BankStatement, StatementLine},
+};
+
+// NOTE(juf): SWIFT MT940 customer statement. A message is a sequence of `:tag:value` fields,
+// values may continue on the following lines, and ends with a line holding a single `-`. Only
+// the fields needed for bookings and balances are interpreted:
+//   :20: transaction reference        :25: account identification
+//   :28C: statement/sequence number   :60F:/:60M: opening balance
+//   :61: statement line               :86: narrative of the preceding :61:
+//   :62F:/:62M: closing balance
+// The SWIFT envelope (`{1:...}{2:...}{4:` ... `-}`) is skipped.
+
+/// Reads all statements of an MT940 file, one per message.
+pub(crate) fn read(path: &Path) -> Result<Vec<BankStatement>, Error> {
+    // NOTE(juf): MT940 is specified for the SWIFT character set, exports in the wild are mostly
+    // Latin-1 or UTF-8, we decode lossy instead of failing on an umlaut in a narrative.
+    let bytes = std::fs::read(path)?;
+    parse(&String::from_utf8_lossy(&bytes))
+}
+
+pub(crate) fn parse(input: &str) -> Result<Vec<BankStatement>, Error> {
+    let mut statements = Vec::new();
+    let mut current: Option<Message> = None;
+    for (tag, line, value) in fields(input) {
+        let invalid = |reason: &str| Error::InvalidRow(format!("mt940 line {line}: {reason}"));
+        if tag == "20" {
+            if let Some(message) = current.take() {
+                statements.push(message.finish()?);
+            }
+            current = Some(Message::new(line, value.trim()));
+            continue;
+        }
+        let message = current
+            .as_mut()
+            .ok_or_else(|| invalid(&format!(":{tag}: before :20:")))?;
+        match tag.as_str() {
+            "25" => message.account = Some(value.trim().to_string()),
+            "28C" => message.sequence = Some(value.trim().to_string()),
+            "60F" | "60M" => message.opening = Some(balance(&value).map_err(|err| invalid(&err))?),
+            "62F" | "62M" => message.closing = Some(balance(&value).map_err(|err| invalid(&err))?),
+            "61" => message
+                .lines
+                .push((line, statement_line(&value).map_err(|err| invalid(&err))?)),
+            "86" => {
+                let Some((_, statement_line)) = message.lines.last_mut() else {
+                    // Narrative on statement level, e.g., after :62F:, not tied to a booking
+                    continue;
+                };
+                narrative(statement_line, &value);
+            }
+            _ => {}
+        }
+    }
+    if let Some(message) = current {
+        statements.push(message.finish()?);
+    }
+    Ok(statements)
+}
+
+/// Fields of the message being read.
+struct Message {
+    line: u64,
+    reference: String,
+    account: Option<String>,
+    sequence: Option<String>,
+    opening: Option<Balance>,
+    closing: Option<Balance>,
+    lines: Vec<(u64, StatementLine)>,
+}
+
+impl Message {
+    fn new(line: u64, reference: &str) -> Self {
+        Self {
+            line,
+            reference: reference.to_string(),
+            account: None,
+            sequence: None,
+            opening: None,
+            closing: None,
+            lines: Vec::new(),
+        }
+    }
+
+    fn finish(self) -> Result<BankStatement, Error> {
+        let account = self
+            .account
+            .ok_or_else(|| Error::InvalidRow(format!("mt940 line {}: missing :25:", self.line)))?;
+        // :20: is only unique per sender, :25: and :28C: make the id unique per account
+        let statement = match self.sequence {
+            Some(sequence) => format!("{account}/{}/{sequence}", self.reference),
+            None => format!("{account}/{}", self.reference),
+        };
+        Ok(BankStatement {
+            balances: Balances {
+                statement,
+                opening: self.opening,
+                closing: self.closing,
+            },
+            lines: self.lines,
+        })
+    }
+}
+
+/// Splits the input into `(tag, line, value)`, where multi-line values are joined by `\n`.
+fn fields(input: &str) -> impl Iterator<Item = (String, u64, String)> + '_ {
+    let mut lines = input.lines().zip(1..).peekable();
+    std::iter::from_fn(move || {
+        loop {
+            let (text, line) = lines.next()?;
+            let Some((tag, value)) = field_start(text) else {
+                // Envelope, message separator `-` or stray text outside of a field
+                continue;
+            };
+            let mut value = value.to_string();
+            while let Some((next, _)) = lines.peek()
+                && field_start(next).is_none()
+                && !is_message_end(next)
+            {
+                value.push('\n');
+                value.push_str(next.trim_end());
+                lines.next();
+            }
+            return Some((tag.to_string(), line, value));
+        }
+    })
+}
+
+/// `:tag:value`, where the tag is two digits and an optional letter.
+fn field_start(text: &str) -> Option<(&str, &str)> {
+    let rest = text.strip_prefix(':')?;
+    let (tag, value) = rest.split_once(':')?;
+    let valid = matches!(tag.len(), 2 | 3)
+        && tag.is_ascii()
+        && tag[..2].bytes().all(|b| b.is_ascii_digit())
+        && tag[2..].bytes().all(|b| b.is_ascii_uppercase());
+    valid.then_some((tag, value.trim_end()))
+}
+
+fn is_message_end(text: &str) -> bool {
+    matches!(text.trim(), "-" | "-}") || text.starts_with('{')
+}
+
+/// `:60F:`/`:62F:` value, e.g., `C250228EUR1000,00`.
+fn balance(value: &str) -> Result<Balance, String> {
+    let value = value.trim();
+    let sign = match value.get(..1) {
+        Some("C") => Decimal::ONE,
+        Some("D") => Decimal::NEGATIVE_ONE,
+        _ => return Err(format!("invalid balance {value:?}, expected C or D mark")),
+    };
+    let date = value
+        .get(1..7)
+        .ok_or_else(|| format!("invalid balance {value:?}"))?;
+    let amount = value
+        .get(10..)
+        .ok_or_else(|| format!("invalid balance {value:?}"))?;
+    Ok(Balance {
+        date: Date::parse(date, "%y%m%d").map_err(|err| err.to_string())?,
+        amount: sign * parse_amount(amount)?,
+    })
+}
+
+/// `:61:` value, e.g., `2503040303CR1500,00NTRFPAYROLL//BANKREF-0001`, followed by an optional
+/// line of supplementary details.
+fn statement_line(value: &str) -> Result<StatementLine, String> {
+    let (first, supplementary) = value.split_once('\n').unwrap_or((value, ""));
+    let invalid = || format!("invalid statement line {first:?}");
+    let value_date = Date::parse(first.get(..6).ok_or_else(invalid)?, "%y%m%d")
+        .map_err(|err| err.to_string())?;
+    let mut rest = &first[6..];
+    // Optional booking date MMDD, the year is the one of the value date unless the booking
+    // happened across the turn of the year
+    let mut date = value_date;
+    if let Some(entry) = rest.get(..4)
+        && entry.bytes().all(|b| b.is_ascii_digit())
+    {
+        let year = match (value_date.month(), &entry[..2]) {
+            (12, "01") => value_date.year() + 1,
+            (1, "12") => value_date.year() - 1,
+            _ => value_date.year(),
+        };
+        date = Date::parse(&format!("{year}{entry}"), "%Y%m%d").map_err(|err| err.to_string())?;
+        rest = &rest[4..];
+    }
+    // R marks the reversal of a credit (RC) or debit (RD), i.e., the money flows the other way
+    let (sign, mark_len) = match rest.get(..2) {
+        Some("RC") => (Decimal::NEGATIVE_ONE, 2),
+        Some("RD") => (Decimal::ONE, 2),
+        _ => match rest.get(..1) {
+            Some("C") => (Decimal::ONE, 1),
+            Some("D") => (Decimal::NEGATIVE_ONE, 1),
+            _ => return Err(invalid()),
+        },
+    };
+    rest = &rest[mark_len..];
+    // Optional funds code, the third letter of the currency
+    if rest.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        rest = &rest[1..];
+    }
+    let amount_len = rest
+        .find(|c: char| !(c.is_ascii_digit() || c == ','))
+        .unwrap_or(rest.len());
+    let amount = sign * parse_amount(&rest[..amount_len])?;
+    // Transaction type, e.g., NTRF, followed by the customer reference and `//` bank reference
+    let references = rest[amount_len..].get(4..).ok_or_else(invalid)?;
+    let (customer, bank) = references.split_once("//").unwrap_or((references, ""));
+    fits("bank reference", bank.trim())?;
+    let non_empty = |value: &str| {
+        let value = value.trim();
+        (!value.is_empty() && value != "NONREF").then(|| value.to_string())
+    };
+    Ok(StatementLine {
+        date,
+        amount,
+        payee: None,
+        memo: non_empty(supplementary),
+        external_id: non_empty(bank),
+        value_date: Some(value_date),
+        counterparty_account: None,
+        reference: non_empty(customer),
+        cleared: None,
+        splits: Vec::new(),
+    })
+}
+
+/// Attaches the `:86:` narrative. Structured narratives (`166?00...?20...`), as common with
+/// German banks, are split into remittance info, counterparty name and account, free text is
+/// taken as is. Subfields are fixed width chunks of a longer text, so they are concatenated.
+fn narrative(line: &mut StatementLine, value: &str) {
+    let value = value.replace('\n', "");
+    let structured = value.len() > 3
+        && value.as_bytes()[..3].iter().all(u8::is_ascii_digit)
+        && value[3..].starts_with('?');
+    if !structured {
+        line.memo = Some(value.trim().to_string()).filter(|memo| !memo.is_empty());
+        return;
+    }
+    let mut remittance = String::new();
+    let mut payee = String::new();
+    for subfield in value[4..].split('?') {
+        let (Some(code), Some(text)) = (
+            subfield.get(..2).and_then(|code| code.parse::<u8>().ok()),
+            subfield.get(2..),
+        ) else {
+            continue;
+        };
+        match code {
+            20..=29 | 60..=63 => remittance.push_str(text),
+            31 => line.counterparty_account = Some(text.trim().to_string()),
+            32 | 33 => payee.push_str(text),
+            _ => {}
+        }
+    }
+    let non_empty = |text: String| Some(text.trim().to_string()).filter(|text| !text.is_empty());
+    line.memo = non_empty(remittance).or(line.memo.take());
+    line.payee = non_empty(payee).or(line.payee.take());
+}
+
+/// Amounts use a decimal comma and no thousands separators, e.g., `1500,` or `1500,00`.
+fn parse_amount(raw: &str) -> Result<Decimal, String> {
+    let normalized = raw.trim().replace(',', ".");
+    Decimal::from_str(normalized.trim_end_matches('.'))
+        .map_err(|_| format!("invalid amount {raw:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use rust_decimal::dec;
+
+    use crate::{
+        date::Date,
+        error::Error,
+        events::MAX_TEXT_LEN,
+        ingest::{feed_statement_processor, post_statement},
+        ledger::Journal,
+        mt940::{field_start, parse, read},
+        objects::statements::{StatementAccounts, StatementLine},
+        processor::Processor,
+    };
+
+    #[test]
+    fn read_statement() {
+        let statements = read(Path::new("tests/statement.mt940")).expect("valid mt940");
+        assert_eq!(2, statements.len());
+        let march = &statements[0];
+        assert_eq!(
+            "10020030/1234567/STARTUMS/00003/001",
+            march.balances.statement
+        );
+        assert_eq!(
+            dec!(1000.00),
+            march.balances.opening.expect("has opening").amount
+        );
+        assert_eq!(
+            dec!(2155.51),
+            march.balances.closing.expect("has closing").amount
+        );
+        assert_eq!(3, march.lines.len());
+        assert_eq!(
+            (
+                6,
+                StatementLine {
+                    date: Date::new(2025, 3, 3).expect("valid date"),
+                    amount: dec!(1500.00),
+                    payee: Some("ACME GmbH".into()),
+                    memo: Some("Gehalt Maerz 2025 EREF+PAYROLL-2025-03".into()),
+                    external_id: Some("BANKREF-0001".into()),
+                    value_date: Some(Date::new(2025, 3, 4).expect("valid date")),
+                    counterparty_account: Some("DE89370400440532013000".into()),
+                    reference: Some("PAYROLL".into()),
+                    cleared: None,
+                    splits: Vec::new(),
+                }
+            ),
+            march.lines[0]
+        );
+        assert_eq!(dec!(-320.00), march.lines[1].1.amount);
+        assert_eq!(Some("Miete Maerz Whg 3".into()), march.lines[1].1.memo);
+        assert_eq!(None, march.lines[1].1.reference, "NONREF is no reference");
+        assert_eq!(dec!(-24.49), march.lines[2].1.amount);
+        assert_eq!(Some("Kontofuehrung".into()), march.lines[2].1.memo);
+
+        let year_end = &statements[1];
+        assert_eq!(
+            dec!(2155.51),
+            year_end.balances.opening.expect("has opening").amount
+        );
+        let (_, reversal) = &year_end.lines[0];
+        assert_eq!(dec!(-100), reversal.amount, "reversal of a credit");
+        assert_eq!(
+            Date::new(2025, 12, 31).expect("valid date"),
+            reversal.date,
+            "booked in the year before the value date"
+        );
+    }
+
+    #[test]
+    fn invalid_fields_are_errors() {
+        assert!(parse(":25:1/2\n:20:X\n").is_err(), ":25: before :20:");
+        assert!(parse(":20:X\n-\n").is_err(), ":25: is required");
+        assert!(parse(":20:X\n:25:1\n:60F:X250101EUR1,00\n").is_err());
+        assert!(parse(":20:X\n:25:1\n:61:250101X1,00NTRFNONREF\n").is_err());
+        assert!(parse(":20:X\n:25:1\n:61:250101C1,00\n").is_err());
+        let long_reference = format!(
+            ":20:X\n:25:1\n:61:250101C1,00NTRFNONREF//{}\n",
+            "9".repeat(MAX_TEXT_LEN + 1)
+        );
+        assert!(parse(&long_reference).is_err());
+        assert!(parse("").expect("empty file").is_empty());
+    }
+
+    #[test]
+    fn non_ascii_tags_are_text() {
+        assert_eq!(None, field_start(":1é:Miete"));
+        assert_eq!(Some(("86", "Miete")), field_start(":86:Miete"));
+    }
+
+    #[test]
+    fn balances_are_checked_against_processor() {
+        let statements = read(Path::new("tests/statement.mt940")).expect("valid mt940");
+        let mut p = Processor::new();
+        for statement in statements.iter() {
+            feed_statement_processor(
+                statement.lines.clone().into_iter().map(Ok),
+                Some(&statement.balances),
+                1,
+                &mut p,
+                |rejection| panic!("rejected {rejection}"),
+            )
+            .expect("balances reconcile");
+        }
+        let acc = p.get_account_store_ref().get(&1).expect("account exists");
+        assert_eq!(dec!(2055.51), acc.total());
+
+        // The March statement once more does not continue where the account stands
+        let err = feed_statement_processor(
+            statements[0].lines.clone().into_iter().map(Ok),
+            Some(&statements[0].balances),
+            1,
+            &mut p,
+            |_| {},
+        )
+        .expect_err("opening balance does not match");
+        assert!(matches!(
+            err,
+            Error::BalanceMismatch {
+                kind: "opening",
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn balances_are_checked_against_journal() {
+        let mut journal = Journal::new();
+        let accounts = StatementAccounts::default();
+        for statement in read(Path::new("tests/statement.mt940")).expect("valid mt940") {
+            post_statement(
+                statement.lines.into_iter().map(Ok),
+                Some(&statement.balances),
+                &accounts,
+                &mut journal,
+                |line, err| panic!("line {line} refused: {err}"),
+            )
+            .expect("balances reconcile");
+        }
+        assert_eq!(dec!(2055.51), journal.balance("Assets:Bank"));
+    }
+}
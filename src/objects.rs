@@ -1,8 +1,8 @@
 pub(crate) mod transactions {
     use rust_decimal::Decimal;
-    use serde::Deserialize;
+    use serde::{Deserialize, Serialize};
 
-    #[derive(Debug, Deserialize, PartialEq, Eq)]
+    #[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
     #[serde(rename_all = "lowercase")]
     pub(crate) enum TxType {
         Deposit,
@@ -10,6 +10,23 @@ pub(crate) mod transactions {
         Dispute,
         Resolve,
         Chargeback,
+        /// Reinstates a locked account
+        #[serde(alias = "reinstate")]
+        Unlock,
+    }
+
+    impl TxType {
+        /// Lowercase name, as used in the input CSV.
+        pub(crate) fn name(&self) -> &'static str {
+            match self {
+                TxType::Deposit => "deposit",
+                TxType::Withdrawal => "withdrawal",
+                TxType::Dispute => "dispute",
+                TxType::Resolve => "resolve",
+                TxType::Chargeback => "chargeback",
+                TxType::Unlock => "unlock",
+            }
+        }
     }
 
     #[derive(Debug, Deserialize, PartialEq, Eq)]
@@ -18,6 +35,9 @@ pub(crate) mod transactions {
         pub client: u16,
         pub tx: u32,
         pub amount: Option<Decimal>,
+        /// Why an operator unlocks the account, the column is optional.
+        #[serde(default)]
+        pub reason: Option<String>,
     }
 
     impl From<Row> for Metadata {
@@ -55,6 +75,17 @@ pub(crate) mod transactions {
                 TxType::Dispute => Ok(Transaction::Dispute(value.into())),
                 TxType::Chargeback => Ok(Transaction::Chargeback(value.into())),
                 TxType::Resolve => Ok(Transaction::Resolve(value.into())),
+                TxType::Unlock => match value.reason.clone() {
+                    Some(reason) if !reason.trim().is_empty() => {
+                        // NOTE(juf): The reason is kept in the event log, it has to fit a record.
+                        crate::events::fits("the reason of an unlock", &reason)
+                            .map_err(crate::error::Error::InvalidRow)?;
+                        Ok(Transaction::Unlock(value.into(), reason))
+                    }
+                    _ => Err(crate::error::Error::InvalidRow(
+                        "unlocks require a reason".into(),
+                    )),
+                },
             }
         }
     }
@@ -71,18 +102,22 @@ pub(crate) mod transactions {
         Dispute(Metadata),
         Resolve(Metadata),
         Chargeback(Metadata),
+        /// Reinstates the locked account, with the reason the operator gave
+        Unlock(Metadata, String),
     }
 
     /// TransactionState describes whether a Transaction
     /// has been neither disputed/resolved/chargedback if it's `Initial`.
     /// Been resolved or chargedback if it's `Finalized`.
     /// Is under dispute `Disputed`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub enum TransactionState {
         Initial(InitialState),
         Finalized,
         Disputed(InitialState),
     }
 
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub enum InitialState {
         Deposit,
         Withdrawal,
@@ -96,6 +131,7 @@ pub(crate) mod transactions {
                 Transaction::Dispute(m) => m,
                 Transaction::Resolve(m) => m,
                 Transaction::Chargeback(m) => m,
+                Transaction::Unlock(m, _) => m,
             }
         }
     }
@@ -107,14 +143,191 @@ pub(crate) mod transactions {
     }
 
     impl Metadata {
+        // used by test
+        #[allow(dead_code)]
         pub(crate) fn new(client: u16, tx_id: u32) -> Self {
             Metadata { client, tx_id }
         }
     }
 }
 
+pub(crate) mod statements {
+    use rust_decimal::Decimal;
+
+    use crate::{
+        date::Date,
+        error::Error,
+        ledger::{JournalEntry, LedgerError, Posting},
+        objects::transactions::{Metadata, Transaction, TxType},
+    };
+
+    /// StatementLine is a single booking on a bank statement, independent of the format it was
+    /// imported from.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub(crate) struct StatementLine {
+        pub(crate) date: Date,
+        /// Signed from the perspective of the bank account, positive amounts are credits.
+        pub(crate) amount: Decimal,
+        pub(crate) payee: Option<String>,
+        pub(crate) memo: Option<String>,
+        /// Id the bank assigned to the booking, e.g., OFX FITID, used to detect re-imports.
+        pub(crate) external_id: Option<String>,
+        /// Date the funds are available, if the bank reports it separately from the booking date.
+        pub(crate) value_date: Option<Date>,
+        /// Account of the counterparty, e.g., its IBAN.
+        pub(crate) counterparty_account: Option<String>,
+        /// Reference the payment was initiated with, e.g., the SEPA end-to-end id.
+        pub(crate) reference: Option<String>,
+        /// Cleared status in the register it was kept in, e.g., QIF's `*` or `X`.
+        pub(crate) cleared: Option<String>,
+        /// Breakdown of the amount onto several contra accounts, empty if there is only one.
+        pub(crate) splits: Vec<Split>,
+    }
+
+    /// Part of a statement line's amount, signed like the line itself.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub(crate) struct Split {
+        /// Contra account, the uncategorized income or expenses account if `None`.
+        pub(crate) account: Option<String>,
+        pub(crate) amount: Decimal,
+        pub(crate) memo: Option<String>,
+        /// Class the split is tagged with besides its category, e.g., QIF's `Food/Vacation`.
+        pub(crate) class: Option<String>,
+    }
+
+    /// Balance of the bank account at the given date as reported by the bank.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub(crate) struct Balance {
+        pub(crate) date: Date,
+        pub(crate) amount: Decimal,
+    }
+
+    /// Balances a statement starts and ends with, used to reconcile the import with the ledger.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub(crate) struct Balances {
+        /// Id of the statement, for reporting.
+        pub(crate) statement: String,
+        pub(crate) opening: Option<Balance>,
+        pub(crate) closing: Option<Balance>,
+    }
+
+    impl Balances {
+        /// Compares the balances with the ones computed from the ledger before and after the
+        /// statement was imported.
+        pub(crate) fn reconcile(&self, before: Decimal, after: Decimal) -> Result<(), Error> {
+            for (kind, expected, actual) in [
+                ("opening", self.opening, before),
+                ("closing", self.closing, after),
+            ] {
+                if let Some(expected) = expected
+                    && expected.amount != actual
+                {
+                    return Err(Error::BalanceMismatch {
+                        statement: self.statement.clone(),
+                        kind,
+                        expected: expected.amount,
+                        actual,
+                    });
+                }
+            }
+            Ok(())
+        }
+    }
+
+    /// Bookings of a statement together with the balances it reports.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub(crate) struct BankStatement {
+        pub(crate) balances: Balances,
+        /// Bookings together with the line they were found at.
+        pub(crate) lines: Vec<(u64, StatementLine)>,
+    }
+
+    /// Ledger accounts statement lines are booked against.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub(crate) struct StatementAccounts {
+        /// The bank account itself, e.g., `Assets:Bank:Checking`.
+        pub(crate) account: String,
+        pub(crate) income: String,
+        pub(crate) expenses: String,
+    }
+
+    impl Default for StatementAccounts {
+        fn default() -> Self {
+            Self {
+                account: "Assets:Bank".into(),
+                income: "Income:Uncategorized".into(),
+                expenses: "Expenses:Uncategorized".into(),
+            }
+        }
+    }
+
+    impl StatementAccounts {
+        /// Contra account for a booking of `amount` on the bank account.
+        pub(crate) fn contra(&self, amount: Decimal) -> &str {
+            if amount.is_sign_negative() {
+                &self.expenses
+            } else {
+                &self.income
+            }
+        }
+    }
+
+    impl StatementLine {
+        pub(crate) fn into_transaction(self, client: u16, tx_id: u32) -> Transaction {
+            let metadata = Metadata { client, tx_id };
+            if self.amount.is_sign_negative() {
+                Transaction::Withdrawal(metadata, -self.amount)
+            } else {
+                Transaction::Deposit(metadata, self.amount)
+            }
+        }
+
+        /// Kind of transaction the line amounts to from the perspective of the bank account.
+        pub(crate) fn action(&self) -> TxType {
+            if self.amount.is_sign_negative() {
+                TxType::Withdrawal
+            } else {
+                TxType::Deposit
+            }
+        }
+
+        /// Books the line against the bank account and the matching contra account, or the
+        /// accounts of its splits. Memo and class of a split are kept with its posting.
+        pub(crate) fn into_entry(
+            self,
+            tx_id: u32,
+            accounts: &StatementAccounts,
+        ) -> Result<JournalEntry, LedgerError> {
+            let action = self.action();
+            let mut postings = vec![Posting::new(&accounts.account, self.amount)];
+            if self.splits.is_empty() {
+                postings.push(Posting::new(accounts.contra(self.amount), -self.amount));
+            }
+            for split in self.splits {
+                let account = match &split.account {
+                    Some(account) => account.as_str(),
+                    None => accounts.contra(split.amount),
+                };
+                postings.push(
+                    Posting::new(account, -split.amount)
+                        .with_meta("memo", split.memo)
+                        .with_meta("class", split.class),
+                );
+            }
+            Ok(JournalEntry::new(tx_id, action, postings)?
+                .describe(Some(self.date), self.payee, self.memo)
+                .with_external_id(self.external_id)
+                .with_meta("value_date", self.value_date.map(|date| date.to_string()))
+                .with_meta("counterparty_account", self.counterparty_account)
+                .with_meta("reference", self.reference)
+                .with_meta("cleared", self.cleared))
+        }
+    }
+}
+
 pub(crate) mod accounts {
     use rust_decimal::Decimal;
+    use thiserror::Error;
 
     // println!("Account Size {}", size_of::<Account>());
     // >> 36
@@ -127,6 +340,14 @@ pub(crate) mod accounts {
                                        // padding                       |    1 byte
     }
 
+    /// A balance of the account, or its total, would no longer fit a [`Decimal`].
+    #[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+    #[error("balance would overflow")]
+    pub(crate) struct Overflow;
+
+    // NOTE(juf): `Decimal` panics on overflow, around 7.9e28. Every balance change is checked
+    // and only applied once all of available, held and their total fit, so a change either
+    // happens as a whole or not at all.
     impl Account {
         pub(crate) fn new(id: u16) -> Self {
             Self {
@@ -139,53 +360,178 @@ pub(crate) mod accounts {
             self.locked
         }
 
+        /// Unlocks the account, the balances stay as they are.
+        pub(crate) fn reinstate(&mut self) {
+            self.locked = false;
+        }
+
+        /// Can not overflow, the total is checked on every balance change and when loading
+        /// accounts, see [`Account::checked_total`].
         pub(crate) fn total(&self) -> Decimal {
-            // TODO(juf): Check overflow behaviour of Decimal library
             self.available + self.held
         }
 
-        pub(crate) fn dispute(&mut self, amount: Decimal) {
-            self.held += amount;
-            self.available -= amount;
+        pub(crate) fn checked_total(&self) -> Result<Decimal, Overflow> {
+            self.available.checked_add(self.held).ok_or(Overflow)
+        }
+
+        /// Takes over the new balances, if they and the total fit.
+        fn set_balances(
+            &mut self,
+            available: Option<Decimal>,
+            held: Option<Decimal>,
+        ) -> Result<(), Overflow> {
+            let changed = Account {
+                available: available.ok_or(Overflow)?,
+                held: held.ok_or(Overflow)?,
+                ..self.clone()
+            };
+            changed.checked_total()?;
+            *self = changed;
+            Ok(())
         }
 
-        pub(crate) fn dispute_withdrawal(&mut self, amount: Decimal) {
-            self.held += amount;
+        pub(crate) fn dispute(&mut self, amount: Decimal) -> Result<(), Overflow> {
+            self.set_balances(
+                self.available.checked_sub(amount),
+                self.held.checked_add(amount),
+            )
         }
 
-        pub(crate) fn resolve(&mut self, amount: Decimal) {
-            self.held -= amount;
-            self.available += amount;
+        /// Credits the withdrawn amount to held, the `hold` policy of
+        /// [`WithdrawalDisputes`](crate::policy::WithdrawalDisputes).
+        pub(crate) fn dispute_withdrawal(&mut self, amount: Decimal) -> Result<(), Overflow> {
+            self.set_balances(Some(self.available), self.held.checked_add(amount))
         }
 
-        pub(crate) fn resolve_withdrawal(&mut self, amount: Decimal) {
-            self.held -= amount;
+        pub(crate) fn resolve(&mut self, amount: Decimal) -> Result<(), Overflow> {
+            self.set_balances(
+                self.available.checked_add(amount),
+                self.held.checked_sub(amount),
+            )
         }
 
-        pub(crate) fn chargeback(&mut self, amount: Decimal) {
-            self.held -= amount;
+        /// Takes back what [`Account::dispute_withdrawal`] credited, the withdrawal stands.
+        pub(crate) fn resolve_withdrawal(&mut self, amount: Decimal) -> Result<(), Overflow> {
+            self.set_balances(Some(self.available), self.held.checked_sub(amount))
+        }
+
+        pub(crate) fn chargeback(&mut self, amount: Decimal) -> Result<(), Overflow> {
+            self.set_balances(Some(self.available), self.held.checked_sub(amount))?;
+            self.locked = true;
+            Ok(())
+        }
+
+        /// Releases what [`Account::dispute_withdrawal`] credited to available, the withdrawal is
+        /// undone and the account locked.
+        pub(crate) fn chargeback_withdrawal(&mut self, amount: Decimal) -> Result<(), Overflow> {
+            // NOTE(juf): Was unclear for long, see `policy` for the whole lifecycle.
+            self.set_balances(
+                self.available.checked_add(amount),
+                self.held.checked_sub(amount),
+            )?;
             self.locked = true;
+            Ok(())
         }
 
-        pub(crate) fn chargeback_withdrawal(&mut self, amount: Decimal) {
-            // NOTE(juf): Chargeback of withdrawal is not really clear to me
-            // This might have some logical insonsistencies or even bugs in it.
-            // I noticed this way too late and weaved it in post-hoc.
-            // Technically we also "release" the held funds, so it should be same.
-            // But we should credit it back to the account, since it was withdrawn from it.
-            self.held -= amount;
-            self.available += amount;
+        /// Credits the withdrawn amount to available and locks the account, the
+        /// `credit-on-chargeback` policy of [`WithdrawalDisputes`](crate::policy::WithdrawalDisputes).
+        pub(crate) fn reverse_withdrawal(&mut self, amount: Decimal) -> Result<(), Overflow> {
+            self.set_balances(self.available.checked_add(amount), Some(self.held))?;
             self.locked = true;
+            Ok(())
         }
 
-        pub(crate) fn deposit(&mut self, amount: Decimal) {
-            self.available += amount;
+        pub(crate) fn deposit(&mut self, amount: Decimal) -> Result<(), Overflow> {
+            self.set_balances(self.available.checked_add(amount), Some(self.held))
         }
 
-        pub(crate) fn withdraw(&mut self, amount: Decimal) {
+        pub(crate) fn withdraw(&mut self, amount: Decimal) -> Result<(), Overflow> {
             if self.available >= amount {
-                self.available -= amount
+                self.set_balances(self.available.checked_sub(amount), Some(self.held))?;
             }
+            Ok(())
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use rust_decimal::{Decimal, dec};
+
+        use crate::objects::accounts::{Account, Overflow};
+
+        fn account(available: Decimal, held: Decimal) -> Account {
+            Account {
+                id: 1,
+                locked: false,
+                available,
+                held,
+            }
+        }
+
+        #[test]
+        fn balance_changes_near_the_limits_overflow_untouched() {
+            let max = Decimal::MAX;
+            let min = Decimal::MIN;
+            for (mut acc, change, what) in [
+                (
+                    account(max - dec!(1), dec!(0)),
+                    Account::deposit as fn(&mut Account, Decimal) -> Result<(), Overflow>,
+                    "available",
+                ),
+                (account(max - dec!(5), dec!(5)), Account::deposit, "total"),
+                (account(dec!(0), max), Account::dispute_withdrawal, "held"),
+                (
+                    account(min + dec!(1), dec!(0)),
+                    Account::dispute,
+                    "available",
+                ),
+                (account(dec!(-5), max), Account::dispute_withdrawal, "total"),
+                (account(max, min + dec!(1)), Account::resolve, "available"),
+                (account(dec!(0), min + dec!(1)), Account::chargeback, "held"),
+                (
+                    account(max, min + dec!(1)),
+                    Account::chargeback_withdrawal,
+                    "available",
+                ),
+                (
+                    account(max - dec!(1), dec!(0)),
+                    Account::reverse_withdrawal,
+                    "available",
+                ),
+            ] {
+                let before = acc.clone();
+                assert_eq!(Err(Overflow), change(&mut acc, dec!(2)), "{what}");
+                assert_eq!(before, acc, "{what} overflows untouched");
+            }
+        }
+
+        #[test]
+        fn balance_changes_up_to_the_limits() {
+            let mut acc = account(Decimal::MAX - dec!(2), dec!(0));
+            assert_eq!(Ok(()), acc.deposit(dec!(2)));
+            assert_eq!(Decimal::MAX, acc.total());
+            assert_eq!(Ok(()), acc.dispute(Decimal::MAX));
+            assert_eq!((dec!(0), Decimal::MAX), (acc.available, acc.held));
+            assert_eq!(Ok(()), acc.chargeback(Decimal::MAX));
+            assert_eq!(
+                Account::new(1),
+                Account {
+                    locked: false,
+                    ..acc.clone()
+                }
+            );
+            assert!(acc.locked);
+            assert_eq!(
+                Ok(()),
+                acc.withdraw(dec!(1)),
+                "insufficient funds change nothing"
+            );
+            assert_eq!(dec!(0), acc.available);
+            assert_eq!(
+                Err(Overflow),
+                account(Decimal::MAX, dec!(1)).checked_total()
+            );
         }
     }
 }
@@ -0,0 +1,363 @@
+use std::{path::Path, str::FromStr};
+
+use rust_decimal::Decimal;
+
+use crate::{
+    date::Date,
+    error::Error,
+    events::fits,
+    objects::statements::{StatementAccounts, StatementLine},
+};
+
+// NOTE(juf): OFX 1.x is SGML where leaf elements are not closed, e.g., `<TRNAMT>-12.50`, whereas
+// OFX 2.x is XML, `<TRNAMT>-12.50</TRNAMT>`. Aggregates such as `<STMTTRN>` are closed in both.
+// So instead of a full blown SGML/XML parser we tokenize tags and treat the text directly
+// following an opening tag as the value of that element, which covers both versions.
+
+// NOTE(juf): A credit card statement (`<CCSTMTRS>`) looks the same as the one of a bank account,
+// but the balance is owed, not held. Charges are negative and run the card into debt, which a
+// client account can not do, so card statements are booked into the journal only, against a
+// liability account.
+
+/// Ledger account credit card statements are booked against, unless another one is given.
+pub(crate) const CREDIT_CARD: &str = "Liabilities:CreditCard";
+
+/// Kind of account an OFX statement is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum OfxAccount {
+    Bank,
+    CreditCard,
+}
+
+/// Transactions of an OFX/QFX file together with the line their `<STMTTRN>` starts at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct OfxStatement {
+    pub(crate) account: OfxAccount,
+    pub(crate) lines: Vec<(u64, StatementLine)>,
+}
+
+impl OfxStatement {
+    /// Ledger accounts the statement is booked against, `account` if given, otherwise the bank
+    /// or credit card account.
+    pub(crate) fn accounts(&self, account: Option<&str>) -> StatementAccounts {
+        let mut accounts = StatementAccounts::default();
+        match (account, self.account) {
+            (Some(account), _) => accounts.account = account.to_string(),
+            (None, OfxAccount::CreditCard) => accounts.account = CREDIT_CARD.to_string(),
+            (None, OfxAccount::Bank) => {}
+        }
+        accounts
+    }
+
+    /// The transactions to book for `client`, bank statements only.
+    pub(crate) fn for_client(self, client: u16) -> Result<Vec<(u64, StatementLine)>, Error> {
+        match self.account {
+            OfxAccount::Bank => Ok(self.lines),
+            OfxAccount::CreditCard => Err(Error::InvalidArgument(format!(
+                "ofx: credit card statements can not be booked for client {client}, import them without --client"
+            ))),
+        }
+    }
+}
+
+/// Reads all bank and credit card transactions (`<STMTTRN>`) of an OFX/QFX file.
+pub(crate) fn read(path: &Path) -> Result<OfxStatement, Error> {
+    // NOTE(juf): OFX 1.x may declare CHARSET:1252, we only deal with UTF-8 (and thereby ASCII)
+    let bytes = std::fs::read(path)?;
+    parse(&String::from_utf8_lossy(&bytes))
+}
+
+/// Parses the transactions, together with the line their `<STMTTRN>` starts at.
+pub(crate) fn parse(input: &str) -> Result<OfxStatement, Error> {
+    let mut lines = Vec::new();
+    let mut account = OfxAccount::Bank;
+    let mut current: Option<(u64, Vec<(String, String)>)> = None;
+    for token in tokenize(input) {
+        match token {
+            Token::Open { name, .. } if name == "CCSTMTRS" => account = OfxAccount::CreditCard,
+            Token::Open { name, offset, .. } if name == "STMTTRN" => {
+                current = Some((line_of(input, offset), Vec::new()));
+            }
+            Token::Open { name, value, .. } => {
+                if let Some((_, fields)) = current.as_mut()
+                    && !value.is_empty()
+                {
+                    fields.push((name, value));
+                }
+            }
+            Token::Close { name } if name == "STMTTRN" => {
+                let Some((line, fields)) = current.take() else {
+                    return Err(Error::InvalidRow(
+                        "ofx: </STMTTRN> without <STMTTRN>".into(),
+                    ));
+                };
+                lines.push((line, to_statement_line(line, &fields)?));
+            }
+            Token::Close { .. } => {}
+        }
+    }
+    if let Some((line, _)) = current {
+        return Err(Error::InvalidRow(format!(
+            "ofx line {line}: <STMTTRN> is never closed"
+        )));
+    }
+    Ok(OfxStatement { account, lines })
+}
+
+fn to_statement_line(line: u64, fields: &[(String, String)]) -> Result<StatementLine, Error> {
+    let field = |name: &str| {
+        fields
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    };
+    let required = |name: &str| {
+        field(name).ok_or_else(|| Error::InvalidRow(format!("ofx line {line}: missing <{name}>")))
+    };
+    let posted = required("DTPOSTED")?;
+    // DTPOSTED is `YYYYMMDD[HHMMSS[.XXX][[gmt offset:tz name]]]`, we only keep the date
+    let date = Date::parse(posted.get(..8).unwrap_or(posted), "%Y%m%d")
+        .map_err(|err| Error::InvalidRow(format!("ofx line {line}: {err}")))?;
+    let fitid = required("FITID")?;
+    fits("<FITID>", fitid)
+        .map_err(|reason| Error::InvalidRow(format!("ofx line {line}: {reason}")))?;
+    let raw_amount = required("TRNAMT")?;
+    // Some banks send a decimal comma, OFX has no thousands separators
+    let amount = Decimal::from_str(&raw_amount.replace(',', ".")).map_err(|_| {
+        Error::InvalidRow(format!("ofx line {line}: invalid amount {raw_amount:?}"))
+    })?;
+    Ok(StatementLine {
+        date,
+        amount,
+        payee: field("NAME").or_else(|| field("PAYEEID")).map(String::from),
+        memo: field("MEMO").map(String::from),
+        external_id: Some(fitid.to_string()),
+        value_date: None,
+        counterparty_account: None,
+        reference: None,
+        cleared: None,
+        splits: Vec::new(),
+    })
+}
+
+enum Token {
+    Open {
+        name: String,
+        value: String,
+        offset: usize,
+    },
+    Close {
+        name: String,
+    },
+}
+
+fn tokenize(input: &str) -> impl Iterator<Item = Token> + '_ {
+    // Skip the OFX 1.x key:value header and the XML prolog/processing instructions of OFX 2.x
+    let start = input
+        .find("<OFX>")
+        .or_else(|| input.find("<ofx>"))
+        .unwrap_or(0);
+    let mut rest = &input[start..];
+    let mut offset = start;
+    std::iter::from_fn(move || {
+        loop {
+            let open = rest.find('<')?;
+            let close = open + rest[open..].find('>')?;
+            let tag = &rest[open + 1..close];
+            let tag_offset = offset + open;
+            let after = &rest[close + 1..];
+            let value_len = after.find('<').unwrap_or(after.len());
+            let value = after[..value_len].trim();
+            offset += close + 1;
+            rest = after;
+            if tag.starts_with('?') || tag.starts_with('!') {
+                continue;
+            }
+            if let Some(name) = tag.strip_prefix('/') {
+                return Some(Token::Close {
+                    name: name.trim().to_ascii_uppercase(),
+                });
+            }
+            return Some(Token::Open {
+                name: tag.trim().to_ascii_uppercase(),
+                value: unescape(value),
+                offset: tag_offset,
+            });
+        }
+    })
+}
+
+fn unescape(value: &str) -> String {
+    if !value.contains('&') {
+        return value.to_string();
+    }
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
+
+fn line_of(input: &str, offset: usize) -> u64 {
+    input[..offset].bytes().filter(|b| *b == b'\n').count() as u64 + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use rust_decimal::dec;
+
+    use crate::{
+        date::Date,
+        events::MAX_TEXT_LEN,
+        ingest::post_statement,
+        ledger::{Journal, LedgerError},
+        objects::statements::{StatementAccounts, StatementLine},
+        ofx::{CREDIT_CARD, OfxAccount, parse, read},
+        processor::{Outcome, Processor},
+    };
+
+    #[test]
+    fn read_sgml_statement() {
+        let statement = read(Path::new("tests/statement-v1.ofx")).expect("valid ofx 1.x");
+        assert_eq!(OfxAccount::Bank, statement.account);
+        let lines = statement.lines;
+        assert_eq!(3, lines.len());
+        assert_eq!(
+            (
+                22,
+                StatementLine {
+                    date: Date::new(2025, 2, 3).expect("valid date"),
+                    amount: dec!(1500.00),
+                    payee: Some("ACME PAYROLL".into()),
+                    memo: Some("Salary February".into()),
+                    external_id: Some("20250203-0001".into()),
+                    value_date: None,
+                    counterparty_account: None,
+                    reference: None,
+                    cleared: None,
+                    splits: Vec::new(),
+                }
+            ),
+            lines[0]
+        );
+        assert_eq!(dec!(-42.17), lines[1].1.amount);
+        assert_eq!(Some("Tom & Jerry's Diner".into()), lines[1].1.payee);
+        assert_eq!(None, lines[2].1.memo);
+    }
+
+    #[test]
+    fn read_xml_statement() {
+        let statement = read(Path::new("tests/statement-v2.ofx")).expect("valid ofx 2.x");
+        assert_eq!(OfxAccount::CreditCard, statement.account);
+        let lines = statement.lines;
+        assert_eq!(2, lines.len());
+        assert_eq!(dec!(-19.99), lines[0].1.amount);
+        assert_eq!(Some("STREAMING CO".into()), lines[0].1.payee);
+        assert_eq!(Some("CC-7781".into()), lines[0].1.external_id);
+        assert_eq!(Date::new(2025, 2, 11).expect("valid date"), lines[1].1.date);
+    }
+
+    #[test]
+    fn missing_fields_are_errors() {
+        let doc = "<OFX><STMTTRN><TRNTYPE>DEBIT<DTPOSTED>20250101<TRNAMT>1.0</STMTTRN></OFX>";
+        assert!(parse(doc).is_err(), "FITID is required for deduplication");
+        let doc = "<OFX><STMTTRN><FITID>1<DTPOSTED>20250101</STMTTRN></OFX>";
+        assert!(parse(doc).is_err());
+        let doc = "<OFX><STMTTRN><FITID>1<DTPOSTED>20250101<TRNAMT>1.0</OFX>";
+        assert!(parse(doc).is_err());
+        let fitid = "9".repeat(MAX_TEXT_LEN + 1);
+        let doc =
+            format!("<OFX><STMTTRN><FITID>{fitid}<DTPOSTED>20250101<TRNAMT>1.0</STMTTRN></OFX>");
+        assert!(
+            parse(&doc).is_err(),
+            "FITID does not fit into the event log"
+        );
+    }
+
+    #[test]
+    fn reimport_is_deduplicated_by_fitid() {
+        let mut p = Processor::new();
+        for _ in 0..2 {
+            let outcomes: Vec<_> = read(Path::new("tests/statement-v1.ofx"))
+                .expect("valid ofx 1.x")
+                .for_client(1)
+                .expect("bank statement")
+                .into_iter()
+                .map(|(_, line)| p.process_statement_line(1, line).expect("can process"))
+                .collect();
+            assert_eq!(3, outcomes.len());
+        }
+        let acc = p.get_account_store_ref().get(&1).expect("account exists");
+        assert_eq!(dec!(1357.83), acc.available);
+        let again = read(Path::new("tests/statement-v1.ofx"))
+            .expect("valid ofx 1.x")
+            .lines
+            .remove(0)
+            .1;
+        assert!(matches!(
+            p.process_statement_line(1, again).expect("can process"),
+            Outcome::Rejected(_)
+        ));
+    }
+
+    #[test]
+    fn reimport_into_journal_is_deduplicated_by_fitid() {
+        let mut journal = Journal::new();
+        let mut refused = Vec::new();
+        for _ in 0..2 {
+            let statement = read(Path::new("tests/statement-v2.ofx")).expect("valid ofx 2.x");
+            let accounts = statement.accounts(None);
+            post_statement(
+                statement.lines.into_iter().map(Ok),
+                None,
+                &accounts,
+                &mut journal,
+                |_, err| refused.push(err),
+            )
+            .expect("valid statement");
+        }
+        assert_eq!(2, journal.entries().len());
+        assert_eq!(
+            vec![
+                LedgerError::DuplicateExternalId("CC-7781".into()),
+                LedgerError::DuplicateExternalId("CC-7790".into())
+            ],
+            refused
+        );
+        assert_eq!(dec!(0), journal.balance(CREDIT_CARD));
+    }
+
+    #[test]
+    fn card_statements_are_booked_against_a_liability() {
+        let statement = read(Path::new("tests/statement-v2.ofx")).expect("valid ofx 2.x");
+        assert_eq!(
+            CREDIT_CARD,
+            statement.accounts(None).account,
+            "card balances are owed"
+        );
+        assert_eq!(
+            "Liabilities:Visa",
+            statement.accounts(Some("Liabilities:Visa")).account
+        );
+        let mut journal = Journal::new();
+        let mut charge = statement.clone();
+        charge.lines.truncate(1);
+        post_statement(
+            charge.lines.into_iter().map(Ok),
+            None,
+            &statement.accounts(None),
+            &mut journal,
+            |line, err| panic!("line {line} refused: {err}"),
+        )
+        .expect("valid statement");
+        assert_eq!(dec!(-19.99), journal.balance(CREDIT_CARD), "19.99 owed");
+        assert_eq!(dec!(19.99), journal.balance("Expenses:Uncategorized"));
+        assert!(statement.for_client(1).is_err());
+        let bank = read(Path::new("tests/statement-v1.ofx")).expect("valid ofx 1.x");
+        assert_eq!(StatementAccounts::default(), bank.accounts(None));
+    }
+}
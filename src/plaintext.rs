@@ -0,0 +1,1051 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+    io::Write,
+    str::FromStr,
+};
+
+use rust_decimal::Decimal;
+
+use crate::{
+    amount::Precision,
+    date::Date,
+    error::Error,
+    ledger::{AccountType, Journal, JournalEntry, Posting},
+    objects::transactions::TxType,
+};
+
+// NOTE(juf): Plain text accounting formats, Beancount and the ledger-cli/hledger journal. Both get
+// the full history, ordered by date, so the tools recompute every balance themselves and the
+// assertions we write at the end check our numbers against theirs. Our amounts carry no
+// commodity, the caller names the one to write. Client transactions carry no date, they are
+// written at `undated`.
+
+/// Journal entries in date order together with what both formats need to know up front.
+struct History<'a> {
+    entries: Vec<(Date, &'a JournalEntry)>,
+    /// Every posted account with the date of its first posting.
+    opened: BTreeMap<&'a str, Date>,
+}
+
+impl<'a> History<'a> {
+    fn of(journal: &'a Journal, undated: Date) -> Self {
+        let mut entries: Vec<_> = journal
+            .entries()
+            .iter()
+            .map(|entry| (entry.date().unwrap_or(undated), entry))
+            .collect();
+        // Stable, entries of the same day keep the order they were posted in
+        entries.sort_by_key(|(date, _)| *date);
+        let mut opened = BTreeMap::new();
+        for (date, entry) in entries.iter() {
+            for posting in entry.postings() {
+                opened.entry(posting.account.as_str()).or_insert(*date);
+            }
+        }
+        Self { entries, opened }
+    }
+
+    fn last_date(&self) -> Option<Date> {
+        self.entries.last().map(|(date, _)| *date)
+    }
+}
+
+/// Writes the journal as Beancount file: `open` directives for every account, one transaction per
+/// entry with its tx id, type and bank details as metadata, and a `balance` assertion for every
+/// account on the day after the last entry. Amounts are rounded to `precision`.
+pub(crate) fn write_beancount(
+    journal: &Journal,
+    commodity: &str,
+    undated: Date,
+    precision: Precision,
+    out: &mut impl Write,
+) -> Result<(), Error> {
+    let history = History::of(journal, undated);
+    writeln!(out, "option \"operating_currency\" \"{commodity}\"")?;
+    writeln!(out)?;
+    for (account, date) in history.opened.iter() {
+        writeln!(
+            out,
+            "{date} open {} {commodity}",
+            beancount_account(account)
+        )?;
+    }
+    for (date, entry) in history.entries.iter() {
+        writeln!(out)?;
+        write!(out, "{date} *")?;
+        if let Some(payee) = entry.payee() {
+            write!(out, " \"{}\"", beancount_string(payee))?;
+        }
+        writeln!(
+            out,
+            " \"{}\" #{}",
+            beancount_string(entry.narration().unwrap_or_default()),
+            entry.action().name()
+        )?;
+        writeln!(out, "  tx: {}", entry.tx_id())?;
+        if let Some(external_id) = entry.external_id() {
+            writeln!(out, "  external_id: \"{}\"", beancount_string(external_id))?;
+        }
+        for (key, value) in entry.metadata() {
+            writeln!(out, "  {key}: \"{}\"", beancount_string(value))?;
+        }
+        for posting in entry.postings() {
+            writeln!(
+                out,
+                "  {}  {} {commodity}",
+                beancount_account(&posting.account),
+                precision.round(posting.amount)
+            )?;
+        }
+    }
+    if let Some(last) = history.last_date() {
+        // Beancount checks balances at the start of the day and includes sub-accounts
+        let rolled_up = journal.rolled_up_balances();
+        writeln!(out)?;
+        for account in history.opened.keys() {
+            let balance = precision.round(rolled_up.get(*account).copied().unwrap_or_default());
+            writeln!(
+                out,
+                "{} balance {}  {balance} {commodity}",
+                last.next_day(),
+                beancount_account(account)
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the journal as ledger-cli/hledger journal: `account` directives for every account, one
+/// transaction per entry with its tx id, type and bank details as tags, and a balance assertion
+/// on the last posting of every account. Amounts are rounded to `precision`.
+pub(crate) fn write_ledger(
+    journal: &Journal,
+    commodity: &str,
+    undated: Date,
+    precision: Precision,
+    out: &mut impl Write,
+) -> Result<(), Error> {
+    let history = History::of(journal, undated);
+    for account in history.opened.keys() {
+        writeln!(out, "account {account}")?;
+    }
+    // Position of the last posting to every account, which carries the balance assertion
+    let mut last_posting = BTreeMap::new();
+    for (idx, (_, entry)) in history.entries.iter().enumerate() {
+        for (posting_idx, posting) in entry.postings().iter().enumerate() {
+            last_posting.insert(posting.account.as_str(), (idx, posting_idx));
+        }
+    }
+    let mut balances: BTreeMap<&str, Decimal> = BTreeMap::new();
+    for (idx, (date, entry)) in history.entries.iter().enumerate() {
+        writeln!(out)?;
+        // hledger's `payee | note`, a lone payee keeps its `|` so it is read back as payee
+        let description = match (entry.payee(), entry.narration()) {
+            (Some(payee), Some(narration)) => format!(" {payee} | {narration}"),
+            (Some(payee), None) => format!(" {payee} |"),
+            (None, Some(narration)) => format!(" {narration}"),
+            (None, None) => String::new(),
+        };
+        writeln!(out, "{date} *{}", single_line(&description))?;
+        writeln!(out, "    ; tx: {}", entry.tx_id())?;
+        writeln!(out, "    ; type: {}", entry.action().name())?;
+        if let Some(external_id) = entry.external_id() {
+            writeln!(out, "    ; external_id: {}", single_line(external_id))?;
+        }
+        for (key, value) in entry.metadata() {
+            writeln!(out, "    ; {key}: {}", single_line(value))?;
+        }
+        for (posting_idx, posting) in entry.postings().iter().enumerate() {
+            let balance = balances.entry(posting.account.as_str()).or_default();
+            *balance += posting.amount;
+            write!(
+                out,
+                "    {}  {} {commodity}",
+                posting.account,
+                precision.round(posting.amount)
+            )?;
+            if last_posting.get(posting.account.as_str()) == Some(&(idx, posting_idx)) {
+                write!(out, " = {} {commodity}", precision.round(*balance))?;
+            }
+            writeln!(out)?;
+        }
+    }
+    Ok(())
+}
+
+/// Books a Beancount or ledger-cli/hledger journal into `journal`. Supported are dated
+/// transactions with postings, one of which may elide its amount, `open`/`close` and ledger's
+/// `account` directives, Beancount `balance` directives and ledger's `= amount` assertions and
+/// assignments on postings. Metadata, `key: value` in Beancount and `; key: value` in ledger, is
+/// kept with the entry, `tx`, `type` and `external_id` as written by [`write_beancount`] and
+/// [`write_ledger`] restore the entry as it was. Everything that would change the numbers, but
+/// is not supported, e.g., `pad`, costs, prices or `include`, is refused instead of skipped.
+pub(crate) fn import(input: &str, journal: &mut Journal) -> Result<(), Error> {
+    let mut directives = parse(input)?;
+    // Opens come first, balances are checked at the start of the day, closes at its end
+    directives.sort_by_key(|dated| (dated.date, dated.directive.rank()));
+    let declares = directives
+        .iter()
+        .any(|dated| matches!(dated.directive, Directive::Open(_)));
+    // Declared accounts with the day they were closed on, if any
+    let mut declared: HashMap<String, Option<Date>> = HashMap::new();
+    for Dated {
+        line,
+        date,
+        directive,
+    } in directives
+    {
+        match directive {
+            Directive::Open(account) => {
+                AccountType::of(&account).map_err(|err| invalid(line, err))?;
+                if declared.insert(account.clone(), None).is_some() {
+                    return Err(invalid(line, format!("{account} is opened twice")));
+                }
+            }
+            Directive::Close(account) => match declared.get_mut(&account) {
+                Some(closed @ None) => *closed = date,
+                Some(Some(_)) => return Err(invalid(line, format!("{account} is closed twice"))),
+                None => return Err(invalid(line, format!("{account} is never opened"))),
+            },
+            Directive::Balance { account, amount } => {
+                let actual = journal.rolled_up_balance(&account);
+                if actual != amount {
+                    return Err(Error::BalanceMismatch {
+                        statement: format!("line {line}"),
+                        kind: "asserted",
+                        expected: amount,
+                        actual,
+                    });
+                }
+            }
+            Directive::Booking(booking) => {
+                if declares {
+                    for posting in booking.postings.iter() {
+                        match declared.get(&posting.account) {
+                            Some(Some(closed)) if date > Some(*closed) => {
+                                return Err(invalid(
+                                    posting.line,
+                                    format!("{} is closed since {closed}", posting.account),
+                                ));
+                            }
+                            Some(_) => {}
+                            None => {
+                                return Err(invalid(
+                                    posting.line,
+                                    format!("{} is never opened", posting.account),
+                                ));
+                            }
+                        }
+                    }
+                }
+                book(line, date, booking, journal)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn book(
+    line: u64,
+    date: Option<Date>,
+    booking: Booking,
+    journal: &mut Journal,
+) -> Result<(), Error> {
+    let mut postings = Vec::with_capacity(booking.postings.len());
+    let mut elided = None;
+    for posting in booking.postings.iter() {
+        let amount = match (posting.amount, posting.assertion) {
+            (Some(amount), _) => Some(amount),
+            // Balance assignment, the posting brings the account to the asserted balance
+            (None, Some(balance)) => Some(balance - journal.balance(&posting.account)),
+            (None, None) => None,
+        };
+        match amount {
+            Some(amount) => postings.push(Posting::new(&posting.account, amount)),
+            None if elided.is_none() => {
+                elided = Some(postings.len());
+                postings.push(Posting::new(&posting.account, Decimal::ZERO));
+            }
+            None => {
+                return Err(invalid(
+                    posting.line,
+                    "only one posting may elide its amount",
+                ));
+            }
+        }
+    }
+    if let Some(idx) = elided {
+        let sum: Decimal = postings.iter().map(|posting| posting.amount).sum();
+        postings[idx].amount = -sum;
+    }
+    let mut tx_id = None;
+    let mut action = None;
+    let mut external_id = None;
+    let mut meta = Vec::new();
+    for (key, value) in booking.meta {
+        match key.as_str() {
+            "tx" => {
+                let id = value
+                    .parse()
+                    .map_err(|_| invalid(line, format!("invalid tx id {value:?}")))?;
+                tx_id = Some(id);
+            }
+            "type" => {
+                let kind = tx_type(&value)
+                    .ok_or_else(|| invalid(line, format!("unknown type {value:?}")))?;
+                action = Some(kind);
+            }
+            "external_id" => external_id = Some(value),
+            _ => meta.push((key, value)),
+        }
+    }
+    let tx_id = match tx_id {
+        Some(tx_id) => tx_id,
+        // Same as for statements, the line doubles as tx id
+        None => u32::try_from(line).map_err(|_| invalid(line, "line exceeds the tx id space"))?,
+    };
+    let action = action.unwrap_or_else(|| {
+        match postings
+            .first()
+            .is_some_and(|posting| posting.amount.is_sign_negative())
+        {
+            true => TxType::Withdrawal,
+            false => TxType::Deposit,
+        }
+    });
+    let mut entry = JournalEntry::new(tx_id, action, postings)
+        .map_err(|err| invalid(line, err))?
+        .describe(date, booking.payee, booking.narration)
+        .with_external_id(external_id);
+    for (key, value) in meta {
+        entry = entry.with_meta(&key, Some(value));
+    }
+    journal.post(entry).map_err(|err| invalid(line, err))?;
+    for posting in booking.postings {
+        if let Some(expected) = posting.assertion {
+            let actual = journal.balance(&posting.account);
+            if actual != expected {
+                return Err(Error::BalanceMismatch {
+                    statement: format!("line {}", posting.line),
+                    kind: "asserted",
+                    expected,
+                    actual,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+fn tx_type(name: &str) -> Option<TxType> {
+    [
+        TxType::Deposit,
+        TxType::Withdrawal,
+        TxType::Dispute,
+        TxType::Resolve,
+        TxType::Chargeback,
+        TxType::Unlock,
+    ]
+    .into_iter()
+    .find(|kind| kind.name() == name)
+}
+
+/// Directive together with where it was found. Only ledger's `account` has no date.
+struct Dated {
+    line: u64,
+    date: Option<Date>,
+    directive: Directive,
+}
+
+enum Directive {
+    /// Beancount `open` or ledger `account`
+    Open(String),
+    Close(String),
+    /// Balance of the account including its children at the start of the day.
+    Balance {
+        account: String,
+        amount: Decimal,
+    },
+    Booking(Booking),
+}
+
+impl Directive {
+    /// Order of directives on the same day.
+    fn rank(&self) -> u8 {
+        match self {
+            Directive::Open(_) => 0,
+            Directive::Balance { .. } => 1,
+            Directive::Booking(_) => 2,
+            Directive::Close(_) => 3,
+        }
+    }
+}
+
+struct Booking {
+    payee: Option<String>,
+    narration: Option<String>,
+    meta: Vec<(String, String)>,
+    postings: Vec<ParsedPosting>,
+}
+
+struct ParsedPosting {
+    line: u64,
+    account: String,
+    /// `None` if elided.
+    amount: Option<Decimal>,
+    /// Balance of the account, excluding its children, after the posting.
+    assertion: Option<Decimal>,
+}
+
+fn parse(input: &str) -> Result<Vec<Dated>, Error> {
+    let mut directives: Vec<Dated> = Vec::new();
+    // NOTE(juf): Our amounts carry no commodity, so books have to stick to a single one
+    let mut commodity = None;
+    let mut in_booking = false;
+    for (text, line) in input.lines().zip(1..) {
+        let text = text.trim_end();
+        let content = text.trim_start();
+        if content.is_empty() {
+            in_booking = false;
+            continue;
+        }
+        if text.starts_with([' ', '\t']) {
+            // Indented lines belong to the directive above, only bookings have any we use
+            if in_booking
+                && let Some(Dated {
+                    directive: Directive::Booking(booking),
+                    ..
+                }) = directives.last_mut()
+            {
+                parse_booking_line(booking, content, line, &mut commodity)?;
+            }
+            continue;
+        }
+        in_booking = false;
+        if content.starts_with([';', '#', '%', '|', '*']) {
+            continue;
+        }
+        if content.starts_with(|c: char| c.is_ascii_digit()) {
+            if let Some(dated) = parse_dated(content, line, &mut commodity)? {
+                in_booking = matches!(dated.directive, Directive::Booking(_));
+                directives.push(dated);
+            }
+            continue;
+        }
+        let (keyword, rest) = content
+            .split_once(char::is_whitespace)
+            .unwrap_or((content, ""));
+        match keyword {
+            "account" => directives.push(Dated {
+                line,
+                date: None,
+                directive: Directive::Open(split_comment(rest).0.trim().to_string()),
+            }),
+            // Presentation only, they do not change any number
+            "option" | "plugin" | "commodity" | "payee" | "tag" | "P" | "decimal-mark" => {}
+            _ => return Err(invalid(line, format!("unsupported directive {keyword:?}"))),
+        }
+    }
+    Ok(directives)
+}
+
+fn parse_dated(
+    content: &str,
+    line: u64,
+    commodity: &mut Option<String>,
+) -> Result<Option<Dated>, Error> {
+    let (date, rest) = content
+        .split_once(char::is_whitespace)
+        .unwrap_or((content, ""));
+    // ledger's auxiliary date, `2025-03-01=2025-03-03`, is dropped
+    let date = date.split('=').next().unwrap_or_default().replace('/', "-");
+    let date = Date::from_str(&date).map_err(|err| invalid(line, err))?;
+    let rest = rest.trim_start();
+    let (keyword, args) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    let first_arg = || {
+        split_comment(args)
+            .0
+            .split_whitespace()
+            .next()
+            .map(String::from)
+            .ok_or_else(|| invalid(line, format!("{keyword} requires an account")))
+    };
+    let directive = match keyword {
+        "open" => Directive::Open(first_arg()?),
+        "close" => Directive::Close(first_arg()?),
+        "balance" => {
+            let account = first_arg()?;
+            let amount = split_comment(args).0.trim_start()[account.len()..].trim();
+            // Beancount allows a tolerance, `100.00 ~ 0.01 USD`, which we do not
+            if amount.contains('~') {
+                return Err(invalid(line, "balance tolerances are not supported"));
+            }
+            Directive::Balance {
+                account,
+                amount: parse_amount(amount, commodity).map_err(|err| invalid(line, err))?,
+            }
+        }
+        "price" | "note" | "event" | "document" | "custom" | "query" | "commodity" => {
+            return Ok(None);
+        }
+        "pad" => return Err(invalid(line, "pad directives are not supported")),
+        _ => Directive::Booking(parse_header(rest).map_err(|err| invalid(line, err))?),
+    };
+    Ok(Some(Dated {
+        line,
+        date: Some(date),
+        directive,
+    }))
+}
+
+/// Everything after the date of a transaction, Beancount `* "payee" "narration" #tag ^link` or
+/// ledger `* (code) payee | note ; key: value`.
+fn parse_header(rest: &str) -> Result<Booking, String> {
+    let mut rest = rest.trim_start();
+    if let Some(after) = rest.strip_prefix("txn")
+        && (after.is_empty() || after.starts_with(char::is_whitespace))
+    {
+        rest = after.trim_start();
+    } else if let Some(after) = rest.strip_prefix(['*', '!']) {
+        rest = after.trim_start();
+    }
+    let mut booking = Booking {
+        payee: None,
+        narration: None,
+        meta: Vec::new(),
+        postings: Vec::new(),
+    };
+    if let Some(after) = rest.strip_prefix('(')
+        && let Some((code, after)) = after.split_once(')')
+    {
+        booking.meta.push(("code".into(), code.trim().to_string()));
+        rest = after.trim_start();
+    }
+    let non_empty = |text: &str| {
+        let text = text.trim();
+        (!text.is_empty()).then(|| text.to_string())
+    };
+    if rest.starts_with('"') {
+        let mut strings = Vec::new();
+        while let Some(after) = rest.strip_prefix('"') {
+            let (string, after) = read_string(after)?;
+            strings.push(string);
+            rest = after.trim_start();
+        }
+        (booking.payee, booking.narration) = match strings.as_slice() {
+            [narration] => (None, non_empty(narration)),
+            [payee, narration] => (non_empty(payee), non_empty(narration)),
+            _ => return Err("expected at most a payee and a narration".into()),
+        };
+        let mut tags = Vec::new();
+        let mut links = Vec::new();
+        for token in split_comment(rest).0.split_whitespace() {
+            match (token.strip_prefix('#'), token.strip_prefix('^')) {
+                (Some(tag), _) if tx_type(tag).is_some() => {
+                    booking.meta.push(("type".into(), tag.to_string()));
+                }
+                (Some(tag), _) => tags.push(tag),
+                (_, Some(link)) => links.push(link),
+                _ => return Err(format!("unexpected {token:?} after the narration")),
+            }
+        }
+        if !tags.is_empty() {
+            booking.meta.push(("tags".into(), tags.join(" ")));
+        }
+        if !links.is_empty() {
+            booking.meta.push(("links".into(), links.join(" ")));
+        }
+    } else {
+        let (description, comment) = split_comment(rest);
+        if let Some((key, value)) = comment.and_then(key_value) {
+            booking.meta.push((key, value));
+        }
+        match description.split_once('|') {
+            Some((payee, note)) => {
+                booking.payee = non_empty(payee);
+                booking.narration = non_empty(note);
+            }
+            None => booking.narration = non_empty(description),
+        }
+    }
+    Ok(booking)
+}
+
+fn parse_booking_line(
+    booking: &mut Booking,
+    content: &str,
+    line: u64,
+    commodity: &mut Option<String>,
+) -> Result<(), Error> {
+    if let Some(comment) = content.strip_prefix(';') {
+        booking.meta.extend(key_value(comment));
+        return Ok(());
+    }
+    // Beancount metadata, keys start lower case where accounts start upper case
+    if content.starts_with(|c: char| c.is_ascii_lowercase()) {
+        let (key, value) = key_value(content)
+            .ok_or_else(|| invalid(line, format!("invalid metadata {content:?}")))?;
+        let value = match value.strip_prefix('"') {
+            Some(quoted) => read_string(quoted).map_err(|err| invalid(line, err))?.0,
+            None => value,
+        };
+        booking.meta.push((key, value));
+        return Ok(());
+    }
+    let content = content
+        .strip_prefix(['*', '!'])
+        .map_or(content, str::trim_start);
+    if content.starts_with(['(', '[']) {
+        return Err(invalid(line, "virtual postings are not supported"));
+    }
+    let (body, comment) = split_comment(content);
+    booking.meta.extend(comment.and_then(key_value));
+    let body = body.trim_end();
+    // ledger ends the account at two spaces or a tab, Beancount accounts have no spaces at all
+    let (account, amounts) = match body.find("  ").or_else(|| body.find('\t')) {
+        Some(idx) => (&body[..idx], body[idx..].trim()),
+        None => match body.split_once(' ') {
+            Some((account, amounts)) if parse_amounts(amounts, &mut commodity.clone()).is_ok() => {
+                (account, amounts.trim())
+            }
+            _ => (body, ""),
+        },
+    };
+    let (amount, assertion) =
+        parse_amounts(amounts, commodity).map_err(|err| invalid(line, err))?;
+    booking.postings.push(ParsedPosting {
+        line,
+        account: account.to_string(),
+        amount,
+        assertion,
+    });
+    Ok(())
+}
+
+/// Amount and balance assertion of a posting, `100.00 USD = 250.00 USD`, both optional.
+fn parse_amounts(
+    text: &str,
+    commodity: &mut Option<String>,
+) -> Result<(Option<Decimal>, Option<Decimal>), String> {
+    if text.contains(['@', '{']) {
+        return Err("costs and prices are not supported".into());
+    }
+    let (amount, assertion) = match text.split_once('=') {
+        Some((amount, assertion)) => (amount.trim(), Some(assertion.trim())),
+        None => (text.trim(), None),
+    };
+    let amount = match amount {
+        "" => None,
+        amount => Some(parse_amount(amount, commodity)?),
+    };
+    let assertion = assertion
+        .map(|assertion| parse_amount(assertion, commodity))
+        .transpose()?;
+    Ok((amount, assertion))
+}
+
+/// `100.00 USD`, `USD 100.00`, `$1,000.00` or a bare number.
+fn parse_amount(text: &str, commodity: &mut Option<String>) -> Result<Decimal, String> {
+    let mut number = None;
+    let mut symbol = String::new();
+    for token in text.split_whitespace() {
+        let digits: String = token
+            .chars()
+            .filter(|c| c.is_ascii_digit() || matches!(c, '-' | '+' | '.'))
+            .collect();
+        symbol.extend(
+            token
+                .chars()
+                .filter(|c| !(c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | ','))),
+        );
+        if digits.is_empty() {
+            continue;
+        }
+        if number.is_some() {
+            return Err(format!("invalid amount {text:?}"));
+        }
+        number = Some(Decimal::from_str(&digits).map_err(|_| format!("invalid amount {text:?}"))?);
+    }
+    if !symbol.is_empty() {
+        match commodity {
+            Some(known) if *known != symbol => {
+                return Err(format!(
+                    "mixed commodities {known} and {symbol} are not supported"
+                ));
+            }
+            Some(_) => {}
+            None => *commodity = Some(symbol),
+        }
+    }
+    number.ok_or_else(|| format!("invalid amount {text:?}"))
+}
+
+/// Splits off a `;` comment.
+fn split_comment(text: &str) -> (&str, Option<&str>) {
+    match text.split_once(';') {
+        Some((text, comment)) => (text, Some(comment)),
+        None => (text, None),
+    }
+}
+
+/// `key: value`, where the key holds no whitespace.
+fn key_value(text: &str) -> Option<(String, String)> {
+    let (key, value) = text.trim().split_once(':')?;
+    (!key.is_empty() && !key.contains(char::is_whitespace))
+        .then(|| (key.to_string(), value.trim().to_string()))
+}
+
+/// Reads a Beancount string up to its closing quote, returns it and what follows.
+fn read_string(text: &str) -> Result<(String, &str), String> {
+    let mut value = String::new();
+    let mut chars = text.char_indices();
+    while let Some((idx, c)) = chars.next() {
+        match c {
+            '"' => return Ok((value, &text[idx + 1..])),
+            '\\' => match chars.next() {
+                Some((_, 'n')) => value.push('\n'),
+                Some((_, escaped)) => value.push(escaped),
+                None => break,
+            },
+            c => value.push(c),
+        }
+    }
+    Err("unterminated string".into())
+}
+
+fn invalid(line: u64, reason: impl Display) -> Error {
+    Error::InvalidRow(format!("journal line {line}: {reason}"))
+}
+
+/// Beancount account components start with a capital letter or digit and only hold letters,
+/// digits and dashes, anything else is replaced.
+fn beancount_account(account: &str) -> String {
+    account
+        .split(':')
+        .map(|component| {
+            let mut sanitized: String = component
+                .chars()
+                .map(|c| {
+                    if c.is_alphanumeric() || c == '-' {
+                        c
+                    } else {
+                        '-'
+                    }
+                })
+                .collect();
+            match sanitized.chars().next() {
+                Some(first) if first.is_ascii_digit() || first.is_uppercase() => {}
+                Some(first) if first.is_lowercase() => {
+                    let upper: String = first.to_uppercase().collect();
+                    sanitized.replace_range(..first.len_utf8(), &upper);
+                }
+                _ => sanitized.insert(0, 'X'),
+            }
+            sanitized
+        })
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+fn beancount_string(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn single_line(value: &str) -> String {
+    value.replace(['\r', '\n'], " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use rust_decimal::dec;
+
+    use crate::{
+        amount::{OverPrecise, Precision, Rounding},
+        camt,
+        date::Date,
+        error::Error,
+        ingest::post_statement,
+        ledger::{Journal, JournalEntry, Posting},
+        objects::{
+            statements::StatementAccounts,
+            transactions::{InitialState, TxType},
+        },
+        plaintext::{beancount_account, import, write_beancount, write_ledger},
+        policy::WithdrawalDisputes,
+    };
+
+    const CENTS: Precision = Precision {
+        scale: 2,
+        rounding: Rounding::Bankers,
+        over_precise: OverPrecise::Reject,
+    };
+
+    fn journal() -> Journal {
+        let mut journal = Journal::new();
+        let salary = JournalEntry::new(
+            7,
+            TxType::Deposit,
+            vec![
+                Posting::new("Assets:Bank", dec!(1500.00)),
+                Posting::new("Income:Salary", dec!(-1500.00)),
+            ],
+        )
+        .expect("balanced")
+        .describe(
+            Date::new(2025, 3, 3).ok(),
+            Some("ACME \"Corp\"".into()),
+            Some("Salary".into()),
+        )
+        .with_external_id(Some("REF-1".into()))
+        .with_meta("reference", Some("E2E-1".into()));
+        journal.post(salary).expect("can post");
+        let client = JournalEntry::for_transaction(
+            1,
+            1,
+            TxType::Deposit,
+            InitialState::Deposit,
+            dec!(5),
+            WithdrawalDisputes::default(),
+        )
+        .expect("moves funds");
+        journal.post(client).expect("can post");
+        let rent = JournalEntry::new(
+            8,
+            TxType::Withdrawal,
+            vec![
+                Posting::new("Assets:Bank", dec!(-300.00)),
+                Posting::new("Expenses:Rent", dec!(300.00)),
+            ],
+        )
+        .expect("balanced")
+        .describe(Date::new(2025, 3, 1).ok(), None, None);
+        journal.post(rent).expect("can post");
+        journal
+    }
+
+    #[test]
+    fn beancount_export() {
+        let mut out = Vec::new();
+        let undated = Date::new(2025, 3, 31).expect("valid date");
+        write_beancount(&journal(), "EUR", undated, CENTS, &mut out).expect("can write");
+        let expected = r#"option "operating_currency" "EUR"
+
+2025-03-01 open Assets:Bank EUR
+2025-03-01 open Expenses:Rent EUR
+2025-03-03 open Income:Salary EUR
+2025-03-31 open Liabilities:Clients:1:Available EUR
+
+2025-03-01 * "" #withdrawal
+  tx: 8
+  Assets:Bank  -300.00 EUR
+  Expenses:Rent  300.00 EUR
+
+2025-03-03 * "ACME \"Corp\"" "Salary" #deposit
+  tx: 7
+  external_id: "REF-1"
+  reference: "E2E-1"
+  Assets:Bank  1500.00 EUR
+  Income:Salary  -1500.00 EUR
+
+2025-03-31 * "" #deposit
+  tx: 1
+  Assets:Bank  5.00 EUR
+  Liabilities:Clients:1:Available  -5.00 EUR
+
+2025-04-01 balance Assets:Bank  1205.00 EUR
+2025-04-01 balance Expenses:Rent  300.00 EUR
+2025-04-01 balance Income:Salary  -1500.00 EUR
+2025-04-01 balance Liabilities:Clients:1:Available  -5.00 EUR
+"#;
+        assert_eq!(expected, String::from_utf8(out).expect("utf-8"));
+    }
+
+    #[test]
+    fn ledger_export() {
+        let mut out = Vec::new();
+        let undated = Date::new(2025, 3, 31).expect("valid date");
+        write_ledger(&journal(), "EUR", undated, CENTS, &mut out).expect("can write");
+        let expected = "account Assets:Bank
+account Expenses:Rent
+account Income:Salary
+account Liabilities:Clients:1:Available
+
+2025-03-01 *
+    ; tx: 8
+    ; type: withdrawal
+    Assets:Bank  -300.00 EUR
+    Expenses:Rent  300.00 EUR = 300.00 EUR
+
+2025-03-03 * ACME \"Corp\" | Salary
+    ; tx: 7
+    ; type: deposit
+    ; external_id: REF-1
+    ; reference: E2E-1
+    Assets:Bank  1500.00 EUR
+    Income:Salary  -1500.00 EUR = -1500.00 EUR
+
+2025-03-31 *
+    ; tx: 1
+    ; type: deposit
+    Assets:Bank  5.00 EUR = 1205.00 EUR
+    Liabilities:Clients:1:Available  -5.00 EUR = -5.00 EUR
+";
+        assert_eq!(expected, String::from_utf8(out).expect("utf-8"));
+    }
+
+    #[test]
+    fn beancount_account_names_are_sanitized() {
+        assert_eq!(
+            "Expenses:Food-Dining",
+            beancount_account("Expenses:Food&Dining")
+        );
+        assert_eq!("Assets:Savings", beancount_account("Assets:savings"));
+        assert_eq!(
+            "Liabilities:Clients:1:Held",
+            beancount_account("Liabilities:Clients:1:Held")
+        );
+        assert_eq!("Income:X-Misc", beancount_account("Income:_Misc"));
+    }
+
+    fn imported(input: &str) -> Result<Journal, Error> {
+        let mut journal = Journal::new();
+        import(input, &mut journal)?;
+        Ok(journal)
+    }
+
+    #[test]
+    fn exports_import_as_they_were_written() {
+        let statement = camt::read(Path::new("tests/statement.camt053.xml"))
+            .expect("valid camt.053")
+            .remove(0);
+        let mut journal = Journal::new();
+        post_statement(
+            statement.lines.into_iter().map(Ok),
+            Some(&statement.balances),
+            &StatementAccounts::default(),
+            &mut journal,
+            |line, err| panic!("line {line} refused: {err}"),
+        )
+        .expect("balances reconcile");
+        let undated = Date::new(2025, 3, 31).expect("valid date");
+
+        let mut beancount = Vec::new();
+        write_beancount(&journal, "EUR", undated, CENTS, &mut beancount).expect("can write");
+        let from_beancount =
+            imported(&String::from_utf8(beancount).expect("utf-8")).expect("valid beancount");
+        assert_eq!(journal.entries(), from_beancount.entries());
+
+        let mut ledger = Vec::new();
+        write_ledger(&journal, "EUR", undated, CENTS, &mut ledger).expect("can write");
+        let from_ledger =
+            imported(&String::from_utf8(ledger).expect("utf-8")).expect("valid ledger");
+        assert_eq!(journal.entries(), from_ledger.entries());
+    }
+
+    #[test]
+    fn import_beancount() {
+        let journal = imported(
+            r#"option "operating_currency" "EUR"
+; a comment
+2025-01-01 open Assets:Bank EUR
+2025-01-01 open Expenses:Food
+2025-01-01 open Equity:Opening-Balances
+2025-03-31 close Expenses:Food
+
+2025-01-01 * "Opening" #deposit
+  Assets:Bank  100.00 EUR
+  Equity:Opening-Balances
+
+2025-02-01 balance Assets:Bank  100.00 EUR
+
+2025-02-03 txn "Bakery" "Bread" #food ^receipt-1
+  note: "fresh"
+  Assets:Bank  -3.50 EUR
+  Expenses:Food  3.50 EUR
+"#,
+        )
+        .expect("valid beancount");
+        assert_eq!(dec!(96.50), journal.balance("Assets:Bank"));
+        assert_eq!(dec!(-100.00), journal.balance("Equity:Opening-Balances"));
+        let bread = &journal.entries()[1];
+        assert_eq!(Some("Bakery"), bread.payee());
+        assert_eq!(Some("Bread"), bread.narration());
+        assert_eq!(TxType::Withdrawal, bread.action());
+        assert_eq!(Some("fresh"), bread.meta("note"));
+        assert_eq!(Some("food"), bread.meta("tags"));
+        assert_eq!(Some("receipt-1"), bread.meta("links"));
+        assert_eq!(14, bread.tx_id(), "line doubles as tx id");
+    }
+
+    #[test]
+    fn import_ledger() {
+        let journal = imported(
+            "2025/01/01 * Opening
+    Assets:Bank  $1,000.00
+    Equity:Opening-Balances
+
+2025/01/05 (42) Landlord | January ; external_id: BANK-7
+    Expenses:Rent  400.00
+    Assets:Bank  = $600.00
+",
+        )
+        .expect("valid ledger");
+        assert_eq!(dec!(600.00), journal.balance("Assets:Bank"));
+        assert_eq!(dec!(400.00), journal.balance("Expenses:Rent"));
+        let rent = &journal.entries()[1];
+        assert_eq!(Some("Landlord"), rent.payee());
+        assert_eq!(Some("January"), rent.narration());
+        assert_eq!(Some("42"), rent.meta("code"));
+        assert_eq!(Some("BANK-7"), rent.external_id());
+    }
+
+    #[test]
+    fn import_refuses_what_it_cannot_book() {
+        let opened = "2025-01-01 open Assets:Bank\n2025-01-01 open Income:Salary\n";
+        let refused = [
+            // Closed account
+            format!(
+                "{opened}2025-01-31 close Income:Salary\n\
+                 2025-02-01 * \"Late\"\n  Assets:Bank  1 EUR\n  Income:Salary\n"
+            ),
+            // Never opened account
+            format!("{opened}2025-02-01 * \"Gift\"\n  Assets:Bank  1 EUR\n  Income:Gifts\n"),
+            // Two elided amounts
+            "2025-02-01 * \"x\"\n  Assets:Bank\n  Income:Salary\n".into(),
+            // Unbalanced
+            "2025-02-01 * \"x\"\n  Assets:Bank  1 EUR\n  Income:Salary  -2 EUR\n".into(),
+            // Mixed commodities
+            "2025-02-01 * \"x\"\n  Assets:Bank  1 EUR\n  Income:Salary  -1 USD\n".into(),
+            // Costs
+            "2025-02-01 * \"x\"\n  Assets:Bank  1 EUR @ 1.1 USD\n  Income:Salary\n".into(),
+            "2025-02-01 pad Assets:Bank Equity:Opening-Balances\n".into(),
+            "include \"other.beancount\"\n".into(),
+        ];
+        for input in refused {
+            assert!(
+                matches!(imported(&input), Err(Error::InvalidRow(_))),
+                "should refuse {input}"
+            );
+        }
+
+        let failed_balance = "2025-02-01 * \"x\"\n  Assets:Bank  1 EUR\n  Income:Salary\n2025-02-02 balance Assets:Bank  2 EUR\n";
+        assert!(matches!(
+            imported(failed_balance),
+            Err(Error::BalanceMismatch {
+                kind: "asserted",
+                ..
+            })
+        ));
+        let failed_assertion = "2025/02/01 x\n    Assets:Bank  1 = 2\n    Income:Salary\n";
+        assert!(matches!(
+            imported(failed_assertion),
+            Err(Error::BalanceMismatch {
+                kind: "asserted",
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,120 @@
+use std::{fmt, str::FromStr};
+
+use crate::{error::Error, objects::transactions::Transaction};
+
+// NOTE(juf): A disputed deposit is clear, the client claims money they never had, so it is held
+// until the dispute is settled and taken away on a chargeback. A disputed withdrawal is the client
+// claiming money they never took out, e.g., a card payment they did not make. What happens to the
+// balances while the dispute is open is a matter of policy, the effects of every step are:
+//
+//   policy                 | dispute             | resolve             | chargeback
+//   -----------------------|---------------------|---------------------|--------------------------
+//   hold                   | held +a, total +a   | held -a, total -a   | held -a, available +a
+//   credit-on-chargeback   | nothing             | nothing             | available +a, total +a
+//   refuse                 | rejected            | -                   | -
+//
+// A chargeback locks the account under every policy. With `hold` the client is credited
+// provisionally, they see the amount coming back in their total but can not spend it before the
+// chargeback, which is how the books were kept before there was a choice, hence the default. With
+// `credit-on-chargeback` the open dispute does not show in the balances at all, the claim is not
+// money of the client before it is granted. Once the dispute is settled both end up with the same
+// balances. Books have to be continued with the policy they were started with, a dispute opened
+// under one policy would be settled under the other otherwise, so the state file, the SQLite
+// books and the event log record it and refuse another one. Books from before the policy was
+// recorded were kept with `hold`.
+
+/// What disputes of withdrawals do to the balances, see the note on top.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum WithdrawalDisputes {
+    /// The amount is credited to held while under dispute and released on a chargeback
+    #[default]
+    Hold,
+    /// Nothing moves before the chargeback credits the amount
+    CreditOnChargeback,
+    /// Withdrawals can not be disputed
+    Refuse,
+}
+
+impl WithdrawalDisputes {
+    /// Whether a withdrawal under dispute holds its amount.
+    pub(crate) fn holds(self) -> bool {
+        match self {
+            WithdrawalDisputes::Hold => true,
+            WithdrawalDisputes::CreditOnChargeback | WithdrawalDisputes::Refuse => false,
+        }
+    }
+}
+
+impl fmt::Display for WithdrawalDisputes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            WithdrawalDisputes::CreditOnChargeback => "credit-on-chargeback",
+            WithdrawalDisputes::Hold => "hold",
+            WithdrawalDisputes::Refuse => "refuse",
+        })
+    }
+}
+
+impl FromStr for WithdrawalDisputes {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hold" => Ok(WithdrawalDisputes::Hold),
+            "credit-on-chargeback" => Ok(WithdrawalDisputes::CreditOnChargeback),
+            "refuse" => Ok(WithdrawalDisputes::Refuse),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown withdrawal dispute policy {s}"
+            ))),
+        }
+    }
+}
+
+// NOTE(juf): A chargeback locks the account, which for long meant nothing of it is processed any
+// more. Whether a locked account still takes deposits, or settles disputes opened before, is a
+// matter of policy too. An operator reinstates it with an `unlock` row giving the reason, which is
+// recorded in the event log.
+
+/// What a locked account still takes, besides being unlocked.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LockedAccounts {
+    /// Nothing
+    #[default]
+    BlockAll,
+    /// Everything but withdrawals
+    BlockWithdrawals,
+    /// Disputes, resolves and chargebacks of earlier transactions
+    AllowDisputes,
+}
+
+impl LockedAccounts {
+    /// Whether a locked account takes `txn`.
+    pub(crate) fn allows(self, txn: &Transaction) -> bool {
+        match (self, txn) {
+            (_, Transaction::Unlock(..)) => true,
+            (LockedAccounts::BlockAll, _) => false,
+            (LockedAccounts::BlockWithdrawals, Transaction::Withdrawal(..)) => false,
+            (LockedAccounts::BlockWithdrawals, _) => true,
+            (
+                LockedAccounts::AllowDisputes,
+                Transaction::Dispute(_) | Transaction::Resolve(_) | Transaction::Chargeback(_),
+            ) => true,
+            (LockedAccounts::AllowDisputes, _) => false,
+        }
+    }
+}
+
+impl FromStr for LockedAccounts {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "block-all" => Ok(LockedAccounts::BlockAll),
+            "block-withdrawals" => Ok(LockedAccounts::BlockWithdrawals),
+            "allow-disputes" => Ok(LockedAccounts::AllowDisputes),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown locked account policy {s}"
+            ))),
+        }
+    }
+}
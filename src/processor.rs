@@ -1,23 +1,44 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, io::Read, path::Path};
 
 use rust_decimal::Decimal;
 
-use crate::objects::{
-    accounts::Account,
-    transactions::{InitialState, Metadata, Transaction, TransactionState},
+#[cfg(feature = "sqlite")]
+use crate::store::sqlite::{self, SqliteAccounts, SqliteTransactions};
+use crate::{
+    amount::{Amount, Precision},
+    error::{Error, Rejection},
+    events::{Event, EventLog, EventReader},
+    invariants::{Invariants, Report},
+    ledger::{Journal, JournalEntry, client_accounts},
+    objects::{
+        accounts::{Account, Overflow},
+        statements::StatementLine,
+        transactions::{InitialState, Metadata, Transaction, TransactionState, TxType},
+    },
+    policy::{LockedAccounts, WithdrawalDisputes},
+    sha256::Digest,
+    state::{self, Record},
+    store::{
+        AccountStore, CachedTransaction, TransactionStore,
+        compact::{Compaction, FinalizedSet, MemoryStats},
+    },
 };
 
 /// Processor is the core which get's feed individual transactions and manages the account and
 /// transaction (simplistic) "ledger".
-pub(crate) struct Processor {
+pub(crate) struct Processor<
+    A: AccountStore = HashMap<u16, Account>,
+    T: TransactionStore = HashMap<u32, CachedTransaction>,
+> {
     // ~upwards of u16::MAX * 36 bytes + internal HashMap overhead of storage consumption
-    account_store: HashMap<u16, Account>,
+    account_store: A,
     // Store transaction state to avoid incorrect "state" transitions, e.g., resolve to dispute.
     // CacheEntry Size with key included: 24, based on helper struct and using size_of::<CacheEntry>()
     // ~upwards of u32::MAX * 24 bytes + internal HashMap overhead of storage consumption
     // unless I am wrong this is ~100 GB+ (and again HashMap internals overhead unaccounted) which does not fit into reasonable memory. RIP this program.
     // I could write my "dumbed" down version of Decimal and use less bytes per Decimal and just
     // implement Into<Decimal> for MyPackedDecimal, save a bit here.
+    // NOTE(juf): Done, see `Amount`.
     // Then the next thing that comes to my mind is, maybe we can construct some sort of prefix
     // tree based on parts of the Decimal and can save multiple txn (id+state) into leafs(buckets)
     // in that tree, but that's maybe grasping, because I am not sure if this would actually
@@ -25,93 +46,680 @@ pub(crate) struct Processor {
     // transaction amounts to allow for that.
     // I guess it's reasonable to persist all or parts of the txn_cache to some external store, be
     // it just the disk, or a KV/Relational Database and keep only "recent" entries in hot storage.
-    txn_cache: HashMap<u32, (Metadata, Decimal, TransactionState)>,
+    // NOTE(juf): Which is what the `TransactionStore` trait is for, the HashMap is the default.
+    txn_cache: T,
+    journal: Option<Journal>,
+    // Bank statement bookings carry an external id (e.g., OFX FITID) instead of a tx id, this
+    // maps it onto the tx id assigned when it was applied, guarding against re-imports.
+    external_ids: HashMap<String, u32>,
+    // Next tx id handed out to bookings without one, always past the highest tx id seen.
+    next_tx_id: u32,
+    event_log: Option<EventLog>,
+    // Finalized transactions moved out of the txn_cache, their tx ids stay taken.
+    compaction: Compaction,
+    // Decimal places deposits and withdrawals are taken in at.
+    precision: Precision,
+    // What the stores do not know to check the invariants, see `invariants`.
+    invariants: Invariants,
+    // Chosen or taken over from the books, the default policy otherwise.
+    withdrawal_disputes: Option<WithdrawalDisputes>,
+    locked_accounts: LockedAccounts,
+}
+
+/// Result of handing a single transaction to [`Processor::process_one`].
+#[must_use]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Outcome {
+    Applied,
+    Rejected(Rejection),
 }
 
 impl Processor {
     pub(crate) fn new() -> Self {
+        Self::with_stores(HashMap::new(), HashMap::new())
+    }
+
+    // used by test
+    #[allow(dead_code)]
+    /// Continues from the state saved by [`Processor::save`], a fresh processor if there is none
+    /// at `path` yet.
+    pub(crate) fn load(path: &Path) -> Result<Self, Error> {
+        Self::new().restore(path)
+    }
+
+    /// Puts the processors of the shards back together, shard `i` owning the clients with
+    /// `client % shards.len() == i`. Other shards only ever hold a rejected, untouched copy of
+    /// an account, created when a dispute was routed to the shard holding the transaction.
+    pub(crate) fn merge(shards: Vec<Self>) -> Self {
+        let count = shards.len();
+        let mut merged = Self::new();
+        for (shard, p) in shards.into_iter().enumerate() {
+            for (client, account) in p.account_store {
+                if usize::from(client) % count == shard
+                    || !merged.account_store.contains_key(&client)
+                {
+                    merged.account_store.insert(client, account);
+                }
+            }
+            merged.txn_cache.extend(p.txn_cache);
+            merged.next_tx_id = merged.next_tx_id.max(p.next_tx_id);
+            merged.compaction.absorb(p.compaction);
+            merged.precision = p.precision;
+            merged.invariants.absorb(p.invariants);
+            merged.withdrawal_disputes = p.withdrawal_disputes;
+            merged.locked_accounts = p.locked_accounts;
+        }
+        merged
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl Processor<SqliteAccounts, SqliteTransactions> {
+    /// Continues from the books in the SQLite file at `path`, set up on first use with
+    /// `withdrawal_disputes`, or the default policy. Books kept with another policy are refused.
+    /// Nothing else is written back before [`Processor::commit`].
+    pub(crate) fn open_sqlite(
+        path: &Path,
+        withdrawal_disputes: Option<WithdrawalDisputes>,
+    ) -> Result<Self, Error> {
+        let books = sqlite::open(path)?;
+        let mut p = Self::with_stores(books.accounts, books.transactions);
+        p.withdrawal_disputes = withdrawal_disputes;
+        match books.withdrawal_disputes {
+            Some(kept) => p.keep_withdrawal_disputes(kept)?,
+            None => {
+                let policy = p.withdrawal_disputes();
+                p.keep_withdrawal_disputes(policy)?;
+                p.txn_cache.record_withdrawal_disputes(policy)?;
+            }
+        }
+        p.next_tx_id = books.next_tx_id;
+        p.external_ids = books.external_ids;
+        for account in p.account_store.accounts() {
+            p.invariants.carry_over(account);
+        }
+        Ok(p)
+    }
+}
+
+impl<A: AccountStore, T: TransactionStore> Processor<A, T> {
+    /// Processor keeping accounts and transactions in the given, empty, stores.
+    pub(crate) fn with_stores(account_store: A, txn_cache: T) -> Self {
         Self {
-            account_store: HashMap::new(),
-            txn_cache: HashMap::new(),
+            account_store,
+            txn_cache,
+            journal: None,
+            external_ids: HashMap::new(),
+            next_tx_id: 1,
+            event_log: None,
+            compaction: Compaction::default(),
+            precision: Precision::default(),
+            invariants: Invariants::default(),
+            withdrawal_disputes: None,
+            locked_accounts: LockedAccounts::default(),
+        }
+    }
+
+    /// Additionally record every applied transaction as a double-entry [`JournalEntry`].
+    pub(crate) fn with_journal(mut self, journal: Journal) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    /// Additionally append every applied transaction to the event log. A log is kept with the
+    /// withdrawal dispute policy of the books, a new one records the policy chosen so far.
+    pub(crate) fn with_event_log(mut self, mut log: EventLog) -> Result<Self, Error> {
+        match log.withdrawal_disputes() {
+            Some(kept) => self.keep_withdrawal_disputes(kept)?,
+            None => {
+                let policy = self.withdrawal_disputes();
+                self.keep_withdrawal_disputes(policy)?;
+                log.record_withdrawal_disputes(policy);
+            }
+        }
+        self.event_log = Some(log);
+        Ok(self)
+    }
+
+    /// Takes deposits and withdrawals in at `precision` instead of the default 4 decimal places.
+    pub(crate) fn with_precision(mut self, precision: Precision) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    /// Disputes of withdrawals follow `policy` instead of the default one. Books loaded later on
+    /// have to be kept with it.
+    pub(crate) fn with_withdrawal_disputes(mut self, policy: WithdrawalDisputes) -> Self {
+        self.withdrawal_disputes = Some(policy);
+        self.invariants.withdrawal_disputes(policy);
+        self
+    }
+
+    /// What disputes of withdrawals do to the balances.
+    pub(crate) fn withdrawal_disputes(&self) -> WithdrawalDisputes {
+        self.withdrawal_disputes.unwrap_or_default()
+    }
+
+    /// Continues books kept with the withdrawal dispute policy `kept`, unless another one was
+    /// chosen, see the note in [`policy`](crate::policy).
+    fn keep_withdrawal_disputes(&mut self, kept: WithdrawalDisputes) -> Result<(), Error> {
+        if let Some(chosen) = self.withdrawal_disputes
+            && chosen != kept
+        {
+            return Err(Error::InvalidState(format!(
+                "the books are kept with withdrawal dispute policy {kept}, not {chosen}"
+            )));
         }
+        self.withdrawal_disputes = Some(kept);
+        self.invariants.withdrawal_disputes(kept);
+        Ok(())
+    }
+
+    /// Locked accounts take what `policy` allows instead of nothing but unlocks.
+    pub(crate) fn with_locked_accounts(mut self, policy: LockedAccounts) -> Self {
+        self.locked_accounts = policy;
+        self.invariants.locked_accounts(policy);
+        self
     }
 
-    pub(crate) fn get_account_store_ref(&self) -> &HashMap<u16, Account> {
+    /// Checks the invariants of the account after every applied transaction, the first one that
+    /// breaks any fails processing with [`Error::BrokenInvariants`], see
+    /// [`invariants`](crate::invariants).
+    pub(crate) fn with_paranoid_checks(mut self) -> Self {
+        self.invariants = std::mem::take(&mut self.invariants).paranoid();
+        self
+    }
+
+    /// Moves finalized transactions out of the transaction store once `compaction` says so.
+    pub(crate) fn with_compaction(mut self, mut compaction: Compaction) -> Self {
+        compaction.finalized = std::mem::take(&mut self.compaction.finalized);
+        self.compaction = compaction;
+        self
+    }
+
+    /// Flushes the event log, if there is one, and reports whether every event made it. Returns
+    /// the head of its hash chain.
+    pub(crate) fn finish_event_log(&mut self) -> Result<Option<Digest>, Error> {
+        match self.event_log.as_mut() {
+            Some(log) => log.finish().map(|_| Some(log.head())),
+            None => Ok(None),
+        }
+    }
+
+    /// Applies the events of a log once more, in order. Every event has to apply and leave the
+    /// transaction in the state recorded, if it refers to one, otherwise the log does not belong
+    /// on top of this processor. The withdrawal dispute policy the log was kept with is taken over,
+    /// unless another one was chosen, which is refused. Returns the number of events replayed.
+    pub(crate) fn replay<R: Read>(&mut self, events: &mut EventReader<R>) -> Result<u64, Error> {
+        if let Some(kept) = events.withdrawal_disputes() {
+            self.keep_withdrawal_disputes(kept)?;
+        }
+        let mut count = 0;
+        for event in events {
+            let event = event?;
+            count += 1;
+            let Some(recorded) = event.state else {
+                let outcome = match event.transaction() {
+                    Transaction::Unlock(Metadata { client, tx_id }, reason) => {
+                        self.unlock(client, tx_id, reason, true)?
+                    }
+                    txn => self.process(txn, None, false)?,
+                };
+                if let Outcome::Rejected(rejection) = outcome {
+                    return Err(Error::InvalidLog(format!(
+                        "event {count} does not apply: {rejection}"
+                    )));
+                }
+                continue;
+            };
+            let tx_id = event.metadata.tx_id;
+            if let Outcome::Rejected(rejection) =
+                self.process(event.transaction(), event.external_id, false)?
+            {
+                return Err(Error::InvalidLog(format!(
+                    "event {count} does not apply: {rejection}"
+                )));
+            }
+            let state = match self.txn_cache.get_mut(tx_id)? {
+                Some((_, _, state)) => Some(*state),
+                None if self.compaction.finalized.contains(tx_id) => {
+                    Some(TransactionState::Finalized)
+                }
+                None => None,
+            };
+            if state != Some(recorded) {
+                return Err(Error::InvalidLog(format!(
+                    "event {count}: tx {tx_id} ends up {state:?} instead of {recorded:?}"
+                )));
+            }
+        }
+        Ok(count)
+    }
+
+    /// Makes everything applied since the last commit durable in one step, for stores that keep
+    /// the books beyond the run. Call it between transactions only.
+    pub(crate) fn commit(&mut self) -> Result<(), Error> {
+        let accounts = self.account_store.take_changed();
+        self.txn_cache
+            .commit(accounts, self.next_tx_id, &self.external_ids)
+    }
+
+    /// Restores the state saved by [`Processor::save`] into this fresh processor, if there is
+    /// any at `path`. The journal is not part of the state. State kept with another withdrawal
+    /// dispute policy than the one chosen is refused.
+    pub(crate) fn restore(self, path: &Path) -> Result<Self, Error> {
+        let mut p = self;
+        let Some(records) = state::read(path)? else {
+            return Ok(p);
+        };
+        // State saved before the policy was recorded was kept with the only one there was
+        let mut withdrawal_disputes = WithdrawalDisputes::Hold;
+        for record in records {
+            match record {
+                Record::WithdrawalDisputes(policy) => withdrawal_disputes = policy,
+                Record::NextTxId(tx_id) => p.next_tx_id = p.next_tx_id.max(tx_id),
+                Record::Account(acc) => {
+                    let id = acc.id;
+                    p.invariants.carry_over(&acc);
+                    if p.account_store.insert(acc).is_some() {
+                        return Err(Error::InvalidState(format!("client {id} is stored twice")));
+                    }
+                }
+                Record::Txn(metadata, amount, state) => {
+                    let tx_id = metadata.tx_id;
+                    let amount = Amount::try_from(amount)
+                        .map_err(|err| Error::InvalidState(format!("tx {tx_id}: {err}")))?;
+                    if !p.txn_cache.insert_new(tx_id, (metadata, amount, state))? {
+                        return Err(Error::InvalidState(format!("tx {tx_id} is stored twice")));
+                    }
+                    p.next_tx_id = p.next_tx_id.max(tx_id.saturating_add(1));
+                }
+                Record::ExternalId(tx_id, external_id) => {
+                    p.external_ids.insert(external_id, tx_id);
+                }
+                Record::Finalized(first, last) => {
+                    for tx_id in first..=last {
+                        if p.txn_cache.contains(tx_id)? || !p.compaction.finalized.insert(tx_id) {
+                            return Err(Error::InvalidState(format!("tx {tx_id} is stored twice")));
+                        }
+                    }
+                    p.next_tx_id = p.next_tx_id.max(last.saturating_add(1));
+                }
+            }
+        }
+        p.keep_withdrawal_disputes(withdrawal_disputes)?;
+        Ok(p)
+    }
+
+    /// Saves accounts, cached transactions and applied external ids to `path`, replacing what
+    /// was there in one step.
+    pub(crate) fn save(&self, path: &Path) -> Result<(), Error> {
+        let mut accounts: Vec<_> = self.account_store.accounts().cloned().collect();
+        accounts.sort_by_key(|acc| acc.id);
+        let records = [
+            Ok(Record::NextTxId(self.next_tx_id)),
+            Ok(Record::WithdrawalDisputes(self.withdrawal_disputes())),
+        ]
+        .into_iter()
+        .chain(accounts.into_iter().map(|acc| Ok(Record::Account(acc))))
+        .chain(self.txn_cache.transactions().map(|txn| {
+            txn.map(|(metadata, amount, state)| Record::Txn(metadata, amount.into(), state))
+        }))
+        .chain(
+            self.compaction
+                .finalized
+                .ranges()
+                .map(|(first, last)| Ok(Record::Finalized(first, last))),
+        )
+        .chain(
+            self.external_ids
+                .iter()
+                .map(|(external_id, tx_id)| Ok(Record::ExternalId(*tx_id, external_id.clone()))),
+        );
+        state::write(path, records)
+    }
+
+    /// Moves the transactions finalized since the last compaction out of the transaction store.
+    /// Transactions the store fails to remove stay where they are, they are just as final there.
+    pub(crate) fn compact(&mut self) {
+        for tx_id in self.compaction.take_pending() {
+            if let Ok(Some(_)) = self.txn_cache.remove(tx_id) {
+                self.compaction.finalized.insert(tx_id);
+            }
+        }
+    }
+
+    /// Checks the invariants of every account against the transaction store.
+    pub(crate) fn check_invariants(&self) -> Result<Report, Error> {
+        self.invariants.check(&self.account_store, &self.txn_cache)
+    }
+
+    pub(crate) fn memory_stats(&self) -> MemoryStats {
+        self.compaction.stats(self.txn_cache.memory())
+    }
+
+    pub(crate) fn get_account_store_ref(&self) -> &A {
         &self.account_store
     }
 
+    // used by test
+    #[allow(dead_code)]
+    pub(crate) fn txn_store(&self) -> &T {
+        &self.txn_cache
+    }
+
+    pub(crate) fn journal(&self) -> Option<&Journal> {
+        self.journal.as_ref()
+    }
+
     // Assumptions:
     // 1. Locked means no transactions associated with the account are being processed any more
+    //    NOTE(juf): Unless the `LockedAccounts` policy allows them, or an operator unlocks it.
     // 2. Transaction IDs are globally unique, but we still assume by accident or malicious intent
     //    that a transaction, e.g., deposit can be submitted twice, therefore we try to guard
     //    against that.
-    // 3. Negative amounts are invalid and should be ignored (this is missing tests)
-    pub(crate) fn process_one(&mut self, txn: Transaction) {
-        let metadata = txn.get_metadata();
-        let acc_id = metadata.client;
-        let acc = self.create_account_if_not_exists(acc_id);
-        if acc.is_locked() {
-            // skip any further transactions? I it is not really specify what locked means
-            return;
+    // 3. Negative amounts are invalid and are rejected
+    // 4. Rejected deposits/withdrawals are not cached, so they can not be disputed later on.
+    /// Fails only with paranoid checks, if the transaction broke the invariants or they can not
+    /// be checked. The transaction is applied then, the processor is of no use any more.
+    pub(crate) fn process_one(&mut self, txn: Transaction) -> Result<Outcome, Error> {
+        self.process(txn, None, false)
+    }
+
+    /// Processes a deposit or withdrawal whose tx id another processor took already, see
+    /// [`shard`](crate::shard). It is rejected as duplicate, unless an earlier check rejects it.
+    pub(crate) fn process_claimed(&mut self, txn: Transaction) -> Result<Outcome, Error> {
+        self.process(txn, None, true)
+    }
+
+    /// Whether the tx id is taken by a deposit or withdrawal applied here.
+    pub(crate) fn holds(&mut self, tx_id: u32) -> Result<bool, Error> {
+        Ok(self.compaction.finalized.contains(tx_id) || self.txn_cache.contains(tx_id)?)
+    }
+
+    /// Processes a bank statement booking of `client`. Bookings do not come with a tx id, a fresh one
+    /// is assigned. Bookings with an external id are applied at most once.
+    pub(crate) fn process_statement_line(
+        &mut self,
+        client: u16,
+        line: StatementLine,
+    ) -> Result<Outcome, Error> {
+        let tx_id = self.next_tx_id;
+        let external_id = line.external_id.clone();
+        if let Some(external_id) = external_id.as_ref()
+            && let Some(&known) = self.external_ids.get(external_id)
+        {
+            return Ok(Outcome::Rejected(Rejection::DuplicateExternalId {
+                client,
+                tx_id: known,
+                external_id: external_id.clone(),
+            }));
+        }
+        self.process(line.into_transaction(client, tx_id), external_id, false)
+    }
+
+    fn process(
+        &mut self,
+        txn: Transaction,
+        external_id: Option<String>,
+        claimed: bool,
+    ) -> Result<Outcome, Error> {
+        let Metadata { client, tx_id } = txn.get_metadata().clone();
+        if let Transaction::Unlock(_, reason) = txn {
+            return self.unlock(client, tx_id, reason, false);
+        }
+        // NOTE(juf): Check the ledger accounts upfront, once applied there is no way back.
+        if let Some(journal) = self.journal.as_ref()
+            && let Err(source) =
+                journal.check_accounts(client_accounts(client).iter().map(String::as_str))
+        {
+            return Ok(Outcome::Rejected(Rejection::Ledger {
+                client,
+                tx_id,
+                source,
+            }));
+        }
+        match self.apply(txn, claimed) {
+            Ok((action, origin, amount)) => {
+                let violations = self.invariants.applied(
+                    client,
+                    (action, origin, amount),
+                    &self.account_store,
+                    &self.txn_cache,
+                )?;
+                if !violations.is_empty() {
+                    return Err(Error::BrokenInvariants(Report {
+                        after: Some((action, tx_id)),
+                        accounts: 1,
+                        transactions: 1,
+                        violations,
+                    }));
+                }
+                let withdrawal_disputes = self.withdrawal_disputes();
+                if let Some(journal) = self.journal.as_mut()
+                    && let Some(entry) = JournalEntry::for_transaction(
+                        client,
+                        tx_id,
+                        action,
+                        origin,
+                        amount,
+                        withdrawal_disputes,
+                    )
+                {
+                    journal
+                        .post(entry)
+                        .expect("ledger accounts are checked before applying the transaction");
+                }
+                if let Some(log) = self.event_log.as_mut() {
+                    let state = match self.txn_cache.get_mut(tx_id)? {
+                        Some((_, _, state)) => Some(*state),
+                        None => unreachable!("applied transactions are cached"),
+                    };
+                    log.append(&Event {
+                        action,
+                        metadata: Metadata { client, tx_id },
+                        amount: matches!(action, TxType::Deposit | TxType::Withdrawal)
+                            .then_some(amount),
+                        state,
+                        external_id: external_id.clone(),
+                        reason: None,
+                    });
+                }
+                if let Some(external_id) = external_id {
+                    self.external_ids.insert(external_id, tx_id);
+                }
+                if matches!(action, TxType::Resolve | TxType::Chargeback)
+                    && self.compaction.finalized(tx_id)
+                {
+                    self.compact();
+                }
+                Ok(Outcome::Applied)
+            }
+            Err(rejection) => Ok(Outcome::Rejected(rejection)),
+        }
+    }
+
+    /// Reinstates the locked account of `client` for the operator's `reason`. The reason is only
+    /// kept in the event log, so unlocks are rejected without one, unless they are `replayed` from
+    /// it. Unlocks move no money and do not show in the journal.
+    fn unlock(
+        &mut self,
+        client: u16,
+        tx_id: u32,
+        reason: String,
+        replayed: bool,
+    ) -> Result<Outcome, Error> {
+        if self.event_log.is_none() && !replayed {
+            return Ok(Outcome::Rejected(Rejection::UnrecordedUnlock {
+                client,
+                tx_id,
+            }));
+        }
+        if !self
+            .account_store
+            .get(client)
+            .is_some_and(Account::is_locked)
+        {
+            return Ok(Outcome::Rejected(Rejection::NotLocked { client, tx_id }));
+        }
+        self.account_store.get_or_create(client).reinstate();
+        let violations = self
+            .invariants
+            .unlocked(client, &self.account_store, &self.txn_cache)?;
+        if !violations.is_empty() {
+            return Err(Error::BrokenInvariants(Report {
+                after: Some((TxType::Unlock, tx_id)),
+                accounts: 1,
+                transactions: 1,
+                violations,
+            }));
+        }
+        if let Some(log) = self.event_log.as_mut() {
+            log.append(&Event {
+                action: TxType::Unlock,
+                metadata: Metadata { client, tx_id },
+                amount: None,
+                state: None,
+                external_id: None,
+                reason: Some(reason),
+            });
+        }
+        Ok(Outcome::Applied)
+    }
+
+    /// Applies the transaction to the account and transaction state. On success returns what was
+    /// done, the kind of transaction the funds originate from and the amount moved. Deposits and
+    /// withdrawals that are `claimed` elsewhere are duplicates.
+    fn apply(
+        &mut self,
+        txn: Transaction,
+        claimed: bool,
+    ) -> Result<(TxType, InitialState, Decimal), Rejection> {
+        let Metadata { client, tx_id } = txn.get_metadata().clone();
+        let withdrawal_disputes = self.withdrawal_disputes();
+        // NOTE(juf): Single lookup into the account store, the txn_cache is a disjoint field so we
+        // can hold on to `acc` while working with it.
+        let acc = self.account_store.get_or_create(client);
+        // NOTE(juf): What a locked account still takes is up to the policy, see `LockedAccounts`.
+        if acc.is_locked() && !self.locked_accounts.allows(&txn) {
+            return Err(Rejection::AccountLocked { client, tx_id });
         }
         match txn {
-            Transaction::Deposit(Metadata { client: _, tx_id }, amount) => {
+            Transaction::Unlock(..) => unreachable!("unlocks take their own path, see `unlock`"),
+            Transaction::Deposit(metadata, amount) => {
                 if amount.is_sign_negative() {
-                    return;
-                }
-                if let Some(acc) = self.account_store.get_mut(&acc_id) {
-                    self.txn_cache.entry(tx_id).or_insert_with(|| {
-                        acc.deposit(amount);
-                        (
-                            metadata.clone(),
-                            amount,
-                            TransactionState::Initial(
-                                crate::objects::transactions::InitialState::Deposit,
-                            ),
-                        )
+                    return Err(Rejection::NegativeAmount {
+                        client,
+                        tx_id,
+                        amount,
                     });
-                    // ELSE ignore double reporting of deposit
-                };
+                }
+                let amount = admit(&self.precision, client, tx_id, amount)?;
+                let packed = pack(client, tx_id, amount)?;
+                // NOTE(juf): Taking the tx id caches the transaction, so an overflow has to be
+                // known before, it is reported ahead of duplicates.
+                let mut deposited = acc.clone();
+                deposited
+                    .deposit(amount)
+                    .map_err(|source| overflow(client, tx_id, source))?;
+                let cached = (
+                    metadata,
+                    packed,
+                    TransactionState::Initial(InitialState::Deposit),
+                );
+                if claimed
+                    || self.compaction.finalized.contains(tx_id)
+                    || !self
+                        .txn_cache
+                        .insert_new(tx_id, cached)
+                        .map_err(|err| storage_failure(client, tx_id, err))?
+                {
+                    return Err(Rejection::DuplicateTransaction { client, tx_id });
+                }
+                self.next_tx_id = self.next_tx_id.max(tx_id.saturating_add(1));
+                *acc = deposited;
+                Ok((TxType::Deposit, InitialState::Deposit, amount))
             }
-            Transaction::Withdrawal(Metadata { client: _, tx_id }, amount) => {
+            Transaction::Withdrawal(metadata, amount) => {
                 if amount.is_sign_negative() {
-                    return;
+                    return Err(Rejection::NegativeAmount {
+                        client,
+                        tx_id,
+                        amount,
+                    });
                 }
-                if let Some(acc) = self.account_store.get_mut(&acc_id) {
-                    self.txn_cache.entry(tx_id).or_insert_with(|| {
-                        acc.withdraw(amount);
-                        (
-                            metadata.clone(),
-                            amount,
-                            TransactionState::Initial(
-                                crate::objects::transactions::InitialState::Withdrawal,
-                            ),
-                        )
+                let amount = admit(&self.precision, client, tx_id, amount)?;
+                let packed = pack(client, tx_id, amount)?;
+                // NOTE(juf): Two lookups, duplicates are reported before insufficient funds, but
+                // only transactions with sufficient funds are cached.
+                if claimed
+                    || self.compaction.finalized.contains(tx_id)
+                    || self
+                        .txn_cache
+                        .contains(tx_id)
+                        .map_err(|err| storage_failure(client, tx_id, err))?
+                {
+                    return Err(Rejection::DuplicateTransaction { client, tx_id });
+                }
+                if acc.available < amount {
+                    return Err(Rejection::InsufficientFunds {
+                        client,
+                        tx_id,
+                        available: acc.available,
+                        requested: amount,
                     });
-                    // ELSE ignore double reporting of withdraw
                 }
-            }
-            Transaction::Dispute(Metadata { client, tx_id }) => {
-                if let Some((metadata, amount, state)) = self.txn_cache.get_mut(&tx_id)
-                    && let Some(acc) = self.account_store.get_mut(&acc_id)
+                let mut withdrawn = acc.clone();
+                withdrawn
+                    .withdraw(amount)
+                    .map_err(|source| overflow(client, tx_id, source))?;
+                let cached = (
+                    metadata,
+                    packed,
+                    TransactionState::Initial(InitialState::Withdrawal),
+                );
+                if !self
+                    .txn_cache
+                    .insert_new(tx_id, cached)
+                    .map_err(|err| storage_failure(client, tx_id, err))?
                 {
-                    if metadata.client != client {
-                        // foreign account / mismatch, disregard
-                        return;
+                    return Err(Rejection::DuplicateTransaction { client, tx_id });
+                }
+                self.next_tx_id = self.next_tx_id.max(tx_id.saturating_add(1));
+                *acc = withdrawn;
+                Ok((TxType::Withdrawal, InitialState::Withdrawal, amount))
+            }
+            Transaction::Dispute(_) => {
+                let (amount, state) = lookup(
+                    &mut self.txn_cache,
+                    &self.compaction.finalized,
+                    TxType::Dispute,
+                    client,
+                    tx_id,
+                )?;
+                let origin = match *state {
+                    TransactionState::Initial(InitialState::Deposit) => {
+                        acc.dispute(amount)
+                            .map_err(|source| overflow(client, tx_id, source))?;
+                        InitialState::Deposit
                     }
-                    match state {
-                        TransactionState::Initial(InitialState::Deposit) => {
-                            acc.dispute(*amount);
-                            *state = TransactionState::Disputed(InitialState::Deposit);
-                        }
-                        TransactionState::Initial(InitialState::Withdrawal) => {
-                            acc.dispute_withdrawal(*amount);
-                            *state = TransactionState::Disputed(InitialState::Withdrawal);
+                    TransactionState::Initial(InitialState::Withdrawal) => {
+                        match withdrawal_disputes {
+                            WithdrawalDisputes::Hold => acc
+                                .dispute_withdrawal(amount)
+                                .map_err(|source| overflow(client, tx_id, source))?,
+                            WithdrawalDisputes::CreditOnChargeback => {}
+                            WithdrawalDisputes::Refuse => {
+                                return Err(Rejection::WithdrawalDispute { client, tx_id });
+                            }
                         }
-                        _ => {} // Do nothing. There is not valid transition for this state and
-                                // operation type.
+                        InitialState::Withdrawal
                     }
+                    // There is no valid transition for this state and operation type.
+                    _ => return Err(invalid_transition(client, tx_id, TxType::Dispute, *state)),
                 };
+                *state = TransactionState::Disputed(origin);
+                Ok((TxType::Dispute, origin, amount))
             }
             // For both Resolve and Chargeback we could transfer the transaction to a less
             // expensive "store", e.g., HashSet<u32>, which would "free up" (we remove it from the
@@ -123,67 +731,172 @@ impl Processor {
             // lookups you do is preferred. Technically we do not have to first look into the
             // HashSet. If both stores are required we could order the lookup priority fixed or
             // dynamically based on the statistical occurrence of Resolve/Chargebacks vs. Not.
-            Transaction::Resolve(Metadata { client, tx_id }) => {
-                if let Some((metadata, amount, state)) = self.txn_cache.get_mut(&tx_id)
-                    && let Some(acc) = self.account_store.get_mut(&acc_id)
-                {
-                    if metadata.client != client {
-                        // foreign account / mismatch, disregard
-                        return;
+            // NOTE(juf): Done by `Compaction`, opt-in, the set is a bitmap and only consulted for
+            // tx ids the store does not know or is about to take, see `lookup`.
+            Transaction::Resolve(_) => {
+                let (amount, state) = lookup(
+                    &mut self.txn_cache,
+                    &self.compaction.finalized,
+                    TxType::Resolve,
+                    client,
+                    tx_id,
+                )?;
+                // NOTE(juf): Once a transaction has been Resolved, we could think about
+                // removing it from the cache, _but_ what if we receive the same transaction
+                // again later? We would deposit the amount again.
+                // Compaction transfers it to a less expensive "store", see `Compaction`
+                let origin = match *state {
+                    TransactionState::Disputed(InitialState::Deposit) => {
+                        acc.resolve(amount)
+                            .map_err(|source| overflow(client, tx_id, source))?;
+                        InitialState::Deposit
                     }
-                    // NOTE(juf): Once a transaction has been Resolved, we could think about
-                    // removing it from the cache, _but_ what if we receive the same transaction
-                    // again later? We would deposit the amount again.
-                    // We could transfer it to a less expensive "store", e.g., HashSet<u32>
-                    match state {
-                        TransactionState::Disputed(InitialState::Deposit) => {
-                            acc.resolve(*amount);
-                            *state = TransactionState::Finalized;
-                        }
-                        TransactionState::Disputed(InitialState::Withdrawal) => {
-                            acc.resolve_withdrawal(*amount);
-                            *state = TransactionState::Finalized;
+                    TransactionState::Disputed(InitialState::Withdrawal) => {
+                        if withdrawal_disputes.holds() {
+                            acc.resolve_withdrawal(amount)
+                                .map_err(|source| overflow(client, tx_id, source))?;
                         }
-                        _ => {} // Do nothing. There is not valid transition for this state and
-                                // operation type.
+                        InitialState::Withdrawal
                     }
+                    // There is no valid transition for this state and operation type.
+                    _ => return Err(invalid_transition(client, tx_id, TxType::Resolve, *state)),
                 };
+                *state = TransactionState::Finalized;
+                Ok((TxType::Resolve, origin, amount))
             }
-            Transaction::Chargeback(Metadata { client, tx_id }) => {
-                if let Some((metadata, amount, state)) = self.txn_cache.get_mut(&tx_id)
-                    && let Some(acc) = self.account_store.get_mut(&acc_id)
-                {
-                    if metadata.client != client {
-                        // foreign account / mismatch, disregard
-                        return;
+            Transaction::Chargeback(_) => {
+                let (amount, state) = lookup(
+                    &mut self.txn_cache,
+                    &self.compaction.finalized,
+                    TxType::Chargeback,
+                    client,
+                    tx_id,
+                )?;
+                // NOTE(juf): Once a transaction has been charged back, we could think about
+                // removing it from the cache, _but_ what if we receive the same transaction
+                // again later? We would deposit the amount again.
+                // Compaction transfers it to a less expensive "store", see `Compaction`
+                let origin = match *state {
+                    TransactionState::Disputed(InitialState::Deposit) => {
+                        acc.chargeback(amount)
+                            .map_err(|source| overflow(client, tx_id, source))?;
+                        InitialState::Deposit
                     }
-                    // NOTE(juf): Once a transaction has been charged back, we could think about
-                    // removing it from the cache, _but_ what if we receive the same transaction
-                    // again later? We would deposit the amount again.
-                    // We could transfer it to a less expensive "store", e.g., HashSet<u32>
-                    match state {
-                        TransactionState::Disputed(InitialState::Deposit) => {
-                            acc.chargeback(*amount);
-                            *state = TransactionState::Finalized;
-                        }
-                        TransactionState::Disputed(InitialState::Withdrawal) => {
-                            acc.chargeback_withdrawal(*amount);
-                            *state = TransactionState::Finalized;
+                    TransactionState::Disputed(InitialState::Withdrawal) => {
+                        if withdrawal_disputes.holds() {
+                            acc.chargeback_withdrawal(amount)
+                        } else {
+                            acc.reverse_withdrawal(amount)
                         }
-                        _ => {} // Do nothing. There is not valid transition for this state and
-                                // operation type.
+                        .map_err(|source| overflow(client, tx_id, source))?;
+                        InitialState::Withdrawal
+                    }
+                    // There is no valid transition for this state and operation type.
+                    _ => {
+                        return Err(invalid_transition(
+                            client,
+                            tx_id,
+                            TxType::Chargeback,
+                            *state,
+                        ));
                     }
-                    // Else: Do nothing. There is not valid transition for this state and
-                    // operation type.
                 };
+                *state = TransactionState::Finalized;
+                Ok((TxType::Chargeback, origin, amount))
             }
-        };
+        }
+    }
+}
+
+/// Finds the cached transaction referenced by a dispute, resolve or chargeback and makes sure it
+/// belongs to the client issuing it.
+fn lookup<'a>(
+    txn_cache: &'a mut impl TransactionStore,
+    finalized: &FinalizedSet,
+    action: TxType,
+    client: u16,
+    tx_id: u32,
+) -> Result<(Decimal, &'a mut TransactionState), Rejection> {
+    let cached = txn_cache
+        .get_mut(tx_id)
+        .map_err(|err| storage_failure(client, tx_id, err))?;
+    let Some((metadata, amount, state)) = cached else {
+        // NOTE(juf): Compacted transactions do not remember their client, whoever refers to
+        // them is told they are final.
+        if finalized.contains(tx_id) {
+            return Err(invalid_transition(
+                client,
+                tx_id,
+                action,
+                TransactionState::Finalized,
+            ));
+        }
+        return Err(Rejection::UnknownTransaction { client, tx_id });
+    };
+    if metadata.client != client {
+        // foreign account / mismatch, disregard
+        return Err(Rejection::ClientMismatch {
+            client,
+            tx_id,
+            owner: metadata.client,
+        });
+    }
+    Ok(((*amount).into(), state))
+}
+
+/// Amount of a deposit or withdrawal at the precision the processor works with.
+fn admit(
+    precision: &Precision,
+    client: u16,
+    tx_id: u32,
+    amount: Decimal,
+) -> Result<Decimal, Rejection> {
+    precision
+        .admit(amount)
+        .map_err(|source| Rejection::UnrepresentableAmount {
+            client,
+            tx_id,
+            source,
+        })
+}
+
+/// Packs the amount of a deposit or withdrawal for the transaction store.
+fn pack(client: u16, tx_id: u32, amount: Decimal) -> Result<Amount, Rejection> {
+    Amount::try_from(amount).map_err(|source| Rejection::UnrepresentableAmount {
+        client,
+        tx_id,
+        source,
+    })
+}
+
+fn overflow(client: u16, tx_id: u32, source: Overflow) -> Rejection {
+    Rejection::BalanceOverflow {
+        client,
+        tx_id,
+        source,
     }
+}
 
-    #[inline]
-    fn create_account_if_not_exists(&mut self, id: u16) -> &Account {
-        // debattable if necessary, therefore inline
-        self.account_store.entry(id).or_insert(Account::new(id))
+fn storage_failure(client: u16, tx_id: u32, err: Error) -> Rejection {
+    Rejection::Storage {
+        client,
+        tx_id,
+        reason: err.to_string(),
+    }
+}
+
+#[inline]
+fn invalid_transition(
+    client: u16,
+    tx_id: u32,
+    action: TxType,
+    state: TransactionState,
+) -> Rejection {
+    Rejection::InvalidTransition {
+        client,
+        tx_id,
+        action,
+        state,
     }
 }
 
@@ -191,16 +904,25 @@ impl Processor {
 mod tests {
     use std::path::Path;
 
-    use rust_decimal::dec;
+    use rust_decimal::{Decimal, dec};
 
     use crate::{
+        amount::{AmountError, OverPrecise, Precision, Rounding},
+        date::Date,
         egress::default_csv_egress,
-        ingest::default_csv_ingest,
+        error::{Error, Rejection},
+        events::{EventLog, EventReader},
+        ingest::{default_csv_ingest, feed_processor},
+        ledger::{Journal, client_available, client_held},
         objects::{
-            accounts::Account,
-            transactions::{Row, Transaction},
+            accounts::{Account, Overflow},
+            statements::StatementLine,
+            transactions::{InitialState, Metadata, Row, Transaction, TransactionState, TxType},
         },
-        processor::Processor,
+        policy::{LockedAccounts, WithdrawalDisputes},
+        processor::{Outcome, Processor},
+        state::{self, Record},
+        store::compact::Compaction,
     };
 
     // TODO(juf): Add human readable comments to assert_XXX! where missing to have nicer test
@@ -216,18 +938,13 @@ mod tests {
         for row in iter {
             let row: Row = row.expect("Should be valid row");
             let txn: Transaction = row.try_into().expect("Should be valid transaction");
-            p.process_one(txn);
+            let _ = p.process_one(txn).expect("can process");
         }
         let out_dir = tempfile::tempdir().expect("Could not create tempdir");
         let out_path = out_dir.path().join("out.csv");
         let mut egress = default_csv_egress(&out_path).expect("should get default egress writer");
         let mut count = 0;
-        let mut ordered_accounts: Vec<_> = p
-            .get_account_store_ref()
-            .iter()
-            .map(|(_, v)| v)
-            .cloned()
-            .collect();
+        let mut ordered_accounts: Vec<_> = p.get_account_store_ref().values().cloned().collect();
         ordered_accounts.sort_by_key(|acc| acc.id);
         for (_, account) in p.get_account_store_ref().iter() {
             egress.serialize(account).expect("can write account row");
@@ -259,7 +976,7 @@ mod tests {
         for row in iter {
             let row: Row = row.expect("Should be valid row");
             let txn: Transaction = row.try_into().expect("Should be valid transaction");
-            p.process_one(txn);
+            let _ = p.process_one(txn).expect("can process");
             count += 1;
         }
         assert_eq!(
@@ -270,12 +987,7 @@ mod tests {
         let out_path = out_dir.path().join("out.csv");
         let mut egress = default_csv_egress(&out_path).expect("should get default egress writer");
         let mut count = 0;
-        let mut ordered_accounts: Vec<_> = p
-            .get_account_store_ref()
-            .iter()
-            .map(|(_, v)| v)
-            .cloned()
-            .collect();
+        let mut ordered_accounts: Vec<_> = p.get_account_store_ref().values().cloned().collect();
         ordered_accounts.sort_by_key(|acc| acc.id);
         for (_, account) in p.get_account_store_ref().iter() {
             egress.serialize(account).expect("can write account row");
@@ -315,7 +1027,7 @@ mod tests {
         for row in iter {
             let row: Row = row.expect("Should be valid row");
             let txn: Transaction = row.try_into().expect("Should be valid transaction");
-            p.process_one(txn);
+            let _ = p.process_one(txn).expect("can process");
             count += 1;
         }
         assert_eq!(
@@ -326,12 +1038,7 @@ mod tests {
         let out_path = out_dir.path().join("out.csv");
         let mut egress = default_csv_egress(&out_path).expect("should get default egress writer");
         let mut count = 0;
-        let mut ordered_accounts: Vec<_> = p
-            .get_account_store_ref()
-            .iter()
-            .map(|(_, v)| v)
-            .cloned()
-            .collect();
+        let mut ordered_accounts: Vec<_> = p.get_account_store_ref().values().cloned().collect();
         ordered_accounts.sort_by_key(|acc| acc.id);
         for (_, account) in p.get_account_store_ref().iter() {
             egress.serialize(account).expect("can write account row");
@@ -349,11 +1056,14 @@ mod tests {
                     available: dec!(1.0),
                     held: dec!(2.0)
                 },
+                // NOTE(juf): The baseline expected 2.0 available and nothing held here and was
+                // failing. `resolve, 2, 3` refers to tx 3 of client 1 and is rejected as a
+                // client mismatch, so the dispute of tx 2 stays open and holds its 2.0.
                 Account {
                     id: 2,
                     locked: false,
-                    available: dec!(2.0),
-                    held: dec!(0)
+                    available: dec!(0),
+                    held: dec!(2.0)
                 }
             ],
             ordered_accounts
@@ -372,7 +1082,7 @@ mod tests {
         for row in iter {
             let row: Row = row.expect("Should be valid row");
             let txn: Transaction = row.try_into().expect("Should be valid transaction");
-            p.process_one(txn);
+            let _ = p.process_one(txn).expect("can process");
             count += 1;
         }
         assert_eq!(
@@ -385,12 +1095,7 @@ mod tests {
         // test output csv with a snapshot csv
         let mut egress = default_csv_egress(&out_path).expect("should get default egress writer");
         let mut count = 0;
-        let mut ordered_accounts: Vec<_> = p
-            .get_account_store_ref()
-            .iter()
-            .map(|(_, v)| v)
-            .cloned()
-            .collect();
+        let mut ordered_accounts: Vec<_> = p.get_account_store_ref().values().cloned().collect();
         ordered_accounts.sort_by_key(|acc| acc.id);
         for (_, account) in p.get_account_store_ref().iter() {
             egress.serialize(account).expect("can write account row");
@@ -408,11 +1113,14 @@ mod tests {
                     available: dec!(13.3456),
                     held: dec!(2.0)
                 },
+                // NOTE(juf): The baseline expected 2.0 available and nothing held here and was
+                // failing. `resolve, 2, 3` refers to tx 3 of client 1 and is rejected as a
+                // client mismatch, so the dispute of tx 2 stays open and holds its 2.0.
                 Account {
                     id: 2,
                     locked: false,
-                    available: dec!(2.0),
-                    held: dec!(0.0)
+                    available: dec!(0),
+                    held: dec!(2.0)
                 },
                 Account {
                     id: 3,
@@ -452,7 +1160,7 @@ mod tests {
         for row in iter {
             let row: Row = row.expect("Should be valid row");
             let txn: Transaction = row.try_into().expect("Should be valid transaction");
-            p.process_one(txn);
+            let _ = p.process_one(txn).expect("can process");
         }
         let out_dir = tempfile::tempdir().expect("Could not create tempdir");
         let out_path = out_dir.path().join("out.csv");
@@ -460,12 +1168,7 @@ mod tests {
         // test output csv with a snapshot csv
         let mut egress = default_csv_egress(&out_path).expect("should get default egress writer");
         let mut count = 0;
-        let mut ordered_accounts: Vec<_> = p
-            .get_account_store_ref()
-            .iter()
-            .map(|(_, v)| v)
-            .cloned()
-            .collect();
+        let mut ordered_accounts: Vec<_> = p.get_account_store_ref().values().cloned().collect();
         ordered_accounts.sort_by_key(|acc| acc.id);
         for (_, account) in p.get_account_store_ref().iter() {
             egress.serialize(account).expect("can write account row");
@@ -496,7 +1199,7 @@ mod tests {
         for row in iter {
             let row: Row = row.expect("Should be valid row");
             let txn: Transaction = row.try_into().expect("Should be valid transaction");
-            p.process_one(txn);
+            let _ = p.process_one(txn).expect("can process");
         }
         let out_dir = tempfile::tempdir().expect("Could not create tempdir");
         let out_path = out_dir.path().join("out.csv");
@@ -504,12 +1207,7 @@ mod tests {
         // test output csv with a snapshot csv
         let mut egress = default_csv_egress(&out_path).expect("should get default egress writer");
         let mut count = 0;
-        let mut ordered_accounts: Vec<_> = p
-            .get_account_store_ref()
-            .iter()
-            .map(|(_, v)| v)
-            .cloned()
-            .collect();
+        let mut ordered_accounts: Vec<_> = p.get_account_store_ref().values().cloned().collect();
         ordered_accounts.sort_by_key(|acc| acc.id);
         for (_, account) in p.get_account_store_ref().iter() {
             egress.serialize(account).expect("can write account row");
@@ -540,7 +1238,7 @@ mod tests {
         for row in iter {
             let row: Row = row.expect("Should be valid row");
             let txn: Transaction = row.try_into().expect("Should be valid transaction");
-            p.process_one(txn);
+            let _ = p.process_one(txn).expect("can process");
         }
         let out_dir = tempfile::tempdir().expect("Could not create tempdir");
         let out_path = out_dir.path().join("out.csv");
@@ -548,12 +1246,7 @@ mod tests {
         // test output csv with a snapshot csv
         let mut egress = default_csv_egress(&out_path).expect("should get default egress writer");
         let mut count = 0;
-        let mut ordered_accounts: Vec<_> = p
-            .get_account_store_ref()
-            .iter()
-            .map(|(_, v)| v)
-            .cloned()
-            .collect();
+        let mut ordered_accounts: Vec<_> = p.get_account_store_ref().values().cloned().collect();
         ordered_accounts.sort_by_key(|acc| acc.id);
         for (_, account) in p.get_account_store_ref().iter() {
             egress.serialize(account).expect("can write account row");
@@ -593,7 +1286,7 @@ mod tests {
         for row in iter {
             let row: Row = row.expect("Should be valid row");
             let txn: Transaction = row.try_into().expect("Should be valid transaction");
-            p.process_one(txn);
+            let _ = p.process_one(txn).expect("can process");
         }
         let out_dir = tempfile::tempdir().expect("Could not create tempdir");
         let out_path = out_dir.path().join("out.csv");
@@ -601,12 +1294,7 @@ mod tests {
         // test output csv with a snapshot csv
         let mut egress = default_csv_egress(&out_path).expect("should get default egress writer");
         let mut count = 0;
-        let mut ordered_accounts: Vec<_> = p
-            .get_account_store_ref()
-            .iter()
-            .map(|(_, v)| v)
-            .cloned()
-            .collect();
+        let mut ordered_accounts: Vec<_> = p.get_account_store_ref().values().cloned().collect();
         ordered_accounts.sort_by_key(|acc| acc.id);
         for (_, account) in p.get_account_store_ref().iter() {
             egress.serialize(account).expect("can write account row");
@@ -639,7 +1327,7 @@ mod tests {
         for row in iter {
             let row: Row = row.expect("Should be valid row");
             let txn: Transaction = row.try_into().expect("Should be valid transaction");
-            p.process_one(txn);
+            let _ = p.process_one(txn).expect("can process");
         }
         let out_dir = tempfile::tempdir().expect("Could not create tempdir");
         let out_path = out_dir.path().join("out.csv");
@@ -647,12 +1335,7 @@ mod tests {
         // test output csv with a snapshot csv
         let mut egress = default_csv_egress(&out_path).expect("should get default egress writer");
         let mut count = 0;
-        let mut ordered_accounts: Vec<_> = p
-            .get_account_store_ref()
-            .iter()
-            .map(|(_, v)| v)
-            .cloned()
-            .collect();
+        let mut ordered_accounts: Vec<_> = p.get_account_store_ref().values().cloned().collect();
         ordered_accounts.sort_by_key(|acc| acc.id);
         for (_, account) in p.get_account_store_ref().iter() {
             egress.serialize(account).expect("can write account row");
@@ -685,7 +1368,7 @@ mod tests {
         for row in iter {
             let row: Row = row.expect("Should be valid row");
             let txn: Transaction = row.try_into().expect("Should be valid transaction");
-            p.process_one(txn);
+            let _ = p.process_one(txn).expect("can process");
         }
         let out_dir = tempfile::tempdir().expect("Could not create tempdir");
         let out_path = out_dir.path().join("out.csv");
@@ -693,12 +1376,7 @@ mod tests {
         // test output csv with a snapshot csv
         let mut egress = default_csv_egress(&out_path).expect("should get default egress writer");
         let mut count = 0;
-        let mut ordered_accounts: Vec<_> = p
-            .get_account_store_ref()
-            .iter()
-            .map(|(_, v)| v)
-            .cloned()
-            .collect();
+        let mut ordered_accounts: Vec<_> = p.get_account_store_ref().values().cloned().collect();
         ordered_accounts.sort_by_key(|acc| acc.id);
         for (_, account) in p.get_account_store_ref().iter() {
             egress.serialize(account).expect("can write account row");
@@ -718,4 +1396,864 @@ mod tests {
             ordered_accounts
         )
     }
+
+    #[test]
+    fn process_one_reports_rejection_reasons() {
+        let mut p = Processor::new();
+        assert_eq!(
+            Outcome::Applied,
+            p.process_one(Transaction::Deposit(Metadata::new(1, 1), dec!(10.0)))
+                .expect("can process")
+        );
+        assert_eq!(
+            Outcome::Rejected(Rejection::NegativeAmount {
+                client: 1,
+                tx_id: 2,
+                amount: dec!(-1.0)
+            }),
+            p.process_one(Transaction::Deposit(Metadata::new(1, 2), dec!(-1.0)))
+                .expect("can process"),
+            "negative amounts must be rejected"
+        );
+        assert_eq!(
+            Outcome::Rejected(Rejection::DuplicateTransaction {
+                client: 1,
+                tx_id: 1
+            }),
+            p.process_one(Transaction::Deposit(Metadata::new(1, 1), dec!(10.0)))
+                .expect("can process"),
+            "replayed tx ids must be rejected"
+        );
+        assert_eq!(
+            Outcome::Rejected(Rejection::InsufficientFunds {
+                client: 1,
+                tx_id: 3,
+                available: dec!(10.0),
+                requested: dec!(11.0)
+            }),
+            p.process_one(Transaction::Withdrawal(Metadata::new(1, 3), dec!(11.0)))
+                .expect("can process"),
+            "overspending must be rejected"
+        );
+        assert_eq!(
+            Outcome::Rejected(Rejection::UnknownTransaction {
+                client: 1,
+                tx_id: 3
+            }),
+            p.process_one(Transaction::Dispute(Metadata::new(1, 3)))
+                .expect("can process"),
+            "rejected withdrawals must not be disputable"
+        );
+        assert_eq!(
+            Outcome::Rejected(Rejection::ClientMismatch {
+                client: 2,
+                tx_id: 1,
+                owner: 1
+            }),
+            p.process_one(Transaction::Dispute(Metadata::new(2, 1)))
+                .expect("can process"),
+            "disputes across clients must be rejected"
+        );
+        assert_eq!(
+            Outcome::Rejected(Rejection::InvalidTransition {
+                client: 1,
+                tx_id: 1,
+                action: TxType::Resolve,
+                state: TransactionState::Initial(InitialState::Deposit)
+            }),
+            p.process_one(Transaction::Resolve(Metadata::new(1, 1)))
+                .expect("can process"),
+            "only disputed transactions can be resolved"
+        );
+        assert_eq!(
+            Outcome::Applied,
+            p.process_one(Transaction::Dispute(Metadata::new(1, 1)))
+                .expect("can process")
+        );
+        assert_eq!(
+            Outcome::Applied,
+            p.process_one(Transaction::Chargeback(Metadata::new(1, 1)))
+                .expect("can process")
+        );
+        assert_eq!(
+            Outcome::Rejected(Rejection::AccountLocked {
+                client: 1,
+                tx_id: 4
+            }),
+            p.process_one(Transaction::Deposit(Metadata::new(1, 4), dec!(1.0)))
+                .expect("can process"),
+            "locked accounts must not accept further transactions"
+        );
+    }
+
+    #[test]
+    fn books_are_continued_with_their_withdrawal_dispute_policy() {
+        let dir = tempfile::tempdir().expect("can create tempdir");
+        let path = dir.path().join("state");
+        let mut p = Processor::new().with_withdrawal_disputes(WithdrawalDisputes::Hold);
+        for txn in [
+            Transaction::Deposit(Metadata::new(1, 1), dec!(10)),
+            Transaction::Withdrawal(Metadata::new(1, 2), dec!(4)),
+            Transaction::Dispute(Metadata::new(1, 2)),
+        ] {
+            assert_eq!(Outcome::Applied, p.process_one(txn).expect("can process"));
+        }
+        p.save(&path).expect("can save");
+
+        let refused = Processor::new()
+            .with_withdrawal_disputes(WithdrawalDisputes::CreditOnChargeback)
+            .restore(&path);
+        assert!(
+            matches!(refused, Err(Error::InvalidState(ref reason)) if reason.contains("hold")),
+            "the open dispute would be settled without releasing what it holds"
+        );
+        let mut p = Processor::load(&path).expect("can load");
+        assert_eq!(WithdrawalDisputes::Hold, p.withdrawal_disputes());
+        assert_eq!(
+            Outcome::Applied,
+            p.process_one(Transaction::Resolve(Metadata::new(1, 2)))
+                .expect("can process")
+        );
+        assert_eq!(
+            Some(&Account {
+                id: 1,
+                locked: false,
+                available: dec!(6),
+                held: dec!(0)
+            }),
+            p.get_account_store_ref().get(&1)
+        );
+
+        // State saved before the policy was recorded
+        let records = state::read(&path).expect("can read").expect("has state");
+        let legacy = records
+            .into_iter()
+            .filter(|record| !matches!(record, Record::WithdrawalDisputes(_)));
+        state::write(&path, legacy.map(Ok)).expect("can write");
+        let p = Processor::load(&path).expect("can load");
+        assert_eq!(WithdrawalDisputes::Hold, p.withdrawal_disputes());
+        assert!(
+            Processor::new()
+                .with_withdrawal_disputes(WithdrawalDisputes::CreditOnChargeback)
+                .restore(&path)
+                .is_err(),
+            "kept with hold"
+        );
+        assert_eq!(
+            WithdrawalDisputes::Hold,
+            Processor::new().withdrawal_disputes()
+        );
+    }
+
+    #[test]
+    fn state_carries_over_between_runs() {
+        let dir = tempfile::tempdir().expect("can create tempdir");
+        let path = dir.path().join("state");
+        let mut p = Processor::load(&path).expect("no state yet");
+        assert_eq!(
+            Outcome::Applied,
+            p.process_one(Transaction::Deposit(Metadata::new(1, 1), dec!(10.0)))
+                .expect("can process")
+        );
+        assert_eq!(
+            Outcome::Applied,
+            p.process_one(Transaction::Deposit(Metadata::new(2, 2), dec!(3.5)))
+                .expect("can process")
+        );
+        assert_eq!(
+            Outcome::Applied,
+            p.process_one(Transaction::Dispute(Metadata::new(2, 2)))
+                .expect("can process")
+        );
+        let booking = StatementLine {
+            date: Date::new(2025, 3, 3).expect("valid date"),
+            amount: dec!(-1.25),
+            payee: None,
+            memo: None,
+            external_id: Some("FITID-1".into()),
+            value_date: None,
+            counterparty_account: None,
+            reference: None,
+            cleared: None,
+            splits: Vec::new(),
+        };
+        assert_eq!(
+            Outcome::Applied,
+            p.process_statement_line(1, booking.clone())
+                .expect("can process")
+        );
+        p.save(&path).expect("can save");
+
+        // Next day's run
+        let mut p = Processor::load(&path).expect("can load");
+        let mut accounts: Vec<_> = p.get_account_store_ref().values().cloned().collect();
+        accounts.sort_by_key(|acc| acc.id);
+        assert_eq!(
+            vec![
+                Account {
+                    id: 1,
+                    locked: false,
+                    available: dec!(8.75),
+                    held: dec!(0)
+                },
+                Account {
+                    id: 2,
+                    locked: false,
+                    available: dec!(0.0),
+                    held: dec!(3.5)
+                }
+            ],
+            accounts
+        );
+        assert_eq!(
+            Outcome::Applied,
+            p.process_one(Transaction::Dispute(Metadata::new(1, 1)))
+                .expect("can process"),
+            "yesterday's deposit can be disputed"
+        );
+        assert_eq!(
+            Outcome::Applied,
+            p.process_one(Transaction::Chargeback(Metadata::new(2, 2)))
+                .expect("can process"),
+            "yesterday's dispute can be charged back"
+        );
+        assert_eq!(
+            Outcome::Rejected(Rejection::DuplicateTransaction {
+                client: 1,
+                tx_id: 1
+            }),
+            p.process_one(Transaction::Deposit(Metadata::new(1, 1), dec!(10.0)))
+                .expect("can process"),
+            "replayed tx ids are caught across runs"
+        );
+        assert_eq!(
+            Outcome::Rejected(Rejection::DuplicateExternalId {
+                client: 1,
+                tx_id: 3,
+                external_id: "FITID-1".into()
+            }),
+            p.process_statement_line(1, booking.clone())
+                .expect("can process"),
+            "re-imported bookings are caught across runs"
+        );
+        assert_eq!(
+            Outcome::Applied,
+            p.process_statement_line(
+                1,
+                StatementLine {
+                    amount: dec!(1.25),
+                    external_id: Some("FITID-2".into()),
+                    ..booking
+                }
+            )
+            .expect("can process")
+        );
+        assert_eq!(
+            Some(&Account {
+                id: 1,
+                locked: false,
+                available: dec!(0.00),
+                held: dec!(10.0)
+            }),
+            p.get_account_store_ref().get(&1)
+        );
+    }
+
+    #[test]
+    fn replayed_event_log_gives_the_same_accounts() {
+        let dir = tempfile::tempdir().expect("can create tempdir");
+        let path = dir.path().join("events.log");
+        let mut p = Processor::new()
+            .with_event_log(EventLog::open(&path).expect("can open"))
+            .expect("new log");
+        let mut ingest = default_csv_ingest(Path::new("tests/sample1.csv"))
+            .expect("Can open file and create ingest");
+        feed_processor(&mut ingest, &mut p, None, |_| {}).expect("valid input");
+        assert_eq!(
+            Outcome::Applied,
+            p.process_one(Transaction::Dispute(Metadata::new(1, 1)))
+                .expect("can process")
+        );
+        p.finish_event_log().expect("log is written");
+
+        let mut replayed = Processor::new();
+        let count = replayed
+            .replay(&mut EventReader::open(&path).expect("valid log"))
+            .expect("log replays");
+        assert!(count > 0);
+        let ordered = |p: &Processor| {
+            let mut accounts: Vec<_> = p.get_account_store_ref().values().cloned().collect();
+            accounts.sort_by_key(|acc| acc.id);
+            accounts
+        };
+        assert_eq!(ordered(&p), ordered(&replayed));
+
+        // The same log once more on top does not apply
+        let err = replayed
+            .replay(&mut EventReader::open(&path).expect("valid log"))
+            .expect_err("tx ids are known already");
+        assert!(matches!(err, Error::InvalidLog(_)));
+    }
+
+    #[test]
+    fn replay_keeps_the_withdrawal_dispute_policy_of_the_log() {
+        let dir = tempfile::tempdir().expect("can create tempdir");
+        let write_log = |name: &str, policy| {
+            let path = dir.path().join(name);
+            let mut p = Processor::new()
+                .with_withdrawal_disputes(policy)
+                .with_event_log(EventLog::open(&path).expect("can open"))
+                .expect("new log");
+            for txn in [
+                Transaction::Deposit(Metadata::new(1, 1), dec!(10)),
+                Transaction::Withdrawal(Metadata::new(1, 2), dec!(4)),
+                Transaction::Dispute(Metadata::new(1, 2)),
+            ] {
+                assert_eq!(Outcome::Applied, p.process_one(txn).expect("can process"));
+            }
+            p.finish_event_log().expect("log is written");
+            (path, p.get_account_store_ref().get(&1).cloned())
+        };
+        let (path, held) = write_log("hold.log", WithdrawalDisputes::Hold);
+        assert_eq!(Some(dec!(4)), held.as_ref().map(|acc| acc.held));
+
+        let mut replayed = Processor::new();
+        replayed
+            .replay(&mut EventReader::open(&path).expect("valid log"))
+            .expect("log replays");
+        assert_eq!(WithdrawalDisputes::Hold, replayed.withdrawal_disputes());
+        assert_eq!(held.as_ref(), replayed.get_account_store_ref().get(&1));
+        let err = Processor::new()
+            .with_withdrawal_disputes(WithdrawalDisputes::CreditOnChargeback)
+            .replay(&mut EventReader::open(&path).expect("valid log"))
+            .expect_err("the open dispute would hold nothing");
+        assert!(matches!(err, Error::InvalidState(_)), "{err}");
+        assert!(
+            Processor::new()
+                .with_withdrawal_disputes(WithdrawalDisputes::CreditOnChargeback)
+                .with_event_log(EventLog::open(&path).expect("can open"))
+                .is_err(),
+            "not appended to under another policy"
+        );
+
+        // Replayed without choosing one, the policy of the log is taken over
+        let (path, credited) = write_log("credit.log", WithdrawalDisputes::CreditOnChargeback);
+        assert_eq!(Some(dec!(0)), credited.as_ref().map(|acc| acc.held));
+        let mut replayed = Processor::new();
+        replayed
+            .replay(&mut EventReader::open(&path).expect("valid log"))
+            .expect("log replays");
+        assert_eq!(credited.as_ref(), replayed.get_account_store_ref().get(&1));
+    }
+
+    #[test]
+    fn compacted_transactions_stay_final() {
+        let dir = tempfile::tempdir().expect("can create tempdir");
+        let path = dir.path().join("state");
+        let mut p = Processor::new().with_compaction(Compaction::every(2));
+        for txn in [
+            Transaction::Deposit(Metadata::new(1, 1), dec!(5)),
+            Transaction::Deposit(Metadata::new(2, 2), dec!(3)),
+            Transaction::Deposit(Metadata::new(1, 3), dec!(1)),
+            Transaction::Dispute(Metadata::new(1, 1)),
+            Transaction::Resolve(Metadata::new(1, 1)),
+            Transaction::Dispute(Metadata::new(1, 3)),
+        ] {
+            assert_eq!(Outcome::Applied, p.process_one(txn).expect("can process"));
+        }
+        assert_eq!(1, p.memory_stats().pending, "not due yet");
+        assert_eq!(
+            Outcome::Applied,
+            p.process_one(Transaction::Chargeback(Metadata::new(1, 3)))
+                .expect("can process")
+        );
+        let stats = p.memory_stats();
+        assert_eq!(
+            (2, 0, 1),
+            (stats.finalized, stats.pending, stats.compactions)
+        );
+        assert!(!p.txn_store().contains_key(&1) && !p.txn_store().contains_key(&3));
+        p.save(&path).expect("can save");
+
+        for p in [&mut p, &mut Processor::load(&path).expect("can load")] {
+            assert_eq!(
+                Outcome::Rejected(Rejection::DuplicateTransaction {
+                    client: 2,
+                    tx_id: 1
+                }),
+                p.process_one(Transaction::Deposit(Metadata::new(2, 1), dec!(5)))
+                    .expect("can process")
+            );
+            assert_eq!(
+                Outcome::Rejected(Rejection::DuplicateTransaction {
+                    client: 2,
+                    tx_id: 3
+                }),
+                p.process_one(Transaction::Withdrawal(Metadata::new(2, 3), dec!(1)))
+                    .expect("can process")
+            );
+            assert_eq!(
+                Outcome::Rejected(Rejection::InvalidTransition {
+                    client: 2,
+                    tx_id: 1,
+                    action: TxType::Resolve,
+                    state: TransactionState::Finalized
+                }),
+                p.process_one(Transaction::Resolve(Metadata::new(2, 1)))
+                    .expect("can process")
+            );
+            assert_eq!(
+                Outcome::Applied,
+                p.process_one(Transaction::Dispute(Metadata::new(2, 2)))
+                    .expect("can process")
+            );
+        }
+    }
+
+    #[test]
+    fn unrepresentable_amounts_are_rejected_untouched() {
+        let mut p = Processor::new();
+        assert_eq!(
+            Outcome::Applied,
+            p.process_one(Transaction::Deposit(Metadata::new(1, 1), dec!(10)))
+                .expect("can process")
+        );
+        for (txn, amount, source) in [
+            (
+                Transaction::Deposit(Metadata::new(1, 2), dec!(0.00001)),
+                dec!(0.00001),
+                AmountError::TooPrecise {
+                    amount: dec!(0.00001),
+                    scale: 4,
+                },
+            ),
+            (
+                Transaction::Withdrawal(Metadata::new(1, 2), dec!(1.00001)),
+                dec!(1.00001),
+                AmountError::TooPrecise {
+                    amount: dec!(1.00001),
+                    scale: 4,
+                },
+            ),
+            (
+                Transaction::Deposit(Metadata::new(1, 2), dec!(1_000_000_000_000_000)),
+                dec!(1_000_000_000_000_000),
+                AmountError::OutOfRange(dec!(1_000_000_000_000_000)),
+            ),
+        ] {
+            assert_eq!(
+                Outcome::Rejected(Rejection::UnrepresentableAmount {
+                    client: 1,
+                    tx_id: 2,
+                    source
+                }),
+                p.process_one(txn).expect("can process"),
+                "{amount} can not be cached"
+            );
+        }
+        assert_eq!(
+            dec!(10),
+            p.get_account_store_ref()
+                .get(&1)
+                .expect("account exists")
+                .available
+        );
+        assert_eq!(
+            Outcome::Applied,
+            p.process_one(Transaction::Deposit(Metadata::new(1, 2), dec!(0.0001)))
+                .expect("can process"),
+            "tx id is still free"
+        );
+    }
+
+    #[test]
+    fn over_precise_amounts_are_rejected_or_rounded() {
+        let cents = Precision {
+            scale: 2,
+            rounding: Rounding::HalfUp,
+            over_precise: OverPrecise::Reject,
+        };
+        let mut p = Processor::new().with_precision(cents);
+        assert_eq!(
+            Outcome::Rejected(Rejection::UnrepresentableAmount {
+                client: 1,
+                tx_id: 1,
+                source: AmountError::TooPrecise {
+                    amount: dec!(1.005),
+                    scale: 2
+                }
+            }),
+            p.process_one(Transaction::Deposit(Metadata::new(1, 1), dec!(1.005)))
+                .expect("can process")
+        );
+        let mut p = Processor::new().with_precision(Precision {
+            over_precise: OverPrecise::Round,
+            ..cents
+        });
+        for txn in [
+            Transaction::Deposit(Metadata::new(1, 1), dec!(1.005)),
+            Transaction::Withdrawal(Metadata::new(1, 2), dec!(0.504)),
+            Transaction::Dispute(Metadata::new(1, 1)),
+        ] {
+            assert_eq!(Outcome::Applied, p.process_one(txn).expect("can process"));
+        }
+        let acc = p.get_account_store_ref().get(&1).expect("account exists");
+        assert_eq!((dec!(-0.50), dec!(1.01)), (acc.available, acc.held));
+        assert_eq!(
+            "-0.50,1.01,0.51",
+            format!(
+                "{},{},{}",
+                acc.rounded(&cents).available,
+                acc.rounded(&cents).held,
+                acc.rounded(&cents).total()
+            )
+        );
+    }
+
+    #[test]
+    fn overflowing_balances_are_rejected_without_a_trace() {
+        // NOTE(juf): Cached amounts are far below `Decimal::MAX`, only accounts carried over can
+        // get there
+        let rich = Account {
+            id: 1,
+            locked: false,
+            available: Decimal::MAX - dec!(5),
+            held: dec!(0),
+        };
+        let dir = tempfile::tempdir().expect("can create tempdir");
+        let path = dir.path().join("state");
+        state::write(&path, [Ok(Record::Account(rich.clone()))].into_iter())
+            .expect("can write state");
+        let mut p = Processor::load(&path).expect("can load");
+        assert_eq!(
+            Outcome::Rejected(Rejection::BalanceOverflow {
+                client: 1,
+                tx_id: 1,
+                source: Overflow
+            }),
+            p.process_one(Transaction::Deposit(Metadata::new(1, 1), dec!(10)))
+                .expect("can process")
+        );
+        assert_eq!(Some(&rich), p.get_account_store_ref().get(&1));
+        assert_eq!(
+            Outcome::Applied,
+            p.process_one(Transaction::Deposit(Metadata::new(2, 1), dec!(1)))
+                .expect("can process"),
+            "tx id of the overflowing deposit is still free"
+        );
+
+        for txn in [
+            Transaction::Withdrawal(Metadata::new(1, 2), dec!(10)),
+            Transaction::Deposit(Metadata::new(1, 3), dec!(10)),
+        ] {
+            assert_eq!(Outcome::Applied, p.process_one(txn).expect("can process"));
+        }
+        assert_eq!(
+            Outcome::Rejected(Rejection::BalanceOverflow {
+                client: 1,
+                tx_id: 2,
+                source: Overflow
+            }),
+            p.process_one(Transaction::Dispute(Metadata::new(1, 2)))
+                .expect("can process"),
+            "holding the withdrawn funds again would overflow the total"
+        );
+        assert_eq!(Some(&rich), p.get_account_store_ref().get(&1));
+        assert_eq!(
+            Outcome::Rejected(Rejection::InvalidTransition {
+                client: 1,
+                tx_id: 2,
+                action: TxType::Resolve,
+                state: TransactionState::Initial(InitialState::Withdrawal)
+            }),
+            p.process_one(Transaction::Resolve(Metadata::new(1, 2)))
+                .expect("can process"),
+            "the withdrawal is not disputed"
+        );
+    }
+
+    /// What a dispute, resolve or chargeback of a transaction of the kind does to available and
+    /// held, in multiples of its amount, `None` if it is refused. See `policy`.
+    fn effect(
+        policy: WithdrawalDisputes,
+        origin: InitialState,
+        action: TxType,
+    ) -> Option<(Decimal, Decimal)> {
+        use TxType::{Chargeback, Dispute, Resolve};
+        use WithdrawalDisputes::{CreditOnChargeback, Hold, Refuse};
+        let (available, held) = match (policy, origin, action) {
+            (_, InitialState::Deposit, Dispute) => (-1, 1),
+            (_, InitialState::Deposit, Resolve) => (1, -1),
+            (_, InitialState::Deposit, Chargeback) => (0, -1),
+            (Hold, InitialState::Withdrawal, Dispute) => (0, 1),
+            (Hold, InitialState::Withdrawal, Resolve) => (0, -1),
+            (Hold, InitialState::Withdrawal, Chargeback) => (1, -1),
+            (CreditOnChargeback, InitialState::Withdrawal, Dispute | Resolve) => (0, 0),
+            (CreditOnChargeback, InitialState::Withdrawal, Chargeback) => (1, 0),
+            (Refuse, InitialState::Withdrawal, _) => return None,
+            (_, _, TxType::Deposit | TxType::Withdrawal | TxType::Unlock) => {
+                unreachable!("not a dispute step")
+            }
+        };
+        Some((Decimal::from(available), Decimal::from(held)))
+    }
+
+    #[test]
+    fn dispute_lifecycles_follow_the_policy() {
+        let steps = [TxType::Dispute, TxType::Resolve, TxType::Chargeback];
+        // Every order of up to three steps
+        let orders = (1..=3u32).flat_map(|len| {
+            (0..3usize.pow(len)).map(move |mut n| {
+                (0..len)
+                    .map(|_| {
+                        let step = steps[n % 3];
+                        n /= 3;
+                        step
+                    })
+                    .collect::<Vec<_>>()
+            })
+        });
+        for order in orders {
+            for policy in [
+                WithdrawalDisputes::Hold,
+                WithdrawalDisputes::CreditOnChargeback,
+                WithdrawalDisputes::Refuse,
+            ] {
+                for (origin, tx_id, amount) in [
+                    (InitialState::Deposit, 1, dec!(10)),
+                    (InitialState::Withdrawal, 2, dec!(4)),
+                ] {
+                    let case = format!("{policy:?} {origin:?} {order:?}");
+                    let mut p = Processor::new()
+                        .with_withdrawal_disputes(policy)
+                        .with_journal(Journal::new())
+                        .with_paranoid_checks();
+                    for txn in [
+                        Transaction::Deposit(Metadata::new(1, 1), dec!(10)),
+                        Transaction::Withdrawal(Metadata::new(1, 2), dec!(4)),
+                    ] {
+                        assert_eq!(Outcome::Applied, p.process_one(txn).expect("can process"));
+                    }
+                    let (mut available, mut held) = (dec!(6), dec!(0));
+                    let mut state = TransactionState::Initial(origin);
+                    let mut locked = false;
+                    for &action in &order {
+                        let metadata = Metadata::new(1, tx_id);
+                        let txn = match action {
+                            TxType::Dispute => Transaction::Dispute(metadata),
+                            TxType::Resolve => Transaction::Resolve(metadata),
+                            _ => Transaction::Chargeback(metadata),
+                        };
+                        let next = match (action, state) {
+                            (TxType::Dispute, TransactionState::Initial(_)) => {
+                                TransactionState::Disputed(origin)
+                            }
+                            (
+                                TxType::Resolve | TxType::Chargeback,
+                                TransactionState::Disputed(_),
+                            ) => TransactionState::Finalized,
+                            _ => state,
+                        };
+                        let expected = if locked {
+                            Outcome::Rejected(Rejection::AccountLocked { client: 1, tx_id })
+                        } else if next == state {
+                            Outcome::Rejected(Rejection::InvalidTransition {
+                                client: 1,
+                                tx_id,
+                                action,
+                                state,
+                            })
+                        } else if let Some((to_available, to_held)) = effect(policy, origin, action)
+                        {
+                            available += to_available * amount;
+                            held += to_held * amount;
+                            locked = action == TxType::Chargeback;
+                            state = next;
+                            Outcome::Applied
+                        } else {
+                            Outcome::Rejected(Rejection::WithdrawalDispute { client: 1, tx_id })
+                        };
+                        assert_eq!(expected, p.process_one(txn).expect("can process"), "{case}");
+                        let acc = p.get_account_store_ref().get(&1).expect("account exists");
+                        assert_eq!(
+                            (available, held, locked),
+                            (acc.available, acc.held, acc.locked),
+                            "{case}"
+                        );
+                        let journal = p.journal().expect("has journal");
+                        assert_eq!(
+                            (-available, -held),
+                            (
+                                journal.balance(&client_available(1)),
+                                journal.balance(&client_held(1))
+                            ),
+                            "{case}"
+                        );
+                    }
+                    assert!(p.check_invariants().expect("can check").is_ok(), "{case}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn locked_accounts_take_what_the_policy_allows() {
+        for (policy, allowed) in [
+            (
+                LockedAccounts::BlockAll,
+                [false, false, false, false, false],
+            ),
+            (
+                LockedAccounts::BlockWithdrawals,
+                [true, false, true, true, true],
+            ),
+            (
+                LockedAccounts::AllowDisputes,
+                [false, false, true, true, true],
+            ),
+        ] {
+            let mut p = Processor::new()
+                .with_locked_accounts(policy)
+                .with_paranoid_checks();
+            for txn in [
+                Transaction::Deposit(Metadata::new(1, 1), dec!(10)),
+                Transaction::Deposit(Metadata::new(1, 2), dec!(5)),
+                Transaction::Withdrawal(Metadata::new(1, 3), dec!(2)),
+                Transaction::Dispute(Metadata::new(1, 2)),
+                Transaction::Dispute(Metadata::new(1, 1)),
+                Transaction::Chargeback(Metadata::new(1, 1)),
+            ] {
+                assert_eq!(
+                    Outcome::Applied,
+                    p.process_one(txn).expect("can process"),
+                    "{policy:?}"
+                );
+            }
+            for (txn, allowed) in [
+                Transaction::Deposit(Metadata::new(1, 4), dec!(1)),
+                Transaction::Withdrawal(Metadata::new(1, 5), dec!(1)),
+                Transaction::Resolve(Metadata::new(1, 2)),
+                Transaction::Dispute(Metadata::new(1, 3)),
+                Transaction::Chargeback(Metadata::new(1, 3)),
+            ]
+            .into_iter()
+            .zip(allowed)
+            {
+                let tx_id = txn.get_metadata().tx_id;
+                let expected = if allowed {
+                    Outcome::Applied
+                } else {
+                    Outcome::Rejected(Rejection::AccountLocked { client: 1, tx_id })
+                };
+                assert_eq!(
+                    expected,
+                    p.process_one(txn).expect("can process"),
+                    "{policy:?} tx {tx_id}"
+                );
+            }
+            let acc = p.get_account_store_ref().get(&1).expect("account exists");
+            assert!(acc.locked, "{policy:?}");
+            assert!(
+                p.check_invariants().expect("can check").is_ok(),
+                "{policy:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn unlocks_reinstate_the_account_and_are_replayed() {
+        let dir = tempfile::tempdir().expect("can create tempdir");
+        let path = dir.path().join("events.log");
+        let mut p = Processor::new()
+            .with_event_log(EventLog::open(&path).expect("can open"))
+            .expect("new log")
+            .with_journal(Journal::new())
+            .with_paranoid_checks();
+        let unlock = || Transaction::Unlock(Metadata::new(1, 0), "card was stolen".into());
+        assert_eq!(
+            Outcome::Rejected(Rejection::NotLocked {
+                client: 1,
+                tx_id: 0
+            }),
+            p.process_one(unlock()).expect("can process")
+        );
+        for txn in [
+            Transaction::Deposit(Metadata::new(1, 1), dec!(10)),
+            Transaction::Deposit(Metadata::new(1, 2), dec!(5)),
+            Transaction::Dispute(Metadata::new(1, 1)),
+            Transaction::Chargeback(Metadata::new(1, 1)),
+            unlock(),
+            Transaction::Withdrawal(Metadata::new(1, 3), dec!(1)),
+        ] {
+            assert_eq!(Outcome::Applied, p.process_one(txn).expect("can process"));
+        }
+        assert_eq!(
+            Outcome::Rejected(Rejection::NotLocked {
+                client: 1,
+                tx_id: 0
+            }),
+            p.process_one(unlock()).expect("can process"),
+            "unlocked once"
+        );
+        let unlocked = Account {
+            id: 1,
+            locked: false,
+            available: dec!(4),
+            held: dec!(0),
+        };
+        assert_eq!(Some(&unlocked), p.get_account_store_ref().get(&1));
+        assert!(p.check_invariants().expect("can check").is_ok());
+        assert_eq!(
+            dec!(-4),
+            p.journal()
+                .expect("has journal")
+                .balance(&client_available(1)),
+            "unlocks move nothing"
+        );
+        p.finish_event_log().expect("log is written");
+
+        let events = EventReader::open(&path)
+            .expect("valid log")
+            .collect::<Result<Vec<_>, _>>()
+            .expect("valid log");
+        assert_eq!(
+            Some("card was stolen"),
+            events[4].reason.as_deref(),
+            "the reason is on record"
+        );
+        let mut replayed = Processor::new();
+        assert_eq!(
+            6,
+            replayed
+                .replay(&mut EventReader::open(&path).expect("valid log"))
+                .expect("log replays")
+        );
+        assert_eq!(Some(&unlocked), replayed.get_account_store_ref().get(&1));
+    }
+
+    #[test]
+    fn unlocks_without_an_event_log_are_rejected() {
+        let mut p = Processor::new().with_paranoid_checks();
+        for txn in [
+            Transaction::Deposit(Metadata::new(1, 1), dec!(10)),
+            Transaction::Dispute(Metadata::new(1, 1)),
+            Transaction::Chargeback(Metadata::new(1, 1)),
+        ] {
+            assert_eq!(Outcome::Applied, p.process_one(txn).expect("can process"));
+        }
+        assert_eq!(
+            Outcome::Rejected(Rejection::UnrecordedUnlock {
+                client: 1,
+                tx_id: 0
+            }),
+            p.process_one(Transaction::Unlock(
+                Metadata::new(1, 0),
+                "card was stolen".into()
+            ))
+            .expect("can process")
+        );
+        let acc = p.get_account_store_ref().get(&1).expect("account exists");
+        assert!(acc.locked, "the reason would be lost");
+        assert!(p.check_invariants().expect("can check").is_ok());
+    }
 }
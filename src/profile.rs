@@ -0,0 +1,406 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader},
+    path::Path,
+    str::FromStr,
+};
+
+use rust_decimal::Decimal;
+
+use crate::{
+    date::Date,
+    error::Error,
+    objects::statements::{StatementAccounts, StatementLine},
+};
+
+/// Where the amount of a booking is found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum AmountColumns {
+    /// One signed amount column, negative amounts are debits.
+    Signed(String),
+    /// Separate columns for money going out and coming in, each holding unsigned amounts.
+    DebitCredit { debit: String, credit: String },
+}
+
+/// ImportProfile describes the CSV layout of a specific bank export, so statements can be imported
+/// without preprocessing them by hand.
+///
+/// Profiles are plain `key = value` files, `#` starts a comment:
+///
+/// ```text
+/// name = dkb
+/// delimiter = ;
+/// skip_lines = 4
+/// date_column = Buchungsdatum
+/// date_format = %d.%m.%y
+/// amount_column = Betrag (€)
+/// decimal_comma = true
+/// payee_column = Zahlungsempfänger*in
+/// memo_column = Verwendungszweck
+/// account = Assets:Bank:DKB
+/// ```
+///
+/// Instead of `amount_column` a profile may set `debit_column` and `credit_column`.
+/// With `client` set, rows become [`Transaction`]s of that client, otherwise journal entries
+/// against `account` and `income`/`expenses` (default `Income:Uncategorized` and
+/// `Expenses:Uncategorized`).
+///
+/// [`Transaction`]: crate::objects::transactions::Transaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ImportProfile {
+    pub(crate) name: String,
+    delimiter: u8,
+    skip_lines: usize,
+    date_column: String,
+    date_format: String,
+    amount: AmountColumns,
+    decimal_comma: bool,
+    payee_column: Option<String>,
+    memo_column: Option<String>,
+    pub(crate) accounts: StatementAccounts,
+    pub(crate) client: Option<u16>,
+}
+
+impl ImportProfile {
+    pub(crate) fn from_file(path: &Path) -> Result<Self, Error> {
+        Self::from_str(&std::fs::read_to_string(path)?)
+    }
+
+    /// Reads the statement at `path`, yielding each booking together with its line number in the
+    /// file.
+    pub(crate) fn read(&self, path: &Path) -> Result<StatementReader<'_>, Error> {
+        let f = OpenOptions::new().read(true).open(path)?;
+        let mut buf = BufReader::new(f);
+        let mut skipped = String::new();
+        for _ in 0..self.skip_lines {
+            skipped.clear();
+            buf.read_line(&mut skipped)?;
+        }
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(self.delimiter)
+            .trim(csv::Trim::All)
+            // NOTE(juf): Bank exports love trailing delimiters and summary lines
+            .flexible(true)
+            .from_reader(buf);
+        let headers = reader.headers()?.clone();
+        let column = |name: &str| {
+            headers.iter().position(|h| h == name).ok_or_else(|| {
+                Error::InvalidProfile(format!(
+                    "profile {} expects column {name:?}, found {headers:?}",
+                    self.name
+                ))
+            })
+        };
+        let amount = match &self.amount {
+            AmountColumns::Signed(name) => Columns::Signed(column(name)?),
+            AmountColumns::DebitCredit { debit, credit } => Columns::DebitCredit {
+                debit: column(debit)?,
+                credit: column(credit)?,
+            },
+        };
+        Ok(StatementReader {
+            profile: self,
+            date: column(&self.date_column)?,
+            amount,
+            payee: self.payee_column.as_deref().map(column).transpose()?,
+            memo: self.memo_column.as_deref().map(column).transpose()?,
+            line_offset: self.skip_lines as u64,
+            records: reader.into_records(),
+        })
+    }
+
+    fn parse_amount(&self, raw: &str) -> Result<Decimal, Error> {
+        parse_amount(raw, self.decimal_comma)
+    }
+}
+
+impl FromStr for ImportProfile {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut name = None;
+        let mut delimiter = b',';
+        let mut skip_lines = 0;
+        let mut date_column = None;
+        let mut date_format = None;
+        let mut amount_column = None;
+        let mut debit_column = None;
+        let mut credit_column = None;
+        let mut decimal_comma = false;
+        let mut payee_column = None;
+        let mut memo_column = None;
+        let mut accounts = StatementAccounts::default();
+        let mut client = None;
+        for (idx, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |reason: &str| {
+                Error::InvalidProfile(format!("line {}: {reason}: {line:?}", idx + 1))
+            };
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| invalid("expected key = value"))?;
+            let value = value.trim().to_string();
+            match key.trim() {
+                "name" => name = Some(value),
+                "delimiter" => {
+                    delimiter = match value.as_str() {
+                        "tab" | "\\t" => b'\t',
+                        v if v.len() == 1 => v.as_bytes()[0],
+                        _ => return Err(invalid("delimiter must be a single character")),
+                    }
+                }
+                "skip_lines" => {
+                    skip_lines = value.parse().map_err(|_| invalid("not a number"))?;
+                }
+                "date_column" => date_column = Some(value),
+                "date_format" => date_format = Some(value),
+                "amount_column" => amount_column = Some(value),
+                "debit_column" => debit_column = Some(value),
+                "credit_column" => credit_column = Some(value),
+                "decimal_comma" => {
+                    decimal_comma = value.parse().map_err(|_| invalid("not a boolean"))?;
+                }
+                "payee_column" => payee_column = Some(value),
+                "memo_column" => memo_column = Some(value),
+                "account" => accounts.account = value,
+                "income" => accounts.income = value,
+                "expenses" => accounts.expenses = value,
+                "client" => client = Some(value.parse().map_err(|_| invalid("not a client id"))?),
+                _ => return Err(invalid("unknown key")),
+            }
+        }
+        let missing = |key: &str| Error::InvalidProfile(format!("missing key {key}"));
+        let amount = match (amount_column, debit_column, credit_column) {
+            (Some(amount), None, None) => AmountColumns::Signed(amount),
+            (None, Some(debit), Some(credit)) => AmountColumns::DebitCredit { debit, credit },
+            _ => {
+                return Err(Error::InvalidProfile(
+                    "either amount_column or both debit_column and credit_column are required"
+                        .into(),
+                ));
+            }
+        };
+        Ok(Self {
+            name: name.ok_or_else(|| missing("name"))?,
+            delimiter,
+            skip_lines,
+            date_column: date_column.ok_or_else(|| missing("date_column"))?,
+            date_format: date_format.unwrap_or_else(|| "%Y-%m-%d".into()),
+            amount,
+            decimal_comma,
+            payee_column,
+            memo_column,
+            accounts,
+            client,
+        })
+    }
+}
+
+/// Parses amounts the way banks print them, e.g., `-1.234,56 €`, `1,234.56`, `12,50-`.
+pub(crate) fn parse_amount(raw: &str, decimal_comma: bool) -> Result<Decimal, Error> {
+    let invalid = || Error::InvalidRow(format!("invalid amount {raw:?}"));
+    let mut cleaned: String = raw
+        .chars()
+        .filter(|c| c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | ','))
+        .collect();
+    // Trailing sign, e.g., `12,50-`
+    if let Some(stripped) = cleaned.strip_suffix('-') {
+        cleaned = format!("-{stripped}");
+    }
+    let normalized = if decimal_comma {
+        cleaned.replace('.', "").replace(',', ".")
+    } else {
+        cleaned.replace(',', "")
+    };
+    if normalized.is_empty() {
+        return Err(invalid());
+    }
+    Decimal::from_str(&normalized).map_err(|_| invalid())
+}
+
+enum Columns {
+    Signed(usize),
+    DebitCredit { debit: usize, credit: usize },
+}
+
+pub(crate) struct StatementReader<'a> {
+    profile: &'a ImportProfile,
+    date: usize,
+    amount: Columns,
+    payee: Option<usize>,
+    memo: Option<usize>,
+    line_offset: u64,
+    records: csv::StringRecordsIntoIter<BufReader<File>>,
+}
+
+impl StatementReader<'_> {
+    fn parse(&self, record: &csv::StringRecord) -> Result<StatementLine, Error> {
+        let field = |idx: usize| record.get(idx).unwrap_or_default();
+        let optional = |idx: Option<usize>| {
+            idx.map(field)
+                .filter(|value| !value.is_empty())
+                .map(String::from)
+        };
+        let amount = match self.amount {
+            Columns::Signed(idx) => self.profile.parse_amount(field(idx))?,
+            Columns::DebitCredit { debit, credit } => {
+                let parse = |idx| match field(idx) {
+                    "" => Ok(Decimal::ZERO),
+                    value => self.profile.parse_amount(value).map(|v| v.abs()),
+                };
+                parse(credit)? - parse(debit)?
+            }
+        };
+        Ok(StatementLine {
+            date: Date::parse(field(self.date), &self.profile.date_format)?,
+            amount,
+            payee: optional(self.payee),
+            memo: optional(self.memo),
+            external_id: None,
+            value_date: None,
+            counterparty_account: None,
+            reference: None,
+            cleared: None,
+            splits: Vec::new(),
+        })
+    }
+}
+
+impl Iterator for StatementReader<'_> {
+    /// Line number in the file and the booking found there.
+    type Item = Result<(u64, StatementLine), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let record = match self.records.next()? {
+            Ok(record) => record,
+            Err(err) => return Some(Err(err.into())),
+        };
+        let line = self.line_offset + record.position().map_or(0, |pos| pos.line());
+        Some(
+            self.parse(&record)
+                .map(|statement| (line, statement))
+                .map_err(|err| Error::InvalidRow(format!("line {line}: {err}"))),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use rust_decimal::dec;
+
+    use crate::{
+        date::Date,
+        ledger::Journal,
+        objects::statements::StatementLine,
+        processor::{Outcome, Processor},
+        profile::{ImportProfile, parse_amount},
+    };
+
+    #[test]
+    fn parse_bank_amounts() {
+        assert_eq!(
+            dec!(-1234.56),
+            parse_amount("-1.234,56 €", true).expect("valid")
+        );
+        assert_eq!(dec!(-12.50), parse_amount("12,50-", true).expect("valid"));
+        assert_eq!(
+            dec!(1234.56),
+            parse_amount("$1,234.56", false).expect("valid")
+        );
+        assert_eq!(dec!(0.5), parse_amount("+0.50", false).expect("valid"));
+        assert!(parse_amount("", false).is_err());
+        assert!(parse_amount("n/a", false).is_err());
+    }
+
+    #[test]
+    fn profile_requires_amount_columns() {
+        assert!(
+            "name = x\ndate_column = Date"
+                .parse::<ImportProfile>()
+                .is_err()
+        );
+        assert!(
+            "name = x\ndate_column = Date\ndebit_column = Out"
+                .parse::<ImportProfile>()
+                .is_err()
+        );
+        assert!(
+            "name = x\ndate_column = Date\nnope = 1\namount_column = Amount"
+                .parse::<ImportProfile>()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn import_german_statement() {
+        let profile = ImportProfile::from_file(Path::new("tests/profile-de.profile"))
+            .expect("profile should be valid");
+        let lines: Vec<_> = profile
+            .read(Path::new("tests/statement-de.csv"))
+            .expect("can open statement")
+            .collect::<Result<_, _>>()
+            .expect("statement should be valid");
+        assert_eq!(3, lines.len());
+        assert_eq!(
+            (
+                6,
+                StatementLine {
+                    date: Date::new(2025, 1, 2).expect("valid date"),
+                    amount: dec!(-1234.56),
+                    payee: Some("Vermieter GmbH".into()),
+                    memo: Some("Miete Januar".into()),
+                    external_id: None,
+                    value_date: None,
+                    counterparty_account: None,
+                    reference: None,
+                    cleared: None,
+                    splits: Vec::new(),
+                }
+            ),
+            lines[0]
+        );
+        assert_eq!(dec!(2500.00), lines[1].1.amount);
+        assert_eq!(None, lines[2].1.memo);
+        let mut journal = Journal::new();
+        for (line, statement) in lines {
+            let entry = statement
+                .into_entry(line as u32, &profile.accounts)
+                .expect("single contra account is balanced");
+            journal
+                .post(entry)
+                .expect("no chart, every account is accepted");
+        }
+        assert_eq!(dec!(1257.40), journal.balance("Assets:Bank:Girokonto"));
+        assert_eq!(dec!(1242.60), journal.balance("Expenses:Uncategorized"));
+        assert_eq!(dec!(-2500.00), journal.balance("Income:Uncategorized"));
+    }
+
+    #[test]
+    fn import_us_statement_with_debit_credit_columns_into_processor() {
+        let profile = ImportProfile::from_file(Path::new("tests/profile-us.profile"))
+            .expect("profile should be valid");
+        let client = profile.client.expect("profile maps onto a client");
+        let mut p = Processor::new();
+        for line in profile
+            .read(Path::new("tests/statement-us.csv"))
+            .expect("can open statement")
+        {
+            let (line, statement) = line.expect("statement should be valid");
+            assert_eq!(
+                Outcome::Applied,
+                p.process_one(statement.into_transaction(client, line as u32))
+                    .expect("can process")
+            );
+        }
+        let acc = p
+            .get_account_store_ref()
+            .get(&client)
+            .expect("account should exist");
+        assert_eq!(dec!(1962.51), acc.available);
+    }
+}
@@ -0,0 +1,545 @@
+use std::{io::Write, path::Path, str::FromStr};
+
+use rust_decimal::Decimal;
+
+use crate::{
+    amount::Precision,
+    date::Date,
+    error::Error,
+    ledger::{AccountType, Journal, Posting},
+    objects::statements::{Split, StatementLine},
+};
+
+// NOTE(juf): Quicken Interchange Format, records of one field per line, the first character names
+// the field, `^` ends the record. `!Type:` headers switch between sections. Only the banking
+// sections (Bank, Cash, CCard, Oth A, Oth L) carry transactions we can map, lists such as
+// categories, classes or memorized transactions are skipped. Investment sections are refused
+// instead of being dropped silently. All registers of a file are booked against the one bank
+// account of the import. Cleared status, split memos and classes are kept as metadata of the
+// entry and its postings and written back out, fields without a place in the journal, e.g.,
+// addresses, are counted.
+
+/// Banking transactions of a QIF file, together with the line their record starts at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Register {
+    pub(crate) lines: Vec<(u64, StatementLine)>,
+    /// Fields of the transactions that were not imported.
+    pub(crate) dropped: usize,
+}
+
+/// Reads all banking transactions of a QIF file.
+pub(crate) fn read(path: &Path) -> Result<Register, Error> {
+    // NOTE(juf): Quicken writes the system code page, decode lossy instead of refusing the file
+    let bytes = std::fs::read(path)?;
+    parse(&String::from_utf8_lossy(&bytes))
+}
+
+/// Parses the transactions, together with the line their record starts at.
+pub(crate) fn parse(input: &str) -> Result<Register, Error> {
+    let mut lines = Vec::new();
+    let mut dropped = 0;
+    let mut banking = false;
+    let mut record: Option<Record> = None;
+    for (text, line) in input.lines().zip(1..) {
+        let text = text.trim_end();
+        let invalid = |reason: String| Error::InvalidRow(format!("qif line {line}: {reason}"));
+        if let Some(header) = text.strip_prefix('!') {
+            if record.is_some() {
+                return Err(invalid(format!("!{header} inside a record, missing ^")));
+            }
+            banking = match header.trim() {
+                "Type:Bank" | "Type:Cash" | "Type:CCard" | "Type:Oth A" | "Type:Oth L" => true,
+                "Type:Invst" => {
+                    return Err(invalid("investment accounts are not supported".into()));
+                }
+                _ => false,
+            };
+            continue;
+        }
+        if !banking || text.is_empty() {
+            continue;
+        }
+        if text.starts_with('^') {
+            if let Some(record) = record.take() {
+                lines.push((record.line, record.into_statement_line().map_err(invalid)?));
+            }
+            continue;
+        }
+        let record = record.get_or_insert_with(|| Record::new(line));
+        let mut chars = text.chars();
+        let field = chars.next();
+        let value = chars.as_str().trim();
+        match field {
+            Some('D') => record.date = Some(parse_date(value).map_err(invalid)?),
+            Some('T' | 'U') => record.amount = Some(parse_amount(value).map_err(invalid)?),
+            Some('P') => record.payee = Some(value.to_string()),
+            Some('M') => record.memo = Some(value.to_string()),
+            Some('N') => record.number = Some(value.to_string()),
+            Some('C') => record.cleared = Some(value.to_string()),
+            Some('L') => record.category = Some(value.to_string()),
+            Some('S') => record.splits.push(RecordSplit {
+                category: Some(value.to_string()),
+                ..RecordSplit::default()
+            }),
+            Some('E') => match record.splits.last_mut() {
+                Some(split) => split.memo = Some(value.to_string()),
+                None => record.splits.push(RecordSplit {
+                    memo: Some(value.to_string()),
+                    ..RecordSplit::default()
+                }),
+            },
+            Some('$') => {
+                let amount = parse_amount(value).map_err(invalid)?;
+                match record.splits.last_mut() {
+                    Some(split) if split.amount.is_none() => split.amount = Some(amount),
+                    // Amount without category, the uncategorized account takes it
+                    _ => record.splits.push(RecordSplit {
+                        amount: Some(amount),
+                        ..RecordSplit::default()
+                    }),
+                }
+            }
+            // Address, percentages and the like
+            _ => dropped += 1,
+        }
+    }
+    if let Some(record) = record {
+        return Err(Error::InvalidRow(format!(
+            "qif line {}: record is never closed with ^",
+            record.line
+        )));
+    }
+    Ok(Register { lines, dropped })
+}
+
+/// Fields of the record being read.
+struct Record {
+    line: u64,
+    date: Option<Date>,
+    amount: Option<Decimal>,
+    payee: Option<String>,
+    memo: Option<String>,
+    number: Option<String>,
+    cleared: Option<String>,
+    category: Option<String>,
+    splits: Vec<RecordSplit>,
+}
+
+/// Fields of a split of the record being read.
+#[derive(Default)]
+struct RecordSplit {
+    category: Option<String>,
+    amount: Option<Decimal>,
+    memo: Option<String>,
+}
+
+impl Record {
+    fn new(line: u64) -> Self {
+        Self {
+            line,
+            date: None,
+            amount: None,
+            payee: None,
+            memo: None,
+            number: None,
+            cleared: None,
+            category: None,
+            splits: Vec::new(),
+        }
+    }
+
+    fn into_statement_line(self) -> Result<StatementLine, String> {
+        let date = self.date.ok_or("missing date (D)")?;
+        let amount = self.amount.ok_or("missing amount (T)")?;
+        let mut splits = Vec::with_capacity(self.splits.len());
+        for split in self.splits {
+            let split_amount = split.amount.ok_or("split without amount ($)")?;
+            let (category, class) = split_class(split.category.as_deref());
+            splits.push(Split {
+                account: category_account(category, split_amount),
+                amount: split_amount,
+                memo: split.memo.filter(|memo| !memo.is_empty()),
+                class,
+            });
+        }
+        let split_sum: Decimal = splits.iter().map(|split| split.amount).sum();
+        if !splits.is_empty() && split_sum != amount {
+            return Err(format!("splits sum up to {split_sum}, not {amount}"));
+        }
+        // Without splits, the category is a single split covering the whole amount
+        let (category, class) = split_class(self.category.as_deref());
+        let account = category_account(category, amount);
+        if splits.is_empty() && (account.is_some() || class.is_some()) {
+            splits.push(Split {
+                account,
+                amount,
+                memo: None,
+                class,
+            });
+        }
+        Ok(StatementLine {
+            date,
+            amount,
+            payee: self.payee.filter(|payee| !payee.is_empty()),
+            memo: self.memo.filter(|memo| !memo.is_empty()),
+            external_id: None,
+            value_date: None,
+            counterparty_account: None,
+            reference: self.number.filter(|number| !number.is_empty()),
+            cleared: self.cleared,
+            splits,
+        })
+    }
+}
+
+/// Splits the class suffix off a QIF category, `Food/Vacation` is category `Food` of class
+/// `Vacation`.
+fn split_class(category: Option<&str>) -> (&str, Option<String>) {
+    let (category, class) = match category.unwrap_or_default().split_once('/') {
+        Some((category, class)) => (category, Some(class.trim().to_string())),
+        None => (category.unwrap_or_default(), None),
+    };
+    (category.trim(), class.filter(|class| !class.is_empty()))
+}
+
+/// Maps a QIF category, without its class, onto a ledger account. `[Savings]` is a transfer to
+/// another account, `Food:Groceries` an expense or income category, depending on the sign of the
+/// amount.
+fn category_account(category: &str, amount: Decimal) -> Option<String> {
+    if category.is_empty() {
+        return None;
+    }
+    if let Some(transfer) = category
+        .strip_prefix('[')
+        .and_then(|rest| rest.strip_suffix(']'))
+    {
+        return Some(
+            if transfer.contains(':') && AccountType::of(transfer).is_ok() {
+                transfer.to_string()
+            } else {
+                format!("Assets:{transfer}")
+            },
+        );
+    }
+    if category.contains(':') && AccountType::of(category).is_ok() {
+        return Some(category.to_string());
+    }
+    let root = if amount.is_sign_negative() {
+        "Expenses"
+    } else {
+        "Income"
+    };
+    Some(format!("{root}:{category}"))
+}
+
+/// Inverse of [`category_account`] and [`split_class`], the category of the contra posting.
+fn category_of(posting: &Posting) -> String {
+    let category = match posting.account.split_once(':') {
+        Some(("Income" | "Expenses", category)) => category.to_string(),
+        Some(("Assets", transfer)) => format!("[{transfer}]"),
+        _ => format!("[{}]", posting.account),
+    };
+    match posting.meta("class") {
+        Some(class) => format!("{category}/{class}"),
+        None => category,
+    }
+}
+
+/// Dates are `MM/DD/YYYY`, `MM/DD/YY` or Quicken's `M/D'YY`, all possibly padded with spaces.
+fn parse_date(raw: &str) -> Result<Date, String> {
+    let invalid = || format!("invalid date {raw:?}");
+    let normalized: String = raw.chars().filter(|c| !c.is_whitespace()).collect();
+    let mut parts = normalized.split(['/', '\'', '-']);
+    let (Some(month), Some(day), Some(year), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(invalid());
+    };
+    let number = |part: &str| part.parse::<u16>().map_err(|_| invalid());
+    let year = match (year.len(), number(year)?) {
+        // Two digit years pivot at 70, as Quicken does
+        (2, year) if year < 70 => 2000 + year,
+        (2, year) => 1900 + year,
+        (4, year) => year,
+        _ => return Err(invalid()),
+    };
+    let (Ok(month), Ok(day)) = (u8::try_from(number(month)?), u8::try_from(number(day)?)) else {
+        return Err(invalid());
+    };
+    Date::new(year, month, day).map_err(|err| err.to_string())
+}
+
+/// Amounts use a decimal point and may carry `,` thousands separators.
+fn parse_amount(raw: &str) -> Result<Decimal, String> {
+    Decimal::from_str(&raw.replace(',', "")).map_err(|_| format!("invalid amount {raw:?}"))
+}
+
+/// Writes the register of `account`, i.e., every journal entry posting to it, as QIF. Entries
+/// without a date, which is every client transaction, can not be expressed in QIF and are
+/// skipped. Amounts are rounded to `precision`. Returns the number of skipped entries.
+pub(crate) fn write(
+    journal: &Journal,
+    account: &str,
+    precision: Precision,
+    out: &mut impl Write,
+) -> Result<usize, Error> {
+    let section = match AccountType::of(account)? {
+        AccountType::Liabilities => "CCard",
+        _ => "Bank",
+    };
+    writeln!(out, "!Type:{section}")?;
+    let mut skipped = 0;
+    for entry in journal.entries() {
+        let (own, contra): (Vec<_>, Vec<_>) = entry
+            .postings()
+            .iter()
+            .partition(|posting| posting.account == account);
+        if own.is_empty() {
+            continue;
+        }
+        let Some(date) = entry.date() else {
+            skipped += 1;
+            continue;
+        };
+        let amount: Decimal = own.iter().map(|posting| posting.amount).sum();
+        writeln!(
+            out,
+            "D{:02}/{:02}/{:04}",
+            date.month(),
+            date.day(),
+            date.year()
+        )?;
+        writeln!(out, "T{}", precision.round(amount))?;
+        if let Some(cleared) = entry.meta("cleared") {
+            writeln!(out, "C{cleared}")?;
+        }
+        if let Some(number) = entry.meta("reference") {
+            writeln!(out, "N{number}")?;
+        }
+        if let Some(payee) = entry.payee() {
+            writeln!(out, "P{payee}")?;
+        }
+        if let Some(narration) = entry.narration() {
+            writeln!(out, "M{narration}")?;
+        }
+        match contra.as_slice() {
+            [single] if single.meta("memo").is_none() => writeln!(out, "L{}", category_of(single))?,
+            splits => {
+                for split in splits {
+                    writeln!(out, "S{}", category_of(split))?;
+                    if let Some(memo) = split.meta("memo") {
+                        writeln!(out, "E{memo}")?;
+                    }
+                    writeln!(out, "${}", precision.round(-split.amount))?;
+                }
+            }
+        }
+        writeln!(out, "^")?;
+    }
+    Ok(skipped)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use rust_decimal::dec;
+
+    use crate::{
+        amount::Precision,
+        date::Date,
+        ingest::post_statement,
+        ledger::Journal,
+        objects::statements::{Split, StatementAccounts, StatementLine},
+        processor::Processor,
+        qif::{parse, read, write},
+    };
+
+    #[test]
+    fn read_bank_register() {
+        let register = read(Path::new("tests/household.qif")).expect("valid qif");
+        assert_eq!(0, register.dropped);
+        let lines = register.lines;
+        assert_eq!(4, lines.len(), "category list is skipped");
+        assert_eq!(
+            (
+                12,
+                StatementLine {
+                    date: Date::new(2025, 3, 3).expect("valid date"),
+                    amount: dec!(1500.00),
+                    payee: Some("ACME GmbH".into()),
+                    memo: Some("Salary March".into()),
+                    external_id: None,
+                    value_date: None,
+                    counterparty_account: None,
+                    reference: None,
+                    cleared: Some("X".into()),
+                    splits: vec![Split {
+                        account: Some("Income:Salary".into()),
+                        amount: dec!(1500.00),
+                        memo: None,
+                        class: None,
+                    }],
+                }
+            ),
+            lines[0]
+        );
+        let groceries = &lines[1].1;
+        assert_eq!(dec!(-1234.56), groceries.amount);
+        assert_eq!(Date::new(2025, 3, 5).expect("valid date"), groceries.date);
+        assert_eq!(Some("1042".into()), groceries.reference);
+        assert_eq!(
+            vec![
+                Split {
+                    account: Some("Expenses:Food:Groceries".into()),
+                    amount: dec!(-1000.00),
+                    memo: Some("Weekly shop".into()),
+                    class: None,
+                },
+                Split {
+                    account: Some("Expenses:Household".into()),
+                    amount: dec!(-200.00),
+                    memo: None,
+                    class: Some("Renovation".into()),
+                },
+                Split {
+                    account: None,
+                    amount: dec!(-34.56),
+                    memo: None,
+                    class: None,
+                },
+            ],
+            groceries.splits
+        );
+        assert_eq!(
+            Some("Assets:Savings".into()),
+            lines[2].1.splits[0].account,
+            "transfer"
+        );
+        assert_eq!(
+            Date::new(1999, 12, 31).expect("valid date"),
+            lines[3].1.date
+        );
+        assert!(lines[3].1.splits.is_empty());
+    }
+
+    #[test]
+    fn invalid_records_are_errors() {
+        assert!(parse("!Type:Invst\nD1/1/25\nT1\n^\n").is_err());
+        assert!(parse("!Type:Bank\nT1\n^\n").is_err(), "missing date");
+        assert!(parse("!Type:Bank\nD1/1/25\n^\n").is_err(), "missing amount");
+        assert!(parse("!Type:Bank\nD13/1/25\nT1\n^\n").is_err());
+        assert!(parse("!Type:Bank\nD1/1/25\nT1\n").is_err(), "missing ^");
+        assert!(
+            parse("!Type:Bank\nD1/1/25\nT-10\nSFood\n$-4\nSRent\n$-5\n^\n").is_err(),
+            "splits do not add up"
+        );
+        assert!(
+            parse("!Type:Cat\nNFood\nE\n^\n")
+                .expect("list")
+                .lines
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn unmapped_fields_are_counted() {
+        let register = parse("!Type:Bank\nD1/1/25\nT-5\nA1 Main St\nASpringfield\nL/Trip\n^\n")
+            .expect("valid qif");
+        assert_eq!(2, register.dropped, "address lines");
+        assert_eq!(
+            vec![Split {
+                account: None,
+                amount: dec!(-5),
+                memo: None,
+                class: Some("Trip".into()),
+            }],
+            register.lines[0].1.splits,
+            "a class without category is kept"
+        );
+    }
+
+    #[test]
+    fn ledger_round_trips_through_qif() {
+        let accounts = StatementAccounts::default();
+        let mut journal = Journal::new();
+        post_statement(
+            read(Path::new("tests/household.qif"))
+                .expect("valid qif")
+                .lines
+                .into_iter()
+                .map(Ok),
+            None,
+            &accounts,
+            &mut journal,
+            |line, err| panic!("line {line} refused: {err}"),
+        )
+        .expect("valid statement");
+        assert_eq!(dec!(1000.00), journal.balance("Expenses:Food:Groceries"));
+        assert_eq!(dec!(34.56), journal.balance("Expenses:Uncategorized"));
+        assert_eq!(dec!(250.00), journal.balance("Assets:Savings"));
+
+        let mut out = Vec::new();
+        assert_eq!(
+            0,
+            write(
+                &journal,
+                &accounts.account,
+                Precision {
+                    scale: 2,
+                    ..Precision::default()
+                },
+                &mut out
+            )
+            .expect("can write")
+        );
+        let exported = String::from_utf8(out).expect("utf-8");
+        assert!(exported.starts_with("!Type:Bank\nD03/03/2025\nT1500.00\nCX\nPACME GmbH\n"));
+        let reimported = parse(&exported).expect("valid qif").lines;
+        let original = read(Path::new("tests/household.qif"))
+            .expect("valid qif")
+            .lines;
+        assert_eq!(original.len(), reimported.len());
+        for ((_, original), (_, reimported)) in original.iter().zip(reimported.iter()) {
+            assert_eq!(original.date, reimported.date);
+            assert_eq!(original.amount, reimported.amount);
+            assert_eq!(original.payee, reimported.payee);
+            assert_eq!(original.memo, reimported.memo);
+            assert_eq!(original.reference, reimported.reference);
+            assert_eq!(original.cleared, reimported.cleared);
+            for (original, reimported) in original.splits.iter().zip(reimported.splits.iter()) {
+                assert_eq!(original.memo, reimported.memo);
+                assert_eq!(original.class, reimported.class);
+            }
+        }
+        assert!(exported.contains("SHousehold/Renovation\n$-200.00\n"));
+        assert!(exported.contains("SFood:Groceries\nEWeekly shop\n$-1000.00\n"));
+        // Uncategorized parts come back under their ledger account
+        assert_eq!(
+            Some("Expenses:Uncategorized".into()),
+            reimported[1].1.splits[2].account
+        );
+        assert_eq!(
+            Some("Income:Uncategorized".into()),
+            reimported[3].1.splits[0].account
+        );
+    }
+
+    #[test]
+    fn undated_entries_are_skipped_on_export() {
+        let mut p = Processor::new().with_journal(Journal::new());
+        let _ = p
+            .process_one(crate::objects::transactions::Transaction::Deposit(
+                crate::objects::transactions::Metadata::new(1, 1),
+                dec!(10),
+            ))
+            .expect("can process");
+        let mut out = Vec::new();
+        let journal = p.journal().expect("has journal");
+        assert_eq!(
+            1,
+            write(journal, "Assets:Bank", Precision::default(), &mut out).expect("can write")
+        );
+        assert_eq!("!Type:Bank\n", String::from_utf8(out).expect("utf-8"));
+    }
+}
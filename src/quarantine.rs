@@ -0,0 +1,200 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufWriter, Read, Seek, SeekFrom},
+    ops::Range,
+    path::Path,
+};
+
+use crate::error::Error;
+
+/// A single input row that could not be turned into a transaction.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct QuarantinedRow {
+    pub(crate) line: u64,
+    pub(crate) code: &'static str,
+    pub(crate) reason: String,
+    /// The row as it was read, without the line terminator. Not necessarily UTF-8.
+    pub(crate) raw: Vec<u8>,
+}
+
+/// Quarantine collects rows which failed to deserialize or convert into a [`Transaction`]
+/// into a separate CSV, so a single bad line does not abort the whole run.
+///
+/// [`Transaction`]: crate::objects::transactions::Transaction
+pub(crate) struct Quarantine {
+    // NOTE(juf): csv does not hand out the raw bytes of a record, so we keep a second handle on
+    // the input and read the byte range of the offending record back. Bad rows should be rare
+    // so the additional seek+read is fine. The bytes are written out as they are, serde would
+    // insist on UTF-8, so rows are written as byte records.
+    source: File,
+    writer: csv::Writer<BufWriter<File>>,
+    count: usize,
+}
+
+impl Quarantine {
+    pub(crate) fn create(input: &Path, path: &Path) -> Result<Self, Error> {
+        let source = OpenOptions::new().read(true).open(input)?;
+        let f = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        let writer = csv::WriterBuilder::new().from_writer(BufWriter::new(f));
+        Ok(Self {
+            source,
+            writer,
+            count: 0,
+        })
+    }
+
+    /// Records the row at `line`, spanning `bytes` in the input, as rejected because of `err`.
+    pub(crate) fn quarantine(
+        &mut self,
+        line: u64,
+        bytes: Range<u64>,
+        err: &Error,
+    ) -> Result<(), Error> {
+        let row = QuarantinedRow {
+            line,
+            code: err.code(),
+            reason: err.to_string(),
+            raw: self.raw(bytes)?,
+        };
+        if self.count == 0 {
+            self.writer
+                .write_record(["line", "code", "reason", "raw"])?;
+        }
+        self.writer.write_record([
+            row.line.to_string().as_bytes(),
+            row.code.as_bytes(),
+            row.reason.as_bytes(),
+            &row.raw,
+        ])?;
+        self.count += 1;
+        Ok(())
+    }
+
+    pub(crate) fn count(&self) -> usize {
+        self.count
+    }
+
+    pub(crate) fn flush(&mut self) -> Result<(), Error> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// The bytes of the record, without the line terminator and blank lines before it.
+    fn raw(&mut self, bytes: Range<u64>) -> Result<Vec<u8>, Error> {
+        let mut buf = Vec::with_capacity(bytes.end.saturating_sub(bytes.start) as usize);
+        self.source.seek(SeekFrom::Start(bytes.start))?;
+        (&mut self.source)
+            .take(bytes.end.saturating_sub(bytes.start))
+            .read_to_end(&mut buf)?;
+        let terminator = |b: &u8| matches!(b, b'\r' | b'\n');
+        let start = buf.iter().position(|b| !terminator(b)).unwrap_or(buf.len());
+        let end = buf
+            .iter()
+            .rposition(|b| !terminator(b))
+            .map_or(start, |i| i + 1);
+        buf.truncate(end);
+        buf.drain(..start);
+        Ok(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use rust_decimal::dec;
+
+    use crate::{
+        ingest::{default_csv_ingest, feed_processor},
+        objects::accounts::Account,
+        processor::Processor,
+        quarantine::Quarantine,
+    };
+
+    #[test]
+    fn bad_rows_are_quarantined_and_processing_continues() {
+        let input = Path::new("tests/sample-bad-rows.csv");
+        let mut ingest = default_csv_ingest(input).expect("Can open file and create ingest");
+        let out_dir = tempfile::tempdir().expect("Could not create tempdir");
+        let out_path = out_dir.path().join("quarantine.csv");
+        let mut quarantine =
+            Quarantine::create(input, &out_path).expect("Can create quarantine file");
+        let mut p = Processor::new();
+        feed_processor(&mut ingest, &mut p, Some(&mut quarantine), |_| {})
+            .expect("bad rows should not abort processing");
+        assert_eq!(3, quarantine.count(), "Unexpected amount of bad rows");
+        quarantine.flush().expect("can flush quarantine file");
+        let written = std::fs::read_to_string(&out_path).expect("can read quarantine file");
+        let mut lines = written.lines();
+        assert_eq!(Some("line,code,reason,raw"), lines.next());
+        let rows: Vec<_> = lines.collect();
+        assert_eq!(3, rows.len());
+        assert!(rows[0].starts_with("3,invalid_row,"), "got {}", rows[0]);
+        assert!(
+            rows[0].ends_with(",\"deposit, 1, 2,\""),
+            "should keep original text, got {}",
+            rows[0]
+        );
+        assert!(rows[1].starts_with("5,malformed_field,"), "got {}", rows[1]);
+        assert!(
+            rows[1].ends_with(",\"transfer, 1, 4, 1.0\""),
+            "got {}",
+            rows[1]
+        );
+        assert!(rows[2].starts_with("6,unequal_lengths,"), "got {}", rows[2]);
+        assert_eq!(
+            Some(&Account {
+                id: 1,
+                locked: false,
+                available: dec!(4.0),
+                held: dec!(0)
+            }),
+            p.get_account_store_ref().get(&1)
+        );
+    }
+
+    #[test]
+    fn raw_rows_are_kept_byte_for_byte() {
+        let dir = tempfile::tempdir().expect("Could not create tempdir");
+        let input = dir.path().join("in.csv");
+        std::fs::write(
+            &input,
+            b"type,client,tx,amount\n  deposit, 1, 2,\t \r\ndeposit, 1, 3, 1.0\ndeposit, 1, \xff, 1.0\n",
+        )
+        .expect("can write input");
+        let out_path = dir.path().join("quarantine.csv");
+        let mut ingest = default_csv_ingest(&input).expect("Can open file and create ingest");
+        let mut quarantine =
+            Quarantine::create(&input, &out_path).expect("Can create quarantine file");
+        let mut p = Processor::new();
+        feed_processor(&mut ingest, &mut p, Some(&mut quarantine), |_| {})
+            .expect("bad rows should not abort processing");
+        quarantine.flush().expect("can flush quarantine file");
+        let mut written = csv::Reader::from_path(&out_path).expect("can read quarantine file");
+        let raw: Vec<_> = written
+            .byte_records()
+            .map(|row| row.expect("valid row")[3].to_vec())
+            .collect();
+        assert_eq!(
+            vec![
+                b"  deposit, 1, 2,\t ".to_vec(),
+                b"deposit, 1, \xff, 1.0".to_vec()
+            ],
+            raw
+        );
+    }
+
+    #[test]
+    fn bad_rows_abort_without_quarantine() {
+        let mut ingest = default_csv_ingest(Path::new("tests/sample-bad-rows.csv"))
+            .expect("Can open file and create ingest");
+        let mut p = Processor::new();
+        let err = feed_processor(&mut ingest, &mut p, None, |_| {})
+            .expect_err("bad rows should abort processing");
+        assert_eq!("invalid_row", err.code());
+    }
+}
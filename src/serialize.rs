@@ -1,6 +1,6 @@
 use serde::{Serialize, ser::SerializeStruct};
 
-use crate::objects::accounts::Account;
+use crate::{amount::Precision, objects::accounts::Account};
 
 impl Serialize for Account {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
@@ -16,3 +16,14 @@ impl Serialize for Account {
         obj.end()
     }
 }
+
+impl Account {
+    /// The account as written out, balances rounded to `precision`.
+    pub(crate) fn rounded(&self, precision: &Precision) -> Account {
+        Account {
+            available: precision.round(self.available),
+            held: precision.round(self.held),
+            ..self.clone()
+        }
+    }
+}
@@ -0,0 +1,488 @@
+use std::{
+    collections::HashMap,
+    io::Read,
+    sync::mpsc::{self, Receiver, Sender, SyncSender},
+    thread,
+};
+
+use crate::{
+    error::{Error, Rejection},
+    ingest::for_each_transaction,
+    objects::transactions::{Metadata, Transaction},
+    processor::{Outcome, Processor},
+    quarantine::Quarantine,
+    store::compact::FinalizedSet,
+};
+
+// NOTE(juf): The fan-out from the README. The reader thread parses the input and hands every row
+// to the worker of shard `client % N`, each worker owns a processor with the accounts of its
+// clients and their transactions, no locks involved, and the processors are merged once the
+// input is through.
+// Tx ids are global though, a client may reference the tx id of another one. The reader
+// remembers which shards saw a deposit or withdrawal of every tx id. As long as that is only the
+// shard of the client at hand, which is what any sane input looks like, rows go straight through.
+// Otherwise the reader waits for the shards in question to catch up and asks them who holds the
+// transaction: disputes go to that shard, deposits and withdrawals whose tx id is taken are
+// rejected as duplicates by the shard of their client. Accounts and transactions end up the same
+// as when processing the rows one by one. Only the reason differs for a dispute of a locked
+// client on the transaction of another one, the shard holding the transaction does not know
+// about the lock and reports the client mismatch instead.
+// Only transactions that can still be disputed need their shards remembered. Workers report the
+// tx ids they resolve or charge back and the reader moves them into a `FinalizedSet`, a bitmap
+// for dense ranges, all it keeps of them is that they are taken. Deposits and withdrawals of such
+// a tx id are duplicates, disputes of one ask every shard who holds it, both rare in sane input.
+
+/// Most shards, the shards a tx id was seen in are kept as bit mask.
+pub(crate) const MAX_SHARDS: usize = 64;
+/// Rows handed to a worker at once.
+const BATCH: usize = 1024;
+/// Batches waiting for a worker before the reader blocks.
+const QUEUED_BATCHES: usize = 16;
+
+/// Row number, transaction and whether its tx id is taken in another shard.
+type Routed = (u64, Transaction, bool);
+
+enum Message {
+    Rows(Vec<Routed>),
+    /// Whether the shard holds the tx id, answered once every row sent before is processed.
+    Holds(u32, mpsc::Sender<Result<bool, Error>>),
+}
+
+struct Shard {
+    sender: SyncSender<Message>,
+    batch: Vec<Routed>,
+}
+
+impl Shard {
+    fn flush(&mut self) {
+        if !self.batch.is_empty() {
+            let batch = std::mem::replace(&mut self.batch, Vec::with_capacity(BATCH));
+            self.sender
+                .send(Message::Rows(batch))
+                .expect("workers run until the router hangs up");
+        }
+    }
+}
+
+/// Reader side, hands every transaction to the shard it belongs to.
+struct Router {
+    shards: Vec<Shard>,
+    /// Bit mask of the shards that saw a deposit or withdrawal with the tx id, as long as it is
+    /// not known to be finalized.
+    claims: HashMap<u32, u64>,
+    /// Tx ids a shard finalized, which shard is not kept.
+    finalized: FinalizedSet,
+    /// Tx ids the workers finalized since the last row.
+    reports: Receiver<u32>,
+}
+
+impl Router {
+    fn route(&mut self, row: u64, txn: Transaction) -> Result<(), Error> {
+        self.take_reports();
+        let Metadata { client, tx_id } = txn.get_metadata().clone();
+        let home = usize::from(client) % self.shards.len();
+        let claims = self.claims.get(&tx_id).copied().unwrap_or(0);
+        let elsewhere = claims & !(1 << home);
+        let finalized = self.finalized.contains(tx_id);
+        let (shard, claimed) = match txn {
+            Transaction::Deposit(..) | Transaction::Withdrawal(..) if finalized => (home, true),
+            // About the account, not a transaction
+            Transaction::Unlock(..) => (home, false),
+            _ if finalized => {
+                let every = u64::MAX >> (MAX_SHARDS - self.shards.len());
+                (self.holder(tx_id, every)?.unwrap_or(home), false)
+            }
+            Transaction::Deposit(..) | Transaction::Withdrawal(..) => {
+                self.claims.insert(tx_id, claims | 1 << home);
+                let claimed = elsewhere != 0 && self.holder(tx_id, elsewhere)?.is_some();
+                (home, claimed)
+            }
+            _ if elsewhere == 0 => (home, false),
+            _ => (self.holder(tx_id, claims)?.unwrap_or(home), false),
+        };
+        let shard = &mut self.shards[shard];
+        shard.batch.push((row, txn, claimed));
+        if shard.batch.len() >= BATCH {
+            shard.flush();
+        }
+        Ok(())
+    }
+
+    /// Forgets the shards of the tx ids the workers reported as finalized.
+    fn take_reports(&mut self) {
+        for tx_id in self.reports.try_iter() {
+            self.claims.remove(&tx_id);
+            self.finalized.insert(tx_id);
+        }
+    }
+
+    /// Shard out of the `candidates` mask holding the transaction, once they processed every row
+    /// routed so far.
+    fn holder(&mut self, tx_id: u32, candidates: u64) -> Result<Option<usize>, Error> {
+        let mut answers = Vec::new();
+        for (idx, shard) in self.shards.iter_mut().enumerate() {
+            if candidates & 1 << idx != 0 {
+                shard.flush();
+                let (reply, answer) = mpsc::channel();
+                shard
+                    .sender
+                    .send(Message::Holds(tx_id, reply))
+                    .expect("workers run until the router hangs up");
+                answers.push((idx, answer));
+            }
+        }
+        for (idx, answer) in answers {
+            if answer.recv().expect("workers answer every question")? {
+                return Ok(Some(idx));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Hands the rows still batched to the workers and hangs up, which lets them finish.
+    fn finish(mut self) {
+        for shard in self.shards.iter_mut() {
+            shard.flush();
+        }
+    }
+}
+
+/// Processes what the router sends until it hangs up and reports the tx ids it finalized back.
+/// Returns the processor and the rejections along with their row number. Once processing fails,
+/// the rows that follow are dropped, questions are still answered.
+fn work(
+    mut p: Processor,
+    messages: Receiver<Message>,
+    reports: Sender<u32>,
+) -> Result<(Processor, Vec<(u64, Rejection)>), Error> {
+    let mut rejections = Vec::new();
+    let mut failed = None;
+    for message in messages {
+        match message {
+            Message::Rows(_) if failed.is_some() => {}
+            Message::Rows(rows) => {
+                for (row, txn, claimed) in rows {
+                    let finalizes =
+                        matches!(txn, Transaction::Resolve(_) | Transaction::Chargeback(_))
+                            .then(|| txn.get_metadata().tx_id);
+                    let outcome = if claimed {
+                        p.process_claimed(txn)
+                    } else {
+                        p.process_one(txn)
+                    };
+                    let outcome = match outcome {
+                        Ok(outcome) => outcome,
+                        Err(err) => {
+                            failed = Some(err);
+                            break;
+                        }
+                    };
+                    match (outcome, finalizes) {
+                        (Outcome::Rejected(rejection), _) => rejections.push((row, rejection)),
+                        // The router hangs up once the input is through
+                        (Outcome::Applied, Some(tx_id)) => {
+                            let _ = reports.send(tx_id);
+                        }
+                        (Outcome::Applied, None) => {}
+                    }
+                }
+            }
+            // The router may have its answer from another shard already and hung up
+            Message::Holds(tx_id, reply) => {
+                let _ = reply.send(p.holds(tx_id));
+            }
+        }
+    }
+    match failed {
+        Some(err) => Err(err),
+        None => Ok((p, rejections)),
+    }
+}
+
+/// Like [`feed_processor`](crate::ingest::feed_processor), but processes the rows on `shards`
+/// worker threads, see the note on top. Rejections are reported in input order once every row is
+/// through. The processor of every shard is set up by `new_processor`. Returns the merged
+/// processor.
+pub(crate) fn feed_sharded<R: Read>(
+    reader: &mut csv::Reader<R>,
+    shards: usize,
+    new_processor: impl Fn() -> Processor,
+    quarantine: Option<&mut Quarantine>,
+    mut on_rejection: impl FnMut(Rejection),
+) -> Result<Processor, Error> {
+    assert!(
+        (1..=MAX_SHARDS).contains(&shards),
+        "between 1 and {MAX_SHARDS} shards"
+    );
+    let (fed, results) = thread::scope(|scope| {
+        // NOTE(juf): Unbounded, a worker blocked on its reports while the router blocks on the
+        // full queue of that worker would never get going again. The router takes them on every
+        // row, they do not pile up.
+        let (reporter, reports) = mpsc::channel();
+        let mut router = Router {
+            shards: Vec::with_capacity(shards),
+            claims: HashMap::new(),
+            finalized: FinalizedSet::default(),
+            reports,
+        };
+        let mut workers = Vec::with_capacity(shards);
+        for _ in 0..shards {
+            let (sender, messages) = mpsc::sync_channel(QUEUED_BATCHES);
+            let p = new_processor();
+            let reporter = reporter.clone();
+            workers.push(scope.spawn(move || work(p, messages, reporter)));
+            router.shards.push(Shard {
+                sender,
+                batch: Vec::with_capacity(BATCH),
+            });
+        }
+        let fed = for_each_transaction(reader, quarantine, |row, txn| router.route(row, txn));
+        router.finish();
+        let results: Vec<_> = workers
+            .into_iter()
+            .map(|worker| {
+                worker
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            })
+            .collect();
+        (fed, results)
+    });
+    fed?;
+    let results = results.into_iter().collect::<Result<Vec<_>, _>>()?;
+    let (processors, rejections): (Vec<_>, Vec<_>) = results.into_iter().unzip();
+    let mut rejections: Vec<_> = rejections.into_iter().flatten().collect();
+    rejections.sort_by_key(|(row, _)| *row);
+    for (_, rejection) in rejections {
+        on_rejection(rejection);
+    }
+    Ok(Processor::merge(processors))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{collections::HashMap, fmt::Write, path::Path, sync::mpsc};
+
+    use rust_decimal::dec;
+
+    use crate::{
+        error::Rejection,
+        ingest::{default_csv_ingest, feed_processor},
+        objects::accounts::Account,
+        objects::transactions::{Metadata, Transaction},
+        processor::Processor,
+        shard::{BATCH, Router, Shard, feed_sharded, work},
+        store::{AccountStore, CachedTransaction, TransactionStore, compact::FinalizedSet},
+    };
+
+    /// Transactions CSV of `rows` rows over `clients` clients, deterministic for a `seed`. Mostly
+    /// deposits and withdrawals, disputes of earlier ones that get resolved or charged back and
+    /// some negative amounts. If `across_clients`, also tx ids reused by another client and
+    /// disputes across clients. Only the first 50 clients get locked and only the others touch
+    /// tx ids of other clients, see the note on top for why.
+    pub(crate) fn mixed_input(rows: u32, clients: u16, seed: u64, across_clients: bool) -> String {
+        let mut state = seed;
+        let mut random = move |below: u32| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            ((state >> 33) % u64::from(below)) as u32
+        };
+        let mut txns: Vec<(u16, u32)> = Vec::new();
+        let mut out = String::from("type,client,tx,amount\n");
+        for row in 0..rows {
+            let mut client = random(u32::from(clients)) as u16;
+            let tx_id = row + 1;
+            let earlier = match txns.len() {
+                0 => None,
+                len => Some(txns[random(len as u32) as usize]),
+            };
+            let line = match (random(100), earlier) {
+                (kind @ 0..75, _) | (kind, None) => {
+                    let tx_id = match earlier {
+                        Some((owner, reused))
+                            if across_clients && kind % 40 == 0 && owner >= 50 =>
+                        {
+                            client = client.max(50);
+                            reused
+                        }
+                        _ => tx_id,
+                    };
+                    let mut amount = f64::from(random(100_000)) / 100.0;
+                    if kind % 37 == 0 {
+                        amount = -amount;
+                    }
+                    txns.push((client, tx_id));
+                    let action = if kind < 55 { "deposit" } else { "withdrawal" };
+                    format!("{action},{client},{tx_id},{amount}")
+                }
+                (75..88, Some((owner, tx_id))) => format!("dispute,{owner},{tx_id},"),
+                (88..96, Some((owner, tx_id))) => format!("resolve,{owner},{tx_id},"),
+                (96..98, Some((owner, tx_id))) if owner < 50 => {
+                    format!("chargeback,{owner},{tx_id},")
+                }
+                (_, Some((_, tx_id))) if across_clients => {
+                    client = client.max(50);
+                    format!("dispute,{client},{tx_id},")
+                }
+                (_, Some((owner, tx_id))) => format!("resolve,{owner},{tx_id},"),
+            };
+            writeln!(out, "{line}").expect("can write to a string");
+        }
+        out
+    }
+
+    type Outcome = (Vec<Account>, Vec<CachedTransaction>, Vec<Rejection>);
+
+    fn outcome(p: &Processor, rejections: Vec<Rejection>) -> Outcome {
+        let mut accounts: Vec<_> = p.get_account_store_ref().accounts().cloned().collect();
+        accounts.sort_by_key(|acc| acc.id);
+        let mut txns: Vec<_> = p
+            .txn_store()
+            .transactions()
+            .collect::<Result<_, _>>()
+            .expect("can iterate");
+        txns.sort_by_key(|(metadata, _, _)| metadata.tx_id);
+        (accounts, txns, rejections)
+    }
+
+    fn in_order(path: &Path) -> Outcome {
+        let mut p = Processor::new();
+        let mut rejections = Vec::new();
+        let mut ingest = default_csv_ingest(path).expect("can open input");
+        feed_processor(&mut ingest, &mut p, None, |rejection| {
+            rejections.push(rejection)
+        })
+        .expect("can process");
+        outcome(&p, rejections)
+    }
+
+    fn sharded(path: &Path, shards: usize) -> Outcome {
+        let mut rejections = Vec::new();
+        let mut ingest = default_csv_ingest(path).expect("can open input");
+        let p = feed_sharded(&mut ingest, shards, Processor::new, None, |rejection| {
+            rejections.push(rejection)
+        })
+        .expect("can process");
+        outcome(&p, rejections)
+    }
+
+    #[test]
+    fn sharded_is_the_same_as_in_order() {
+        let dir = tempfile::tempdir().expect("can create tempdir");
+        let path = dir.path().join("in.csv");
+        std::fs::write(&path, mixed_input(20_000, 200, 7, true)).expect("can write input");
+        let expected = in_order(&path);
+        assert!(expected.0.iter().any(|acc| acc.locked));
+        for across_clients in [
+            |rejection: &Rejection| matches!(rejection, Rejection::ClientMismatch { .. }),
+            |rejection: &Rejection| matches!(rejection, Rejection::DuplicateTransaction { .. }),
+        ] {
+            assert!(expected.2.iter().any(across_clients));
+        }
+        for shards in [1, 2, 3, 8, 64] {
+            // NOTE(juf): Not assert_eq!, the diff of thousands of rows helps no one
+            assert!(expected == sharded(&path, shards), "{shards} shards");
+        }
+    }
+
+    #[test]
+    fn finalized_tx_ids_are_no_longer_claimed() {
+        let rejections = std::thread::scope(|scope| {
+            let (reporter, reports) = mpsc::channel();
+            let mut router = Router {
+                shards: Vec::new(),
+                claims: HashMap::new(),
+                finalized: FinalizedSet::default(),
+                reports,
+            };
+            let mut workers = Vec::new();
+            for _ in 0..2 {
+                let (sender, messages) = mpsc::sync_channel(1);
+                let reporter = reporter.clone();
+                workers.push(scope.spawn(move || work(Processor::new(), messages, reporter)));
+                router.shards.push(Shard {
+                    sender,
+                    batch: Vec::with_capacity(BATCH),
+                });
+            }
+            for (row, txn) in [
+                Transaction::Deposit(Metadata::new(1, 1), dec!(5)),
+                Transaction::Deposit(Metadata::new(2, 2), dec!(3)),
+                Transaction::Dispute(Metadata::new(1, 1)),
+                Transaction::Resolve(Metadata::new(1, 1)),
+            ]
+            .into_iter()
+            .enumerate()
+            {
+                router.route(row as u64, txn).expect("can route");
+            }
+            // Both shards are through once they answer
+            router.holder(1, 0b11).expect("shards answer");
+            router.take_reports();
+            assert_eq!(vec![(2, 0b01)], router.claims.drain().collect::<Vec<_>>());
+            assert!(router.finalized.contains(1));
+
+            router
+                .route(4, Transaction::Deposit(Metadata::new(2, 1), dec!(1)))
+                .expect("can route");
+            router
+                .route(5, Transaction::Dispute(Metadata::new(2, 1)))
+                .expect("can route");
+            router.finish();
+            workers
+                .into_iter()
+                .flat_map(|worker| {
+                    worker
+                        .join()
+                        .expect("worker finishes")
+                        .expect("can process")
+                        .1
+                })
+                .map(|(_, rejection)| rejection)
+                .collect::<Vec<_>>()
+        });
+        assert_eq!(
+            vec![
+                Rejection::DuplicateTransaction {
+                    client: 2,
+                    tx_id: 1
+                },
+                Rejection::ClientMismatch {
+                    client: 2,
+                    tx_id: 1,
+                    owner: 1
+                }
+            ],
+            rejections
+        );
+    }
+
+    #[test]
+    fn tx_ids_are_global_across_shards() {
+        let dir = tempfile::tempdir().expect("can create tempdir");
+        let path = dir.path().join("in.csv");
+        std::fs::write(
+            &path,
+            "type,client,tx,amount
+deposit,1,1,-1.0
+deposit,2,1,5.0
+deposit,1,1,3.0
+withdrawal,3,2,1.0
+deposit,3,2,4.0
+dispute,1,1,
+dispute,2,1,
+deposit,1,3,2.0
+chargeback,2,1,
+dispute,3,3,
+withdrawal,1,2,1.0
+",
+        )
+        .expect("can write input");
+        let expected = in_order(&path);
+        assert_eq!(6, expected.2.len(), "{:?}", expected.2);
+        for shards in [2, 3] {
+            assert_eq!(expected, sharded(&path, shards), "{shards} shards");
+        }
+    }
+}
@@ -0,0 +1,392 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use rust_decimal::Decimal;
+
+use crate::{
+    error::Error,
+    objects::{
+        accounts::Account,
+        transactions::{InitialState, Metadata, TransactionState},
+    },
+    policy::WithdrawalDisputes,
+};
+
+// NOTE(juf): State of the processor between runs, one record per line:
+//
+//   csv-money-state,3
+//   next_tx_id,42
+//   withdrawal_disputes,credit-on-chargeback
+//   account,1,false,10.5,0
+//   txn,7,1,10.5,deposit
+//   external_id,8,FITID-0001
+//   finalized,100,163
+//   end,5,9f3c2a1b0e4d5c6a
+//
+// The trailer holds the number of records and a FNV-1a hash of every byte before it, a file
+// without one was not written to the end. The file is never written in place, the new state goes
+// into a sibling file which is synced and then renamed over the old one, so a crash leaves either
+// the old or the new state behind, never a mix.
+
+/// Magic of the header line, followed by the format version.
+const MAGIC: &str = "csv-money-state";
+pub(crate) const VERSION: u32 = 3;
+
+/// Single entry of the state file.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Record {
+    /// Next tx id handed out to statement bookings.
+    NextTxId(u32),
+    Account(Account),
+    /// Cached transaction, as kept by the processor to dispute it later on.
+    Txn(Metadata, Decimal, TransactionState),
+    /// External id of a statement booking and the tx id it was applied as.
+    ExternalId(u32, String),
+    /// First and last tx id of a run of compacted, finalized transactions, since version 2.
+    Finalized(u32, u32),
+    /// Policy the books are kept with, since version 3.
+    WithdrawalDisputes(WithdrawalDisputes),
+}
+
+/// Replaces the state at `path` with `records` in one atomic step. The first record that fails
+/// to be produced leaves the previous state in place.
+pub(crate) fn write(
+    path: &Path,
+    records: impl Iterator<Item = Result<Record, Error>>,
+) -> Result<(), Error> {
+    let tmp = sibling(path, ".tmp");
+    let f = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&tmp)?;
+    let mut out = HashingWriter {
+        inner: BufWriter::new(f),
+        hash: FNV_OFFSET,
+    };
+    writeln!(out, "{MAGIC},{VERSION}")?;
+    let mut count = 0u64;
+    for record in records {
+        match record? {
+            Record::NextTxId(tx_id) => writeln!(out, "next_tx_id,{tx_id}")?,
+            Record::Account(acc) => writeln!(
+                out,
+                "account,{},{},{},{}",
+                acc.id, acc.locked, acc.available, acc.held
+            )?,
+            Record::Txn(metadata, amount, state) => writeln!(
+                out,
+                "txn,{},{},{amount},{}",
+                metadata.tx_id,
+                metadata.client,
+                state_name(state)
+            )?,
+            Record::ExternalId(tx_id, external_id) => {
+                if external_id.contains(['\n', '\r']) {
+                    return Err(Error::InvalidState(format!(
+                        "external id {external_id:?} spans several lines"
+                    )));
+                }
+                writeln!(out, "external_id,{tx_id},{external_id}")?
+            }
+            Record::Finalized(first, last) => writeln!(out, "finalized,{first},{last}")?,
+            Record::WithdrawalDisputes(policy) => writeln!(out, "withdrawal_disputes,{policy}")?,
+        }
+        count += 1;
+    }
+    let hash = out.hash;
+    let mut inner = out.inner;
+    writeln!(inner, "end,{count},{hash:016x}")?;
+    let f = inner.into_inner().map_err(|err| err.into_error())?;
+    // NOTE(juf): Data has to be on disk before the rename makes it visible, otherwise a crash
+    // can leave a renamed but empty file behind.
+    f.sync_all()?;
+    drop(f);
+    std::fs::rename(&tmp, path)?;
+    sync_parent(path)
+}
+
+/// Reads the state at `path`, `None` if there is none yet.
+pub(crate) fn read(path: &Path) -> Result<Option<Vec<Record>>, Error> {
+    let f = match File::open(path) {
+        Ok(f) => f,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    let mut records = Vec::new();
+    let mut hash = FNV_OFFSET;
+    let mut lines = BufReader::new(f).lines().zip(1u64..);
+    let invalid = |line: u64, reason: &str| Error::InvalidState(format!("line {line}: {reason}"));
+    let header = match lines.next() {
+        Some((header, _)) => header?,
+        None => return Err(Error::InvalidState("empty file".into())),
+    };
+    match header.split_once(',') {
+        // Versions 2 and 3 only added a record, older files are read as they are
+        Some((MAGIC, "1" | "2")) => {}
+        Some((MAGIC, version)) if version == VERSION.to_string() => {}
+        Some((MAGIC, version)) => {
+            return Err(Error::InvalidState(format!(
+                "unsupported version {version}, expected {VERSION}"
+            )));
+        }
+        _ => return Err(Error::InvalidState("not a state file".into())),
+    }
+    fnv(&mut hash, header.as_bytes());
+    fnv(&mut hash, b"\n");
+    for (text, line) in lines {
+        let text = text?;
+        let mut fields = text.splitn(3, ',');
+        let kind = fields.next().unwrap_or_default();
+        if kind == "end" {
+            let count = fields.next().and_then(|count| count.parse::<usize>().ok());
+            let expected = fields.next().and_then(|h| u64::from_str_radix(h, 16).ok());
+            if count != Some(records.len()) || expected != Some(hash) {
+                return Err(invalid(
+                    line,
+                    "checksum does not match, the file is corrupt",
+                ));
+            }
+            return Ok(Some(records));
+        }
+        fnv(&mut hash, text.as_bytes());
+        fnv(&mut hash, b"\n");
+        let record = match kind {
+            "next_tx_id" => Record::NextTxId(parse(fields.next(), line)?),
+            "withdrawal_disputes" => Record::WithdrawalDisputes(parse(fields.next(), line)?),
+            "account" => {
+                let mut fields = text.split(',').skip(1);
+                let mut acc = Account::new(parse(fields.next(), line)?);
+                acc.locked = parse(fields.next(), line)?;
+                acc.available = parse(fields.next(), line)?;
+                acc.held = parse(fields.next(), line)?;
+                if fields.next().is_some() {
+                    return Err(invalid(line, "too many fields"));
+                }
+                if acc.checked_total().is_err() {
+                    return Err(invalid(line, "total balance overflows"));
+                }
+                acc.into()
+            }
+            "txn" => {
+                let mut fields = text.split(',').skip(1);
+                let tx_id = parse(fields.next(), line)?;
+                let client = parse(fields.next(), line)?;
+                let amount = parse(fields.next(), line)?;
+                let state = fields
+                    .next()
+                    .and_then(state_of)
+                    .ok_or_else(|| invalid(line, "invalid transaction state"))?;
+                Record::Txn(Metadata { client, tx_id }, amount, state)
+            }
+            "finalized" => {
+                let mut fields = text.split(',').skip(1);
+                let first = parse(fields.next(), line)?;
+                let last = parse(fields.next(), line)?;
+                if first > last || fields.next().is_some() {
+                    return Err(invalid(line, "invalid range"));
+                }
+                Record::Finalized(first, last)
+            }
+            "external_id" => {
+                let tx_id = parse(fields.next(), line)?;
+                let external_id = fields
+                    .next()
+                    .ok_or_else(|| invalid(line, "missing external id"))?;
+                Record::ExternalId(tx_id, external_id.to_string())
+            }
+            _ => return Err(invalid(line, &format!("unknown record {kind:?}"))),
+        };
+        records.push(record);
+    }
+    Err(Error::InvalidState(
+        "missing trailer, the file is truncated".into(),
+    ))
+}
+
+impl From<Account> for Record {
+    fn from(acc: Account) -> Self {
+        Record::Account(acc)
+    }
+}
+
+fn parse<T: FromStr>(field: Option<&str>, line: u64) -> Result<T, Error> {
+    let field = field.unwrap_or_default();
+    field
+        .parse()
+        .map_err(|_| Error::InvalidState(format!("line {line}: invalid value {field:?}")))
+}
+
+pub(crate) fn state_name(state: TransactionState) -> &'static str {
+    match state {
+        TransactionState::Initial(InitialState::Deposit) => "deposit",
+        TransactionState::Initial(InitialState::Withdrawal) => "withdrawal",
+        TransactionState::Disputed(InitialState::Deposit) => "disputed_deposit",
+        TransactionState::Disputed(InitialState::Withdrawal) => "disputed_withdrawal",
+        TransactionState::Finalized => "finalized",
+    }
+}
+
+pub(crate) fn state_of(name: &str) -> Option<TransactionState> {
+    match name {
+        "deposit" => Some(TransactionState::Initial(InitialState::Deposit)),
+        "withdrawal" => Some(TransactionState::Initial(InitialState::Withdrawal)),
+        "disputed_deposit" => Some(TransactionState::Disputed(InitialState::Deposit)),
+        "disputed_withdrawal" => Some(TransactionState::Disputed(InitialState::Withdrawal)),
+        "finalized" => Some(TransactionState::Finalized),
+        _ => None,
+    }
+}
+
+/// `path` with `suffix` appended to its file name, in the same directory so a rename stays on
+/// the same file system.
+pub(crate) fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Persists the rename itself, the directory entry lives in the parent.
+#[cfg(unix)]
+pub(crate) fn sync_parent(path: &Path) -> Result<(), Error> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    File::open(parent)?.sync_all()?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) -> Result<(), Error> {
+    Ok(())
+}
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+fn fnv(hash: &mut u64, bytes: &[u8]) {
+    for byte in bytes {
+        *hash ^= u64::from(*byte);
+        *hash = hash.wrapping_mul(FNV_PRIME);
+    }
+}
+
+/// Hashes everything written through it.
+struct HashingWriter<W> {
+    inner: W,
+    hash: u64,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        fnv(&mut self.hash, &buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::{Decimal, dec};
+
+    use crate::{
+        error::Error,
+        objects::{
+            accounts::Account,
+            transactions::{InitialState, Metadata, TransactionState},
+        },
+        policy::WithdrawalDisputes,
+        state::{Record, read, sibling, write},
+    };
+
+    fn records() -> Vec<Record> {
+        vec![
+            Record::NextTxId(9),
+            Account {
+                id: 1,
+                locked: true,
+                available: dec!(1.5),
+                held: dec!(0.0001),
+            }
+            .into(),
+            Record::Txn(
+                Metadata::new(1, 7),
+                dec!(2.25),
+                TransactionState::Disputed(InitialState::Withdrawal),
+            ),
+            Record::Txn(Metadata::new(2, 8), dec!(3), TransactionState::Finalized),
+            Record::ExternalId(8, "FITID,with,commas".into()),
+            Record::Finalized(10, 12),
+            Record::WithdrawalDisputes(WithdrawalDisputes::Hold),
+        ]
+    }
+
+    #[test]
+    fn state_round_trips() {
+        let dir = tempfile::tempdir().expect("can create tempdir");
+        let path = dir.path().join("state");
+        assert_eq!(None, read(&path).expect("missing state is no error"));
+        write(&path, records().into_iter().map(Ok)).expect("can write");
+        assert_eq!(Some(records()), read(&path).expect("can read"));
+        assert!(
+            !sibling(&path, ".tmp").exists(),
+            "temporary file is renamed"
+        );
+
+        // A leftover of a crashed run is replaced, the previous state is not touched until then
+        std::fs::write(sibling(&path, ".tmp"), "garbage").expect("can write");
+        write(&path, records().into_iter().take(2).map(Ok)).expect("can write");
+        assert_eq!(
+            Some(records().into_iter().take(2).collect()),
+            read(&path).expect("can read")
+        );
+    }
+
+    #[test]
+    fn damaged_state_is_refused() {
+        let dir = tempfile::tempdir().expect("can create tempdir");
+        let path = dir.path().join("state");
+        write(&path, records().into_iter().map(Ok)).expect("can write");
+        let written = std::fs::read_to_string(&path).expect("can read");
+
+        let damaged = [
+            // Truncated before the trailer
+            written[..written.find("end,").expect("has trailer")].to_string(),
+            // Changed amount
+            written.replace("2.25", "2.26"),
+            // Dropped record
+            written.replace("next_tx_id,9\n", ""),
+            written.replace("csv-money-state,3", "csv-money-state,4"),
+            written.replace("csv-money-state,3", "type,client,tx,amount"),
+            String::new(),
+        ];
+        for content in damaged {
+            std::fs::write(&path, &content).expect("can write");
+            assert!(
+                matches!(read(&path), Err(Error::InvalidState(_))),
+                "should refuse {content:?}"
+            );
+        }
+
+        let overflowing = Account {
+            id: 1,
+            locked: false,
+            available: Decimal::MAX,
+            held: dec!(1),
+        };
+        write(&path, [Ok(overflowing.into())].into_iter()).expect("can write");
+        assert!(
+            matches!(read(&path), Err(Error::InvalidState(_))),
+            "total of the account overflows"
+        );
+    }
+}
@@ -0,0 +1,227 @@
+use std::collections::{HashMap, hash_map::Entry};
+
+use crate::{
+    amount::Amount,
+    error::Error,
+    objects::{
+        accounts::Account,
+        transactions::{Metadata, TransactionState},
+    },
+};
+
+pub(crate) mod compact;
+pub(crate) mod disk;
+#[cfg(feature = "sqlite")]
+pub(crate) mod sqlite;
+
+// NOTE(juf): Storage behind the processor. Accounts are bounded by the u16 client id, a few MB at
+// most, so they always live in memory and the store can hand out plain references. Transactions
+// are bounded by the u32 tx id only, ~100 GB in the worst case, so their store may have to go to
+// disk or a database, which can fail, hence every access returns a `Result`.
+
+/// What the processor remembers about a deposit or withdrawal to dispute it later on.
+pub(crate) type CachedTransaction = (Metadata, Amount, TransactionState);
+
+pub(crate) trait AccountStore {
+    fn get(&self, client: u16) -> Option<&Account>;
+
+    /// The account of `client`, opened empty on first use.
+    fn get_or_create(&mut self, client: u16) -> &mut Account;
+
+    /// Stores `account`, returns the one it replaced.
+    fn insert(&mut self, account: Account) -> Option<Account>;
+
+    /// Every account, in no particular order.
+    fn accounts(&self) -> Box<dyn Iterator<Item = &Account> + '_>;
+
+    /// Accounts changed since the last call, for stores that keep the books beyond the run.
+    fn take_changed(&mut self) -> Vec<Account> {
+        Vec::new()
+    }
+}
+
+pub(crate) trait TransactionStore {
+    /// Mutable access to the cached transaction, changes are kept by the store.
+    fn get_mut(&mut self, tx_id: u32) -> Result<Option<&mut CachedTransaction>, Error>;
+
+    fn contains(&mut self, tx_id: u32) -> Result<bool, Error>;
+
+    /// Caches the transaction unless its tx id is taken already, returns whether it was.
+    fn insert_new(&mut self, tx_id: u32, txn: CachedTransaction) -> Result<bool, Error>;
+
+    /// Every cached transaction, in no particular order.
+    fn transactions(&self) -> Box<dyn Iterator<Item = Result<CachedTransaction, Error>> + '_>;
+
+    /// Forgets the transaction and returns it. Stores that keep the books beyond the run keep
+    /// it and return `None`, there is nothing to gain from compacting them.
+    fn remove(&mut self, _tx_id: u32) -> Result<Option<CachedTransaction>, Error> {
+        Ok(None)
+    }
+
+    /// Bytes of memory the store takes up, roughly.
+    fn memory(&self) -> usize;
+
+    /// Makes every change since the last commit durable together with the changed `accounts`,
+    /// the next tx id and the applied external ids, all or nothing. Stores that forget
+    /// everything at the end of the run have nothing to do.
+    fn commit(
+        &mut self,
+        _accounts: Vec<Account>,
+        _next_tx_id: u32,
+        _external_ids: &HashMap<String, u32>,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl AccountStore for HashMap<u16, Account> {
+    fn get(&self, client: u16) -> Option<&Account> {
+        HashMap::get(self, &client)
+    }
+
+    fn get_or_create(&mut self, client: u16) -> &mut Account {
+        self.entry(client).or_insert_with(|| Account::new(client))
+    }
+
+    fn insert(&mut self, account: Account) -> Option<Account> {
+        HashMap::insert(self, account.id, account)
+    }
+
+    fn accounts(&self) -> Box<dyn Iterator<Item = &Account> + '_> {
+        Box::new(self.values())
+    }
+}
+
+impl TransactionStore for HashMap<u32, CachedTransaction> {
+    fn get_mut(&mut self, tx_id: u32) -> Result<Option<&mut CachedTransaction>, Error> {
+        Ok(HashMap::get_mut(self, &tx_id))
+    }
+
+    fn contains(&mut self, tx_id: u32) -> Result<bool, Error> {
+        Ok(self.contains_key(&tx_id))
+    }
+
+    fn insert_new(&mut self, tx_id: u32, txn: CachedTransaction) -> Result<bool, Error> {
+        match self.entry(tx_id) {
+            Entry::Occupied(_) => Ok(false),
+            Entry::Vacant(entry) => {
+                entry.insert(txn);
+                Ok(true)
+            }
+        }
+    }
+
+    fn transactions(&self) -> Box<dyn Iterator<Item = Result<CachedTransaction, Error>> + '_> {
+        Box::new(self.values().cloned().map(Ok))
+    }
+
+    fn remove(&mut self, tx_id: u32) -> Result<Option<CachedTransaction>, Error> {
+        Ok(HashMap::remove(self, &tx_id))
+    }
+
+    fn memory(&self) -> usize {
+        // NOTE(juf): hashbrown keeps one control byte per bucket next to the entry
+        self.capacity() * (size_of::<(u32, CachedTransaction)>() + 1)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::collections::HashMap;
+
+    use rust_decimal::dec;
+
+    use crate::{
+        amount::Amount,
+        objects::{
+            accounts::Account,
+            transactions::{InitialState, Metadata, TransactionState},
+        },
+        store::{AccountStore, CachedTransaction, TransactionStore},
+    };
+
+    /// Behaviour every [`TransactionStore`] has to show, shared by the tests of each backend.
+    pub(crate) fn check_transaction_store(store: &mut impl TransactionStore) {
+        let deposit = (
+            Metadata::new(1, 7),
+            Amount::from_units(105_000),
+            TransactionState::Initial(InitialState::Deposit),
+        );
+        assert!(!store.contains(7).expect("can look up"));
+        assert_eq!(None, store.get_mut(7).expect("can look up"));
+        assert!(store.insert_new(7, deposit.clone()).expect("can insert"));
+        assert!(
+            !store
+                .insert_new(
+                    7,
+                    (
+                        Metadata::new(2, 7),
+                        Amount::from_units(10_000),
+                        TransactionState::Finalized
+                    )
+                )
+                .expect("can insert"),
+            "tx id is taken"
+        );
+        assert!(store.contains(7).expect("can look up"));
+        let cached = store.get_mut(7).expect("can look up").expect("is cached");
+        assert_eq!(&deposit, cached);
+        cached.2 = TransactionState::Disputed(InitialState::Deposit);
+        assert_eq!(
+            Some(&mut (
+                Metadata::new(1, 7),
+                Amount::from_units(105_000),
+                TransactionState::Disputed(InitialState::Deposit)
+            )),
+            store.get_mut(7).expect("can look up"),
+            "changes are kept"
+        );
+        let withdrawal = (
+            Metadata::new(1, 8),
+            Amount::from_units(20_000),
+            TransactionState::Initial(InitialState::Withdrawal),
+        );
+        assert!(store.insert_new(8, withdrawal.clone()).expect("can insert"));
+        assert!(store.memory() > 0);
+        let mut all: Vec<CachedTransaction> = store
+            .transactions()
+            .collect::<Result<_, _>>()
+            .expect("can iterate");
+        all.sort_by_key(|(metadata, _, _)| metadata.tx_id);
+        assert_eq!(
+            vec![
+                (
+                    Metadata::new(1, 7),
+                    Amount::from_units(105_000),
+                    TransactionState::Disputed(InitialState::Deposit)
+                ),
+                withdrawal
+            ],
+            all
+        );
+    }
+
+    #[test]
+    fn hash_map_transaction_store() {
+        check_transaction_store(&mut HashMap::new());
+    }
+
+    #[test]
+    fn hash_map_account_store() {
+        let mut store = HashMap::new();
+        assert_eq!(None, AccountStore::get(&store, 1));
+        store.get_or_create(1).deposit(dec!(5)).expect("fits");
+        assert_eq!(dec!(5), store.get_or_create(1).available);
+        assert_eq!(
+            Some(Account {
+                id: 1,
+                locked: false,
+                available: dec!(5),
+                held: dec!(0)
+            }),
+            AccountStore::insert(&mut store, Account::new(1))
+        );
+        assert_eq!(Some(&Account::new(1)), AccountStore::get(&store, 1));
+        assert_eq!(1, store.accounts().count());
+    }
+}
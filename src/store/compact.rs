@@ -0,0 +1,288 @@
+use std::fmt;
+
+// NOTE(juf): Resolved and charged back transactions can never change again, all the processor
+// still needs to know about them is that their tx id is taken. Compaction moves them out of the
+// transaction store into a set of tx ids, ~40 bytes per transaction in the HashMap down to 2 bytes
+// or, for dense ranges, 1 bit. The set is built like a roaring bitmap: tx ids sharing their upper
+// 16 bits go into one container, a sorted array of the lower 16 bits while sparse, a fixed 8 KiB
+// bitmap once the array would be larger.
+
+/// Entries at which an array container is as large as a bitmap one.
+const ARRAY_MAX: usize = 4096;
+const BITMAP_WORDS: usize = 1024;
+
+/// Set of finalized tx ids.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(crate) struct FinalizedSet {
+    /// Sorted by the upper 16 bits of the tx ids they hold.
+    containers: Vec<(u16, Container)>,
+    len: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Container {
+    Array(Vec<u16>),
+    Bitmap(Box<[u64; BITMAP_WORDS]>),
+}
+
+impl FinalizedSet {
+    /// Adds `tx_id`, returns whether it was new.
+    pub(crate) fn insert(&mut self, tx_id: u32) -> bool {
+        let (high, low) = ((tx_id >> 16) as u16, tx_id as u16);
+        let idx = match self
+            .containers
+            .binary_search_by_key(&high, |(high, _)| *high)
+        {
+            Ok(idx) => idx,
+            Err(idx) => {
+                self.containers
+                    .insert(idx, (high, Container::Array(Vec::new())));
+                idx
+            }
+        };
+        let inserted = self.containers[idx].1.insert(low);
+        self.len += u64::from(inserted);
+        inserted
+    }
+
+    pub(crate) fn contains(&self, tx_id: u32) -> bool {
+        let (high, low) = ((tx_id >> 16) as u16, tx_id as u16);
+        match self
+            .containers
+            .binary_search_by_key(&high, |(high, _)| *high)
+        {
+            Ok(idx) => self.containers[idx].1.contains(low),
+            Err(_) => false,
+        }
+    }
+
+    pub(crate) fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Bytes the set occupies.
+    pub(crate) fn memory(&self) -> usize {
+        size_of::<Self>()
+            + self.containers.capacity() * size_of::<(u16, Container)>()
+            + self
+                .containers
+                .iter()
+                .map(|(_, container)| match container {
+                    Container::Array(lows) => lows.capacity() * size_of::<u16>(),
+                    Container::Bitmap(_) => BITMAP_WORDS * size_of::<u64>(),
+                })
+                .sum::<usize>()
+    }
+
+    /// Runs of consecutive tx ids as first and last tx id, ascending.
+    pub(crate) fn ranges(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        let mut ids = self.containers.iter().flat_map(|(high, container)| {
+            let high = u32::from(*high) << 16;
+            container.iter().map(move |low| high | u32::from(low))
+        });
+        let mut run = ids.next().map(|first| (first, first));
+        std::iter::from_fn(move || {
+            let (first, mut last) = run?;
+            loop {
+                match ids.next() {
+                    Some(next) if next == last + 1 => last = next,
+                    next => {
+                        run = next.map(|next| (next, next));
+                        return Some((first, last));
+                    }
+                }
+            }
+        })
+    }
+}
+
+impl Container {
+    fn insert(&mut self, low: u16) -> bool {
+        match self {
+            Container::Array(lows) => {
+                let Err(idx) = lows.binary_search(&low) else {
+                    return false;
+                };
+                lows.insert(idx, low);
+                if lows.len() > ARRAY_MAX {
+                    let mut words = Box::new([0u64; BITMAP_WORDS]);
+                    for low in lows.iter() {
+                        words[usize::from(*low) / 64] |= 1 << (low % 64);
+                    }
+                    *self = Container::Bitmap(words);
+                }
+                true
+            }
+            Container::Bitmap(words) => {
+                let (word, bit) = (usize::from(low) / 64, 1u64 << (low % 64));
+                let inserted = words[word] & bit == 0;
+                words[word] |= bit;
+                inserted
+            }
+        }
+    }
+
+    fn contains(&self, low: u16) -> bool {
+        match self {
+            Container::Array(lows) => lows.binary_search(&low).is_ok(),
+            Container::Bitmap(words) => words[usize::from(low) / 64] & (1 << (low % 64)) != 0,
+        }
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = u16> + '_> {
+        match self {
+            Container::Array(lows) => Box::new(lows.iter().copied()),
+            Container::Bitmap(words) => {
+                Box::new(words.iter().enumerate().flat_map(|(idx, word)| {
+                    (0..64)
+                        .filter(move |bit| word & (1 << bit) != 0)
+                        .map(move |bit| (idx * 64 + bit) as u16)
+                }))
+            }
+        }
+    }
+}
+
+/// When finalized transactions are moved out of the transaction store.
+#[derive(Debug, Default)]
+pub(crate) struct Compaction {
+    /// Compact once that many transactions were finalized since the last time, never if `None`.
+    every: Option<usize>,
+    /// Finalized, not compacted yet.
+    pending: Vec<u32>,
+    pub(crate) finalized: FinalizedSet,
+    compactions: u64,
+}
+
+impl Compaction {
+    pub(crate) fn every(finalized: usize) -> Self {
+        Self {
+            every: Some(finalized.max(1)),
+            ..Self::default()
+        }
+    }
+
+    /// Notes that `tx_id` was finalized, returns whether compaction is due.
+    pub(crate) fn finalized(&mut self, tx_id: u32) -> bool {
+        match self.every {
+            Some(every) => {
+                self.pending.push(tx_id);
+                self.pending.len() >= every
+            }
+            None => false,
+        }
+    }
+
+    /// Takes the finalized transactions waiting to be compacted, counting the compaction.
+    pub(crate) fn take_pending(&mut self) -> Vec<u32> {
+        self.compactions += 1;
+        std::mem::take(&mut self.pending)
+    }
+
+    /// Takes over what `other` compacted and has pending, e.g., of another shard.
+    pub(crate) fn absorb(&mut self, other: Compaction) {
+        self.every = self.every.or(other.every);
+        self.pending.extend(other.pending);
+        for (first, last) in other.finalized.ranges() {
+            for tx_id in first..=last {
+                self.finalized.insert(tx_id);
+            }
+        }
+        self.compactions += other.compactions;
+    }
+
+    pub(crate) fn stats(&self, store_memory: usize) -> MemoryStats {
+        MemoryStats {
+            store_memory,
+            finalized: self.finalized.len(),
+            finalized_memory: self.finalized.memory(),
+            pending: self.pending.len(),
+            compactions: self.compactions,
+        }
+    }
+}
+
+/// Memory taken up by the transactions the processor remembers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct MemoryStats {
+    /// Bytes of the transaction store, as far as it is in memory.
+    pub(crate) store_memory: usize,
+    /// Compacted transactions and the bytes they take up.
+    pub(crate) finalized: u64,
+    pub(crate) finalized_memory: usize,
+    /// Finalized transactions still in the transaction store, waiting to be compacted.
+    pub(crate) pending: usize,
+    pub(crate) compactions: u64,
+}
+
+impl fmt::Display for MemoryStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "transaction store {} KiB, {} compacted transactions in {} KiB, {} waiting, {} compactions",
+            self.store_memory / 1024,
+            self.finalized,
+            self.finalized_memory / 1024,
+            self.pending,
+            self.compactions
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::store::compact::{ARRAY_MAX, Compaction, FinalizedSet};
+
+    #[test]
+    fn finalized_set() {
+        let mut set = FinalizedSet::default();
+        assert!(!set.contains(7));
+        assert!(set.insert(7));
+        assert!(!set.insert(7));
+        assert!(set.insert(u32::MAX));
+        assert!(set.insert(0));
+        assert!(set.contains(7) && set.contains(u32::MAX) && set.contains(0));
+        assert!(!set.contains(8) && !set.contains(7 + (1 << 16)));
+        assert_eq!(3, set.len());
+        assert_eq!(
+            vec![(0, 0), (7, 7), (u32::MAX, u32::MAX)],
+            set.ranges().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn dense_containers_turn_into_bitmaps() {
+        let mut set = FinalizedSet::default();
+        // Every other tx id of one container, then the rest of it
+        for tx_id in (0..2 * ARRAY_MAX as u32 + 2).step_by(2) {
+            assert!(set.insert(tx_id + (5 << 16)));
+        }
+        let as_bitmap = set.memory();
+        assert!(as_bitmap < 9 * 1024, "{as_bitmap} bytes");
+        assert!(set.contains(5 << 16) && !set.contains((5 << 16) + 1));
+        for tx_id in 0..1 << 16 {
+            set.insert(tx_id + (5 << 16));
+        }
+        assert_eq!(1 << 16, set.len());
+        assert_eq!(as_bitmap, set.memory());
+        assert_eq!(
+            vec![(5 << 16, (6 << 16) - 1)],
+            set.ranges().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn compaction_is_due_every_n_finalized() {
+        let mut compaction = Compaction::default();
+        assert!(!compaction.finalized(1), "never by default");
+        assert!(compaction.take_pending().is_empty());
+
+        let mut compaction = Compaction::every(2);
+        assert!(!compaction.finalized(1));
+        assert!(compaction.finalized(2));
+        assert_eq!(vec![1, 2], compaction.take_pending());
+        assert!(!compaction.finalized(3));
+        assert_eq!(1, compaction.stats(0).compactions);
+        assert_eq!(1, compaction.stats(0).pending);
+    }
+}
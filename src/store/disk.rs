@@ -0,0 +1,481 @@
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use crate::{
+    amount::Amount,
+    error::Error,
+    objects::transactions::{InitialState, Metadata, TransactionState},
+    store::{CachedTransaction, TransactionStore},
+};
+
+// NOTE(juf): Transaction store for when the txn_cache does not fit into memory. Tx ids are dense
+// integers, so instead of a B-tree the file is cut into pages of `SLOTS` consecutive tx ids and
+// the tx id itself says which page and slot a transaction lives in. Pages are appended to the
+// file the first time they are written (log-structured allocation, no sparse files needed) and a
+// two-level page table maps page numbers onto file offsets, ~268 MB when the whole u32 space is
+// in use, next to nothing for a few million transactions. The most recently used pages are kept
+// decoded in memory, the least recently used one is written back when room is needed.
+//
+// The file is scratch space for a single run, it is truncated when the store is created. State
+// that outlives the run goes through `Processor::save`.
+
+/// Tx ids per page.
+const SLOTS: u32 = 128;
+/// state: u8 (0 empty, else state + 1) | client: u16 LE | amount units: i64 LE
+const SLOT_LEN: usize = 1 + 2 + 8;
+const PAGE_LEN: usize = SLOTS as usize * SLOT_LEN;
+/// Page numbers per page table leaf.
+const LEAF: usize = 4096;
+const NIL: usize = usize::MAX;
+
+pub(crate) struct DiskStore {
+    file: File,
+    /// File offset of every page written so far.
+    table: PageTable,
+    /// Where the next page goes.
+    end: u64,
+    frames: Vec<Frame>,
+    /// Frame holding a page, by page number.
+    resident: HashMap<u32, usize>,
+    capacity: usize,
+    /// Most and least recently used frame.
+    head: usize,
+    tail: usize,
+    buf: Vec<u8>,
+}
+
+/// Page held in memory, linked into the LRU list.
+struct Frame {
+    page: u32,
+    dirty: bool,
+    slots: Box<[Option<CachedTransaction>]>,
+    prev: usize,
+    next: usize,
+}
+
+impl DiskStore {
+    /// Store backed by the file at `path`, keeping at most `memory_budget` bytes of pages in
+    /// memory, at least one.
+    pub(crate) fn create(path: &Path, memory_budget: usize) -> Result<Self, Error> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        Ok(Self {
+            file,
+            table: PageTable::default(),
+            end: 0,
+            frames: Vec::new(),
+            resident: HashMap::new(),
+            capacity: (memory_budget / Self::frame_size()).max(1),
+            head: NIL,
+            tail: NIL,
+            buf: vec![0; PAGE_LEN],
+        })
+    }
+
+    /// Memory a page takes up while held in memory, roughly.
+    pub(crate) fn frame_size() -> usize {
+        size_of::<Frame>()
+            + SLOTS as usize * size_of::<Option<CachedTransaction>>()
+            // entry in `resident`, HashMap overhead included
+            + 2 * size_of::<(u32, usize)>()
+    }
+
+    // used by test
+    #[allow(dead_code)]
+    /// Number of pages held in memory and written to the file.
+    pub(crate) fn pages(&self) -> (usize, usize) {
+        (self.frames.len(), (self.end / PAGE_LEN as u64) as usize)
+    }
+
+    /// Frame holding `page`, read from the file or created empty. `None` if the page neither
+    /// is in memory nor was written, unless `create` is set.
+    fn frame(&mut self, page: u32, create: bool) -> Result<Option<usize>, Error> {
+        if let Some(&idx) = self.resident.get(&page) {
+            self.unlink(idx);
+            self.push_front(idx);
+            return Ok(Some(idx));
+        }
+        let offset = self.table.get(page);
+        if offset.is_none() && !create {
+            return Ok(None);
+        }
+        let mut slots = match offset {
+            Some(offset) => {
+                self.file.seek(SeekFrom::Start(offset))?;
+                self.file.read_exact(&mut self.buf)?;
+                decode(page, &self.buf)?
+            }
+            None => vec![None; SLOTS as usize].into_boxed_slice(),
+        };
+        let idx = if self.frames.len() < self.capacity {
+            self.frames.push(Frame {
+                page,
+                dirty: false,
+                slots,
+                prev: NIL,
+                next: NIL,
+            });
+            self.frames.len() - 1
+        } else {
+            let idx = self.tail;
+            self.write_back(idx)?;
+            self.unlink(idx);
+            let frame = &mut self.frames[idx];
+            self.resident.remove(&frame.page);
+            std::mem::swap(&mut frame.slots, &mut slots);
+            frame.page = page;
+            frame.dirty = false;
+            idx
+        };
+        self.resident.insert(page, idx);
+        self.push_front(idx);
+        Ok(Some(idx))
+    }
+
+    fn write_back(&mut self, idx: usize) -> Result<(), Error> {
+        let frame = &self.frames[idx];
+        if !frame.dirty {
+            return Ok(());
+        }
+        encode(&frame.slots, &mut self.buf);
+        let offset = match self.table.get(frame.page) {
+            Some(offset) => offset,
+            None => {
+                let offset = self.end;
+                self.end += PAGE_LEN as u64;
+                self.table.set(frame.page, offset);
+                offset
+            }
+        };
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.write_all(&self.buf)?;
+        self.frames[idx].dirty = false;
+        Ok(())
+    }
+
+    fn unlink(&mut self, idx: usize) {
+        let (prev, next) = (self.frames[idx].prev, self.frames[idx].next);
+        match prev {
+            NIL => self.head = next,
+            prev => self.frames[prev].next = next,
+        }
+        match next {
+            NIL => self.tail = prev,
+            next => self.frames[next].prev = prev,
+        }
+    }
+
+    fn push_front(&mut self, idx: usize) {
+        self.frames[idx].prev = NIL;
+        self.frames[idx].next = self.head;
+        match self.head {
+            NIL => self.tail = idx,
+            head => self.frames[head].prev = idx,
+        }
+        self.head = idx;
+    }
+}
+
+impl TransactionStore for DiskStore {
+    fn get_mut(&mut self, tx_id: u32) -> Result<Option<&mut CachedTransaction>, Error> {
+        let Some(idx) = self.frame(tx_id / SLOTS, false)? else {
+            return Ok(None);
+        };
+        let frame = &mut self.frames[idx];
+        let slot = &mut frame.slots[(tx_id % SLOTS) as usize];
+        // NOTE(juf): Handing out `&mut` means the caller may change it, assume it does
+        frame.dirty |= slot.is_some();
+        Ok(slot.as_mut())
+    }
+
+    fn contains(&mut self, tx_id: u32) -> Result<bool, Error> {
+        let Some(idx) = self.frame(tx_id / SLOTS, false)? else {
+            return Ok(false);
+        };
+        Ok(self.frames[idx].slots[(tx_id % SLOTS) as usize].is_some())
+    }
+
+    fn insert_new(&mut self, tx_id: u32, txn: CachedTransaction) -> Result<bool, Error> {
+        let idx = self
+            .frame(tx_id / SLOTS, true)?
+            .expect("pages are created on demand");
+        let frame = &mut self.frames[idx];
+        let slot = &mut frame.slots[(tx_id % SLOTS) as usize];
+        if slot.is_some() {
+            return Ok(false);
+        }
+        *slot = Some(txn);
+        frame.dirty = true;
+        Ok(true)
+    }
+
+    fn remove(&mut self, tx_id: u32) -> Result<Option<CachedTransaction>, Error> {
+        let Some(idx) = self.frame(tx_id / SLOTS, false)? else {
+            return Ok(None);
+        };
+        let frame = &mut self.frames[idx];
+        let removed = frame.slots[(tx_id % SLOTS) as usize].take();
+        frame.dirty |= removed.is_some();
+        Ok(removed)
+    }
+
+    fn memory(&self) -> usize {
+        self.frames.len() * Self::frame_size()
+    }
+
+    fn transactions(&self) -> Box<dyn Iterator<Item = Result<CachedTransaction, Error>> + '_> {
+        // Pages in memory are the current version, the file may hold an older one
+        let in_memory = self
+            .frames
+            .iter()
+            .flat_map(|frame| frame.slots.iter().flatten().cloned().map(Ok));
+        let on_disk = self
+            .table
+            .pages()
+            .filter(|(page, _)| !self.resident.contains_key(page))
+            .flat_map(|(page, offset)| {
+                let mut buf = vec![0; PAGE_LEN];
+                let slots = (&self.file)
+                    .seek(SeekFrom::Start(offset))
+                    .and_then(|_| (&self.file).read_exact(&mut buf))
+                    .map_err(Error::from)
+                    .and_then(|_| decode(page, &buf));
+                let (slots, err) = match slots {
+                    Ok(slots) => (slots.into_vec(), None),
+                    Err(err) => (Vec::new(), Some(Err(err))),
+                };
+                slots.into_iter().flatten().map(Ok).chain(err)
+            });
+        Box::new(in_memory.chain(on_disk))
+    }
+}
+
+fn encode(slots: &[Option<CachedTransaction>], buf: &mut [u8]) {
+    for (slot, out) in slots.iter().zip(buf.chunks_exact_mut(SLOT_LEN)) {
+        match slot {
+            None => out.fill(0),
+            Some((metadata, amount, state)) => {
+                out[0] = match state {
+                    TransactionState::Initial(InitialState::Deposit) => 1,
+                    TransactionState::Initial(InitialState::Withdrawal) => 2,
+                    TransactionState::Disputed(InitialState::Deposit) => 3,
+                    TransactionState::Disputed(InitialState::Withdrawal) => 4,
+                    TransactionState::Finalized => 5,
+                };
+                out[1..3].copy_from_slice(&metadata.client.to_le_bytes());
+                out[3..].copy_from_slice(&amount.units().to_le_bytes());
+            }
+        }
+    }
+}
+
+fn decode(page: u32, buf: &[u8]) -> Result<Box<[Option<CachedTransaction>]>, Error> {
+    buf.chunks_exact(SLOT_LEN)
+        .zip((0..SLOTS).map(|slot| page * SLOTS + slot))
+        .map(|(slot, tx_id)| {
+            let state = match slot[0] {
+                0 => return Ok(None),
+                1 => TransactionState::Initial(InitialState::Deposit),
+                2 => TransactionState::Initial(InitialState::Withdrawal),
+                3 => TransactionState::Disputed(InitialState::Deposit),
+                4 => TransactionState::Disputed(InitialState::Withdrawal),
+                5 => TransactionState::Finalized,
+                other => {
+                    return Err(Error::InvalidState(format!(
+                        "transaction store: tx {tx_id} has invalid state {other}"
+                    )));
+                }
+            };
+            let client = u16::from_le_bytes([slot[1], slot[2]]);
+            let mut units = [0u8; 8];
+            units.copy_from_slice(&slot[3..]);
+            Ok(Some((
+                Metadata { client, tx_id },
+                Amount::from_units(i64::from_le_bytes(units)),
+                state,
+            )))
+        })
+        .collect()
+}
+
+/// Two-level table of page offsets, leaves are allocated as pages get written.
+#[derive(Default)]
+struct PageTable {
+    leaves: Vec<Option<Box<[u64]>>>,
+}
+
+impl PageTable {
+    /// Marks a page without offset, offsets are stored + 1.
+    const NONE: u64 = 0;
+
+    fn get(&self, page: u32) -> Option<u64> {
+        let (leaf, idx) = (page as usize / LEAF, page as usize % LEAF);
+        match self.leaves.get(leaf) {
+            Some(Some(leaf)) if leaf[idx] != Self::NONE => Some(leaf[idx] - 1),
+            _ => None,
+        }
+    }
+
+    fn set(&mut self, page: u32, offset: u64) {
+        let (leaf, idx) = (page as usize / LEAF, page as usize % LEAF);
+        if self.leaves.len() <= leaf {
+            self.leaves.resize_with(leaf + 1, || None);
+        }
+        self.leaves[leaf].get_or_insert_with(|| vec![Self::NONE; LEAF].into_boxed_slice())[idx] =
+            offset + 1;
+    }
+
+    /// Every page written with its offset.
+    fn pages(&self) -> impl Iterator<Item = (u32, u64)> + '_ {
+        self.leaves
+            .iter()
+            .enumerate()
+            .filter_map(|(leaf, entries)| Some((leaf, entries.as_ref()?)))
+            .flat_map(|(leaf, entries)| {
+                entries
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, offset)| **offset != Self::NONE)
+                    .map(move |(idx, offset)| ((leaf * LEAF + idx) as u32, offset - 1))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use rust_decimal::Decimal;
+
+    use crate::{
+        objects::{
+            accounts::Account,
+            transactions::{InitialState, Metadata, Transaction, TransactionState},
+        },
+        processor::{Outcome, Processor},
+        store::{
+            AccountStore, CachedTransaction, TransactionStore,
+            disk::{DiskStore, PAGE_LEN, SLOTS},
+            tests::check_transaction_store,
+        },
+    };
+
+    #[test]
+    fn disk_store_behaves_like_a_store() {
+        let dir = tempfile::tempdir().expect("can create tempdir");
+        check_transaction_store(
+            &mut DiskStore::create(&dir.path().join("txns"), 0).expect("can create"),
+        );
+    }
+
+    /// Deposits spread over many pages, then disputes and resolves reaching back to pages
+    /// evicted long ago, in an order that keeps evicting.
+    fn input(txns: u32) -> impl Iterator<Item = Transaction> {
+        let deposits = (1..=txns).map(|tx_id| {
+            Transaction::Deposit(
+                Metadata::new((tx_id % 7) as u16, tx_id),
+                Decimal::from(tx_id),
+            )
+        });
+        let disputes = (1..=txns).step_by(97).flat_map(|tx_id| {
+            let client = (tx_id % 7) as u16;
+            [
+                Some(Transaction::Dispute(Metadata::new(client, tx_id))),
+                // Taken tx id
+                Some(Transaction::Deposit(
+                    Metadata::new(client, tx_id),
+                    Decimal::ONE,
+                )),
+                (tx_id % 2 == 0).then(|| Transaction::Resolve(Metadata::new(client, tx_id))),
+            ]
+            .into_iter()
+            .flatten()
+        });
+        deposits
+            .chain(disputes)
+            .chain([Transaction::Dispute(Metadata::new(1, txns + 1))])
+    }
+
+    fn sorted(store: &impl TransactionStore) -> Vec<CachedTransaction> {
+        let mut all: Vec<_> = store
+            .transactions()
+            .collect::<Result<_, _>>()
+            .expect("can read back");
+        all.sort_by_key(|(metadata, _, _)| metadata.tx_id);
+        all
+    }
+
+    #[test]
+    fn pages_spill_to_disk_past_the_memory_budget() {
+        let dir = tempfile::tempdir().expect("can create tempdir");
+        let path = dir.path().join("txns");
+        let budget = 4 * DiskStore::frame_size();
+        let store = DiskStore::create(&path, budget).expect("can create");
+        let mut p = Processor::with_stores(HashMap::new(), store);
+        let mut reference = Processor::new();
+        let txns = 50 * SLOTS;
+        for (txn, expected) in input(txns).zip(input(txns)) {
+            assert_eq!(
+                reference.process_one(expected).expect("can process"),
+                p.process_one(txn).expect("can process")
+            );
+        }
+
+        let (in_memory, on_disk) = p.txn_store().pages();
+        assert_eq!(4, in_memory);
+        assert!(on_disk >= 46, "{on_disk} pages written");
+        assert_eq!(
+            (on_disk * PAGE_LEN) as u64,
+            std::fs::metadata(&path).expect("exists").len()
+        );
+        let accounts = |store: &HashMap<u16, Account>| {
+            let mut accounts: Vec<_> = store.accounts().cloned().collect();
+            accounts.sort_by_key(|acc| acc.id);
+            accounts
+        };
+        assert_eq!(
+            accounts(reference.get_account_store_ref()),
+            accounts(p.get_account_store_ref())
+        );
+        assert_eq!(txns as usize, sorted(p.txn_store()).len());
+        assert_eq!(sorted(reference.txn_store()), sorted(p.txn_store()));
+    }
+
+    #[test]
+    fn far_apart_tx_ids() {
+        let dir = tempfile::tempdir().expect("can create tempdir");
+        let store = DiskStore::create(&dir.path().join("txns"), 0).expect("can create");
+        let mut p = Processor::with_stores(HashMap::new(), store);
+        for tx_id in [0, u32::MAX, u32::MAX / 2, 1] {
+            assert_eq!(
+                Outcome::Applied,
+                p.process_one(Transaction::Deposit(Metadata::new(1, tx_id), Decimal::ONE))
+                    .expect("can process")
+            );
+        }
+        assert_eq!(
+            Outcome::Applied,
+            p.process_one(Transaction::Dispute(Metadata::new(1, u32::MAX)))
+                .expect("can process")
+        );
+        let cached = sorted(p.txn_store());
+        assert_eq!(
+            vec![0, 1, u32::MAX / 2, u32::MAX],
+            cached.iter().map(|(m, _, _)| m.tx_id).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            TransactionState::Disputed(InitialState::Deposit),
+            cached[3].2
+        );
+        // Tx ids 0 and 1 share a page, written once, and rewritten in place
+        assert_eq!((1, 3), p.txn_store().pages());
+    }
+}
@@ -0,0 +1,643 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use rusqlite::{Connection, OptionalExtension};
+use rust_decimal::Decimal;
+
+use crate::{
+    amount::Amount,
+    error::Error,
+    objects::{accounts::Account, transactions::Metadata},
+    policy::WithdrawalDisputes,
+    state::{state_name, state_of},
+    store::{AccountStore, CachedTransaction, TransactionStore},
+};
+
+// NOTE(juf): The books in a SQLite file, for keeping them beyond the run and querying them with
+// whatever speaks SQL. Amounts are TEXT, SQLite has no exact decimals and REAL would round, cast
+// them for quick looks only. The processor works on memory as usual, accounts are loaded when
+// opening, transactions as they are referenced, and changes are written back in one SQLite
+// transaction per batch of input rows (`Processor::commit`). A crash loses the batch in flight,
+// never half of it.
+
+/// Schema migrations, the n-th brings the schema to version n + 1. Applied versions are kept in
+/// `PRAGMA user_version`, never change a migration once released, append a new one instead.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE accounts (
+        client INTEGER PRIMARY KEY,
+        available TEXT NOT NULL,
+        held TEXT NOT NULL,
+        total TEXT NOT NULL,
+        locked INTEGER NOT NULL
+    );
+    CREATE TABLE transactions (
+        tx INTEGER PRIMARY KEY,
+        client INTEGER NOT NULL,
+        amount TEXT NOT NULL,
+        state TEXT NOT NULL
+    );
+    CREATE INDEX transactions_by_client ON transactions (client);
+    CREATE TABLE settings (
+        key TEXT PRIMARY KEY,
+        value INTEGER NOT NULL
+    );",
+    "CREATE TABLE external_ids (
+        external_id TEXT PRIMARY KEY,
+        tx INTEGER NOT NULL
+    );",
+    // Books committed before were kept with the only withdrawal dispute policy there was
+    "CREATE TABLE policies (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+    INSERT INTO policies (key, value)
+        SELECT 'withdrawal_disputes', 'hold' WHERE EXISTS (SELECT 1 FROM settings);",
+];
+
+/// Books as found in the file when opening it.
+pub(crate) struct Books {
+    pub(crate) accounts: SqliteAccounts,
+    pub(crate) transactions: SqliteTransactions,
+    pub(crate) next_tx_id: u32,
+    pub(crate) external_ids: HashMap<String, u32>,
+    /// `None` for new books, see [`SqliteTransactions::record_withdrawal_disputes`].
+    pub(crate) withdrawal_disputes: Option<WithdrawalDisputes>,
+}
+
+/// Opens the books at `path`, a new file is set up, an older one migrated.
+pub(crate) fn open(path: &Path) -> Result<Books, Error> {
+    let mut conn = Connection::open(path)?;
+    // NOTE(juf): WAL lets other tools read the books while a run writes to them, FULL syncs
+    // every commit so a committed batch survives a power cut too.
+    conn.pragma_update(None, "journal_mode", "WAL")?;
+    conn.pragma_update(None, "synchronous", "FULL")?;
+    migrate(&mut conn)?;
+
+    let mut accounts = HashMap::new();
+    let mut stmt = conn.prepare("SELECT client, available, held, locked FROM accounts")?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let mut acc = Account::new(row.get(0)?);
+        acc.available = decimal(&row.get::<_, String>(1)?, "account", acc.id.into())?;
+        acc.held = decimal(&row.get::<_, String>(2)?, "account", acc.id.into())?;
+        acc.locked = row.get(3)?;
+        acc.checked_total()
+            .map_err(|err| Error::InvalidState(format!("account {}: {err}", acc.id)))?;
+        accounts.insert(acc.id, acc);
+    }
+    drop(rows);
+    drop(stmt);
+    let next_tx_id = conn
+        .query_row(
+            "SELECT value FROM settings WHERE key = 'next_tx_id'",
+            [],
+            |row| row.get(0),
+        )
+        .optional()?
+        .unwrap_or(1);
+    let external_ids = conn
+        .prepare("SELECT external_id, tx FROM external_ids")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_, _>>()?;
+    let withdrawal_disputes = conn
+        .query_row(
+            "SELECT value FROM policies WHERE key = 'withdrawal_disputes'",
+            [],
+            |row| row.get::<_, String>(0),
+        )
+        .optional()?
+        .map(|policy| {
+            policy.parse().map_err(|_| {
+                Error::InvalidState(format!("unknown withdrawal dispute policy {policy:?}"))
+            })
+        })
+        .transpose()?;
+    Ok(Books {
+        accounts: SqliteAccounts {
+            accounts,
+            changed: HashSet::new(),
+        },
+        transactions: SqliteTransactions {
+            conn,
+            pending: HashMap::new(),
+            committed_next_tx_id: next_tx_id,
+        },
+        next_tx_id,
+        external_ids,
+        withdrawal_disputes,
+    })
+}
+
+fn migrate(conn: &mut Connection) -> Result<(), Error> {
+    let version: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if version > MIGRATIONS.len() {
+        return Err(Error::InvalidState(format!(
+            "books are at schema version {version}, this build knows up to {}",
+            MIGRATIONS.len()
+        )));
+    }
+    for (applied, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", applied + 1)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+/// Accounts of the books, all of them in memory.
+pub(crate) struct SqliteAccounts {
+    accounts: HashMap<u16, Account>,
+    changed: HashSet<u16>,
+}
+
+impl AccountStore for SqliteAccounts {
+    fn get(&self, client: u16) -> Option<&Account> {
+        self.accounts.get(&client)
+    }
+
+    fn get_or_create(&mut self, client: u16) -> &mut Account {
+        // Handing out `&mut` means the caller may change it, assume it does
+        self.changed.insert(client);
+        self.accounts
+            .entry(client)
+            .or_insert_with(|| Account::new(client))
+    }
+
+    fn insert(&mut self, account: Account) -> Option<Account> {
+        self.changed.insert(account.id);
+        self.accounts.insert(account.id, account)
+    }
+
+    fn accounts(&self) -> Box<dyn Iterator<Item = &Account> + '_> {
+        Box::new(self.accounts.values())
+    }
+
+    fn take_changed(&mut self) -> Vec<Account> {
+        let mut changed: Vec<_> = self
+            .changed
+            .drain()
+            .filter_map(|client| self.accounts.get(&client).cloned())
+            .collect();
+        changed.sort_by_key(|acc| acc.id);
+        changed
+    }
+}
+
+/// Transactions of the books, the ones referenced since the last commit are held in memory.
+pub(crate) struct SqliteTransactions {
+    conn: Connection,
+    /// Newer than what the file holds, if it holds them at all.
+    pending: HashMap<u32, CachedTransaction>,
+    /// External ids of tx ids from here on are not in the file yet.
+    committed_next_tx_id: u32,
+}
+
+impl SqliteTransactions {
+    /// Sets up new books to be kept with the withdrawal dispute `policy`, right away, it is not
+    /// up to the batches.
+    pub(crate) fn record_withdrawal_disputes(
+        &mut self,
+        policy: WithdrawalDisputes,
+    ) -> Result<(), Error> {
+        self.conn.execute(
+            "INSERT INTO policies (key, value) VALUES ('withdrawal_disputes', ?1)",
+            [policy.to_string()],
+        )?;
+        Ok(())
+    }
+
+    fn load(&self, tx_id: u32) -> Result<Option<CachedTransaction>, Error> {
+        let row = self
+            .conn
+            .prepare_cached("SELECT client, amount, state FROM transactions WHERE tx = ?1")?
+            .query_row([tx_id], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                ))
+            })
+            .optional()?;
+        row.map(|(client, amount, state)| transaction(tx_id, client, &amount, &state))
+            .transpose()
+    }
+
+    fn committed(&self) -> Result<Vec<CachedTransaction>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT tx, client, amount, state FROM transactions")?;
+        let mut rows = stmt.query([])?;
+        let mut committed = Vec::new();
+        while let Some(row) = rows.next()? {
+            let tx_id = row.get(0)?;
+            if !self.pending.contains_key(&tx_id) {
+                committed.push(transaction(
+                    tx_id,
+                    row.get(1)?,
+                    &row.get::<_, String>(2)?,
+                    &row.get::<_, String>(3)?,
+                )?);
+            }
+        }
+        Ok(committed)
+    }
+}
+
+impl TransactionStore for SqliteTransactions {
+    fn get_mut(&mut self, tx_id: u32) -> Result<Option<&mut CachedTransaction>, Error> {
+        if !self.pending.contains_key(&tx_id) {
+            match self.load(tx_id)? {
+                Some(txn) => self.pending.insert(tx_id, txn),
+                None => return Ok(None),
+            };
+        }
+        Ok(self.pending.get_mut(&tx_id))
+    }
+
+    fn contains(&mut self, tx_id: u32) -> Result<bool, Error> {
+        Ok(self.pending.contains_key(&tx_id) || self.load(tx_id)?.is_some())
+    }
+
+    fn insert_new(&mut self, tx_id: u32, txn: CachedTransaction) -> Result<bool, Error> {
+        if self.contains(tx_id)? {
+            return Ok(false);
+        }
+        self.pending.insert(tx_id, txn);
+        Ok(true)
+    }
+
+    fn transactions(&self) -> Box<dyn Iterator<Item = Result<CachedTransaction, Error>> + '_> {
+        // NOTE(juf): Collected upfront, the rows borrow the statement which borrows the
+        // connection, neither can be returned along with them.
+        match self.committed() {
+            Ok(committed) => Box::new(
+                committed
+                    .into_iter()
+                    .chain(self.pending.values().cloned())
+                    .map(Ok),
+            ),
+            Err(err) => Box::new(std::iter::once(Err(err))),
+        }
+    }
+
+    fn memory(&self) -> usize {
+        self.pending.capacity() * (size_of::<(u32, CachedTransaction)>() + 1)
+    }
+
+    fn commit(
+        &mut self,
+        accounts: Vec<Account>,
+        next_tx_id: u32,
+        external_ids: &HashMap<String, u32>,
+    ) -> Result<(), Error> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut upsert = tx.prepare_cached(
+                "INSERT OR REPLACE INTO accounts (client, available, held, total, locked)
+                VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
+            for acc in accounts {
+                upsert.execute((
+                    acc.id,
+                    acc.available.to_string(),
+                    acc.held.to_string(),
+                    acc.total().to_string(),
+                    acc.locked,
+                ))?;
+            }
+            let mut upsert = tx.prepare_cached(
+                "INSERT OR REPLACE INTO transactions (tx, client, amount, state)
+                VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            for (metadata, amount, state) in self.pending.values() {
+                upsert.execute((
+                    metadata.tx_id,
+                    metadata.client,
+                    Decimal::from(*amount).to_string(),
+                    state_name(*state),
+                ))?;
+            }
+            tx.execute(
+                "INSERT OR REPLACE INTO settings (key, value) VALUES ('next_tx_id', ?1)",
+                [next_tx_id],
+            )?;
+            // External ids are only handed out along with fresh tx ids
+            let mut insert = tx.prepare_cached(
+                "INSERT OR IGNORE INTO external_ids (external_id, tx) VALUES (?1, ?2)",
+            )?;
+            for (external_id, tx_id) in external_ids {
+                if *tx_id >= self.committed_next_tx_id {
+                    insert.execute((external_id, tx_id))?;
+                }
+            }
+        }
+        tx.commit()?;
+        self.pending.clear();
+        self.committed_next_tx_id = next_tx_id;
+        Ok(())
+    }
+}
+
+fn transaction(
+    tx_id: u32,
+    client: u16,
+    amount: &str,
+    state: &str,
+) -> Result<CachedTransaction, Error> {
+    let state = state_of(state)
+        .ok_or_else(|| Error::InvalidState(format!("tx {tx_id}: invalid state {state:?}")))?;
+    Ok((
+        Metadata { client, tx_id },
+        Amount::try_from(decimal(amount, "tx", tx_id)?)
+            .map_err(|err| Error::InvalidState(format!("tx {tx_id}: {err}")))?,
+        state,
+    ))
+}
+
+fn decimal(value: &str, kind: &str, id: u32) -> Result<Decimal, Error> {
+    value
+        .parse()
+        .map_err(|_| Error::InvalidState(format!("{kind} {id}: invalid amount {value:?}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::dec;
+
+    use crate::{
+        date::Date,
+        error::Error,
+        ingest::feed_statement_processor,
+        objects::{
+            accounts::Account,
+            statements::{Balance, Balances, StatementLine},
+            transactions::{Metadata, Transaction},
+        },
+        policy::WithdrawalDisputes,
+        processor::{Outcome, Processor},
+        store::{
+            AccountStore,
+            sqlite::{MIGRATIONS, open},
+            tests::check_transaction_store,
+        },
+    };
+
+    #[test]
+    fn sqlite_store_behaves_like_a_store() {
+        let dir = tempfile::tempdir().expect("can create tempdir");
+        let mut books = open(&dir.path().join("books.sqlite")).expect("can open");
+        check_transaction_store(&mut books.transactions);
+    }
+
+    #[test]
+    fn migrations_are_applied_once() {
+        let dir = tempfile::tempdir().expect("can create tempdir");
+        let path = dir.path().join("books.sqlite");
+        drop(open(&path).expect("can set up"));
+        drop(open(&path).expect("can open again"));
+        let conn = rusqlite::Connection::open(&path).expect("can open");
+        let version: usize = conn
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .expect("has version");
+        assert_eq!(MIGRATIONS.len(), version);
+
+        // Books written by a newer build are left alone
+        conn.pragma_update(None, "user_version", MIGRATIONS.len() + 1)
+            .expect("can set version");
+        drop(conn);
+        assert!(matches!(open(&path), Err(Error::InvalidState(_))));
+    }
+
+    #[test]
+    fn only_committed_batches_survive() {
+        let dir = tempfile::tempdir().expect("can create tempdir");
+        let path = dir.path().join("books.sqlite");
+        let mut p = Processor::open_sqlite(&path, None).expect("can open");
+        let deposit =
+            |client, tx_id, amount| Transaction::Deposit(Metadata::new(client, tx_id), amount);
+        for txn in [
+            deposit(1, 1, dec!(10.5)),
+            deposit(2, 2, dec!(3)),
+            Transaction::Dispute(Metadata::new(1, 1)),
+        ] {
+            assert_eq!(Outcome::Applied, p.process_one(txn).expect("can process"));
+        }
+        let line = StatementLine {
+            date: Date::new(2025, 3, 3).expect("valid date"),
+            amount: dec!(1.25),
+            payee: None,
+            memo: None,
+            external_id: Some("FITID-1".into()),
+            value_date: None,
+            counterparty_account: None,
+            reference: None,
+            cleared: None,
+            splits: Vec::new(),
+        };
+        assert_eq!(
+            Outcome::Applied,
+            p.process_statement_line(2, line.clone())
+                .expect("can process")
+        );
+        p.commit().expect("can commit");
+        // Crash before the next commit
+        assert_eq!(
+            Outcome::Applied,
+            p.process_one(deposit(2, 9, dec!(100)))
+                .expect("can process")
+        );
+        assert_eq!(
+            Outcome::Applied,
+            p.process_one(Transaction::Resolve(Metadata::new(1, 1)))
+                .expect("can process")
+        );
+        drop(p);
+
+        let mut p = Processor::open_sqlite(&path, None).expect("can open again");
+        let mut accounts: Vec<_> = p.get_account_store_ref().accounts().cloned().collect();
+        accounts.sort_by_key(|acc| acc.id);
+        assert_eq!(
+            vec![
+                Account {
+                    id: 1,
+                    locked: false,
+                    available: dec!(0),
+                    held: dec!(10.5)
+                },
+                Account {
+                    id: 2,
+                    locked: false,
+                    available: dec!(4.25),
+                    held: dec!(0)
+                }
+            ],
+            accounts
+        );
+        // Dispute state, external ids and tx ids carry over
+        assert_eq!(
+            Outcome::Applied,
+            p.process_one(Transaction::Chargeback(Metadata::new(1, 1)))
+                .expect("can process")
+        );
+        assert!(matches!(
+            p.process_statement_line(2, line).expect("can process"),
+            Outcome::Rejected(_)
+        ));
+        assert!(matches!(
+            p.process_one(deposit(2, 2, dec!(1))).expect("can process"),
+            Outcome::Rejected(_)
+        ));
+        assert_eq!(
+            Outcome::Applied,
+            p.process_one(deposit(2, 9, dec!(100)))
+                .expect("can process")
+        );
+        p.commit().expect("can commit");
+        drop(p);
+
+        // Readable with plain SQL
+        let conn = rusqlite::Connection::open(&path).expect("can open");
+        let rows: Vec<(u16, String, String, bool)> = conn
+            .prepare("SELECT client, available, total, locked FROM accounts ORDER BY client")
+            .expect("can prepare")
+            .query_map([], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .expect("can query")
+            .collect::<Result<_, _>>()
+            .expect("can read");
+        assert_eq!(
+            vec![
+                (1, "0.0".into(), "0.0".into(), true),
+                (2, "104.25".into(), "104.25".into(), false)
+            ],
+            rows
+        );
+        let states: Vec<(u32, String)> = conn
+            .prepare("SELECT tx, state FROM transactions ORDER BY tx")
+            .expect("can prepare")
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .expect("can query")
+            .collect::<Result<_, _>>()
+            .expect("can read");
+        assert_eq!(
+            vec![
+                (1, "finalized".into()),
+                (2, "deposit".into()),
+                (3, "deposit".into()),
+                (9, "deposit".into())
+            ],
+            states
+        );
+    }
+
+    #[test]
+    fn statements_that_do_not_reconcile_are_not_committed() {
+        let dir = tempfile::tempdir().expect("can create tempdir");
+        let path = dir.path().join("books.sqlite");
+        let date = Date::new(2025, 3, 3).expect("valid date");
+        let line = |external_id: &str, amount| StatementLine {
+            date,
+            amount,
+            payee: None,
+            memo: None,
+            external_id: Some(external_id.into()),
+            value_date: None,
+            counterparty_account: None,
+            reference: None,
+            cleared: None,
+            splits: Vec::new(),
+        };
+        let balances = |opening, closing| Balances {
+            statement: "S-1".into(),
+            opening: Some(Balance {
+                date,
+                amount: opening,
+            }),
+            closing: Some(Balance {
+                date,
+                amount: closing,
+            }),
+        };
+        let mut p = Processor::open_sqlite(&path, None).expect("can open");
+        feed_statement_processor(
+            [Ok((1, line("A", dec!(5))))].into_iter(),
+            Some(&balances(dec!(10), dec!(15))),
+            1,
+            &mut p,
+            |rejection| panic!("rejected {rejection}"),
+        )
+        .expect("balances reconcile");
+        let err = feed_statement_processor(
+            [Ok((1, line("B", dec!(2))))].into_iter(),
+            Some(&balances(dec!(15), dec!(20))),
+            1,
+            &mut p,
+            |rejection| panic!("rejected {rejection}"),
+        )
+        .expect_err("closing balance does not match");
+        assert!(matches!(
+            err,
+            Error::BalanceMismatch {
+                kind: "closing",
+                ..
+            }
+        ));
+        drop(p);
+
+        let mut p = Processor::open_sqlite(&path, None).expect("can open again");
+        assert_eq!(
+            Some(dec!(15)),
+            p.get_account_store_ref().get(1).map(|acc| acc.total())
+        );
+        // The booking of the refused statement is not known either
+        assert_eq!(
+            Outcome::Applied,
+            p.process_statement_line(1, line("B", dec!(2)))
+                .expect("can process")
+        );
+    }
+
+    #[test]
+    fn books_keep_their_withdrawal_dispute_policy() {
+        let dir = tempfile::tempdir().expect("can create tempdir");
+        let path = dir.path().join("books.sqlite");
+        let p = Processor::open_sqlite(&path, Some(WithdrawalDisputes::Refuse)).expect("can open");
+        drop(p);
+        let p = Processor::open_sqlite(&path, None).expect("can open again");
+        assert_eq!(WithdrawalDisputes::Refuse, p.withdrawal_disputes());
+        drop(p);
+        assert!(matches!(
+            Processor::open_sqlite(&path, Some(WithdrawalDisputes::Hold)),
+            Err(Error::InvalidState(_))
+        ));
+
+        // Books committed before the policy was recorded were kept with `hold`
+        let path = dir.path().join("old.sqlite");
+        let mut conn = rusqlite::Connection::open(&path).expect("can open");
+        let tx = conn.transaction().expect("can begin");
+        tx.execute_batch(&MIGRATIONS[..2].join(";"))
+            .expect("can set up the old schema");
+        tx.execute(
+            "INSERT INTO settings (key, value) VALUES ('next_tx_id', 3)",
+            [],
+        )
+        .expect("can insert");
+        tx.pragma_update(None, "user_version", 2)
+            .expect("can set version");
+        tx.commit().expect("can commit");
+        drop(conn);
+        let p = Processor::open_sqlite(&path, None).expect("can migrate");
+        assert_eq!(WithdrawalDisputes::Hold, p.withdrawal_disputes());
+        drop(p);
+        assert_eq!(
+            WithdrawalDisputes::Hold,
+            Processor::open_sqlite(&dir.path().join("new.sqlite"), None)
+                .expect("can open")
+                .withdrawal_disputes()
+        );
+    }
+}
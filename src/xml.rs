@@ -0,0 +1,270 @@
+use crate::error::Error;
+
+// NOTE(juf): Minimal XML reader for statement formats (camt), it does not validate against a
+// schema, does not resolve namespaces (prefixes are dropped) nor DTDs. That is plenty for
+// machine generated bank files and saves us a dependency.
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct Element {
+    pub(crate) name: String,
+    /// Line the start tag is found at.
+    pub(crate) line: u64,
+    pub(crate) attributes: Vec<(String, String)>,
+    pub(crate) children: Vec<Element>,
+    pub(crate) text: String,
+}
+
+impl Element {
+    /// First child with the given local name.
+    pub(crate) fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    pub(crate) fn children_named<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> impl Iterator<Item = &'a Element> + 'a {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+
+    /// Follows the path of local names, e.g., `["Refs", "EndToEndId"]`.
+    pub(crate) fn find(&self, path: &[&str]) -> Option<&Element> {
+        path.iter()
+            .try_fold(self, |element, name| element.child(name))
+    }
+
+    /// Trimmed text of the element at `path`, `None` if missing or empty.
+    pub(crate) fn text_at(&self, path: &[&str]) -> Option<&str> {
+        self.find(path)
+            .map(|element| element.text.trim())
+            .filter(|text| !text.is_empty())
+    }
+
+    // used by test
+    #[allow(dead_code)]
+    pub(crate) fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Parses the document and returns its root element.
+pub(crate) fn parse(input: &str) -> Result<Element, Error> {
+    let mut stack: Vec<Element> = Vec::new();
+    let mut root = None;
+    let mut rest = input;
+    let mut line = 1;
+    let mut counted = 0;
+    while !rest.is_empty() {
+        let Some(open) = rest.find('<') else {
+            append_text(&mut stack, rest)?;
+            break;
+        };
+        append_text(&mut stack, &rest[..open])?;
+        rest = &rest[open..];
+        if let Some(after) = rest.strip_prefix("<!--") {
+            rest = skip_past(after, "-->")?;
+        } else if let Some(after) = rest.strip_prefix("<![CDATA[") {
+            let end = after
+                .find("]]>")
+                .ok_or_else(|| invalid("unterminated CDATA"))?;
+            if let Some(current) = stack.last_mut() {
+                current.text.push_str(&after[..end]);
+            }
+            rest = &after[end + 3..];
+        } else if rest.starts_with("<?") || rest.starts_with("<!") {
+            rest = skip_past(rest, ">")?;
+        } else if let Some(after) = rest.strip_prefix("</") {
+            let end = after.find('>').ok_or_else(|| invalid("unterminated tag"))?;
+            let name = local_name(after[..end].trim());
+            let element = stack
+                .pop()
+                .ok_or_else(|| invalid(&format!("unexpected </{name}>")))?;
+            if element.name != name {
+                return Err(invalid(&format!(
+                    "expected </{}>, found </{name}>",
+                    element.name
+                )));
+            }
+            close(&mut stack, &mut root, element)?;
+            rest = &after[end + 1..];
+        } else {
+            let offset = input.len() - rest.len();
+            line += input[counted..offset]
+                .bytes()
+                .filter(|b| *b == b'\n')
+                .count() as u64;
+            counted = offset;
+            let end = tag_end(rest).ok_or_else(|| invalid("unterminated tag"))?;
+            let tag = &rest[1..end];
+            let (tag, self_closing) = match tag.strip_suffix('/') {
+                Some(tag) => (tag, true),
+                None => (tag, false),
+            };
+            let mut element = open_element(tag)?;
+            element.line = line;
+            if self_closing {
+                close(&mut stack, &mut root, element)?;
+            } else {
+                stack.push(element);
+            }
+            rest = &rest[end + 1..];
+        }
+    }
+    if let Some(element) = stack.pop() {
+        return Err(invalid(&format!("<{}> is never closed", element.name)));
+    }
+    root.ok_or_else(|| invalid("document has no root element"))
+}
+
+fn close(stack: &mut [Element], root: &mut Option<Element>, element: Element) -> Result<(), Error> {
+    match stack.last_mut() {
+        Some(parent) => parent.children.push(element),
+        None if root.is_none() => *root = Some(element),
+        None => return Err(invalid("document has more than one root element")),
+    }
+    Ok(())
+}
+
+fn append_text(stack: &mut [Element], text: &str) -> Result<(), Error> {
+    match stack.last_mut() {
+        Some(current) => current.text.push_str(&unescape(text)),
+        None if text.trim().is_empty() => {}
+        None => return Err(invalid("text outside of the root element")),
+    }
+    Ok(())
+}
+
+fn open_element(tag: &str) -> Result<Element, Error> {
+    let tag = tag.trim();
+    let name_end = tag.find(char::is_whitespace).unwrap_or(tag.len());
+    let mut element = Element {
+        name: local_name(&tag[..name_end]).to_string(),
+        ..Default::default()
+    };
+    let mut rest = tag[name_end..].trim_start();
+    while !rest.is_empty() {
+        let eq = rest
+            .find('=')
+            .ok_or_else(|| invalid(&format!("malformed attribute in <{tag}>")))?;
+        let key = rest[..eq].trim();
+        let value = rest[eq + 1..].trim_start();
+        let quote = value
+            .chars()
+            .next()
+            .filter(|c| *c == '"' || *c == '\'')
+            .ok_or_else(|| invalid(&format!("unquoted attribute in <{tag}>")))?;
+        let end = value[1..]
+            .find(quote)
+            .ok_or_else(|| invalid(&format!("unterminated attribute in <{tag}>")))?;
+        element
+            .attributes
+            .push((local_name(key).to_string(), unescape(&value[1..end + 1])));
+        rest = value[end + 2..].trim_start();
+    }
+    Ok(element)
+}
+
+/// Position of the `>` closing the tag, skipping over quoted attribute values.
+fn tag_end(rest: &str) -> Option<usize> {
+    let mut quote = None;
+    for (idx, c) in rest.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if q == c => quote = None,
+            (None, '>') => return Some(idx),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn skip_past<'a>(rest: &'a str, marker: &str) -> Result<&'a str, Error> {
+    let end = rest
+        .find(marker)
+        .ok_or_else(|| invalid(&format!("missing {marker}")))?;
+    Ok(&rest[end + marker.len()..])
+}
+
+fn local_name(name: &str) -> &str {
+    name.rsplit_once(':').map_or(name, |(_, local)| local)
+}
+
+fn unescape(value: &str) -> String {
+    if !value.contains('&') {
+        return value.to_string();
+    }
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        let after = &rest[amp..];
+        let Some(semicolon) = after.find(';') else {
+            out.push_str(after);
+            return out;
+        };
+        let entity = &after[1..semicolon];
+        let decoded = match entity {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .or_else(|| entity.strip_prefix('#').and_then(|dec| dec.parse().ok()))
+                .and_then(char::from_u32),
+        };
+        match decoded {
+            Some(c) => out.push(c),
+            None => out.push_str(&after[..=semicolon]),
+        }
+        rest = &after[semicolon + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::InvalidRow(format!("xml: {reason}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::xml::parse;
+
+    #[test]
+    fn parse_elements_attributes_and_text() {
+        let doc = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!-- comment -->
+<Document xmlns="urn:iso:std:iso:20022:tech:xsd:camt.053.001.02">
+  <ns:Amt Ccy="EUR">12.50</ns:Amt>
+  <Nm>Tom &amp; Jerry &#228;&#x00FC;</Nm>
+  <Empty/>
+  <Data><![CDATA[<raw>]]></Data>
+</Document>"#;
+        let root = parse(doc).expect("valid document");
+        assert_eq!("Document", root.name);
+        assert_eq!(3, root.line);
+        let amount = root.child("Amt").expect("has amount");
+        assert_eq!("12.50", amount.text);
+        assert_eq!(4, amount.line);
+        assert_eq!(Some("EUR"), amount.attribute("Ccy"));
+        assert_eq!(Some("Tom & Jerry äü"), root.text_at(&["Nm"]));
+        assert!(root.child("Empty").is_some());
+        assert_eq!(None, root.text_at(&["Empty"]));
+        assert_eq!(Some("<raw>"), root.text_at(&["Data"]));
+    }
+
+    #[test]
+    fn parse_rejects_malformed_documents() {
+        assert!(parse("<a><b></a>").is_err());
+        assert!(parse("<a>").is_err());
+        assert!(parse("<a></a><b></b>").is_err());
+        assert!(parse("<a x=1></a>").is_err());
+        assert!(parse("").is_err());
+    }
+}
@@ -0,0 +1,11 @@
+# German bank export, semicolon separated with decimal comma
+name = de-girokonto
+delimiter = ;
+skip_lines = 4
+date_column = Buchungsdatum
+date_format = %d.%m.%y
+amount_column = Betrag (€)
+decimal_comma = true
+payee_column = Zahlungsempfänger*in
+memo_column = Verwendungszweck
+account = Assets:Bank:Girokonto
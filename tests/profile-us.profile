@@ -0,0 +1,8 @@
+# US bank export with separate debit and credit columns
+name = us-checking
+date_column = Date
+date_format = %m/%d/%Y
+debit_column = Debit
+credit_column = Credit
+payee_column = Description
+client = 7